@@ -0,0 +1,27 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::sync::OnceLock;
+use syslog_parser::{ParseMode, SyslogParser};
+
+static PARSER: OnceLock<SyslogParser> = OnceLock::new();
+
+fuzz_target!(|data: &[u8]| {
+    let parser = PARSER.get_or_init(|| SyslogParser::new().unwrap());
+
+    // Strict mode may reject the input, but must not panic
+    let _ = parser.parse_bytes(data, "127.0.0.1", ParseMode::Strict);
+
+    // Lenient mode accepts everything, keeping the original bytes of anything it flags
+    let message = parser
+        .parse_bytes(data, "127.0.0.1", ParseMode::Lenient)
+        .expect("lenient parsing never fails");
+    assert!(message.facility <= 23 && message.severity <= 7);
+    if message.parse_error.is_some() {
+        assert_eq!(message.original_bytes.as_deref(), Some(data));
+    }
+
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = parser.parse(text, "127.0.0.1", ParseMode::Strict);
+    }
+});
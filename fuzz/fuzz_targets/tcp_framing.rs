@@ -0,0 +1,45 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use syslog_parser::{truncate_with_marker, FrameReader, OversizeFilter, OversizePolicy, TRUNCATION_MARKER};
+use tokio::io::BufReader;
+
+// The first byte picks the frame size limit, the rest is the TCP byte stream
+fuzz_target!(|data: &[u8]| {
+    let Some((&limit, stream)) = data.split_first() else {
+        return;
+    };
+    let max_size = usize::from(limit) + 1;
+    // Without digits there are no octet counts, so every frame is a line
+    let lines_only = !stream.iter().any(u8::is_ascii_digit);
+
+    for policy in [OversizePolicy::Drop, OversizePolicy::Truncate, OversizePolicy::Split] {
+        // A tiny read buffer makes frames straddle refills
+        let mut reader = FrameReader::new(BufReader::with_capacity(7, stream), max_size);
+        let mut filter = OversizeFilter::new(policy, max_size);
+        let mut frame = Vec::new();
+        let mut frame_bytes = 0;
+
+        futures::executor::block_on(async {
+            while let Some(complete) = reader.read_frame(&mut frame).await.unwrap() {
+                assert!(frame.len() <= max_size);
+                assert!(complete || frame.len() == max_size);
+                assert!(!lines_only || !frame.contains(&b'\n'));
+                frame_bytes += frame.len();
+
+                let truncated = truncate_with_marker(&frame, max_size);
+                assert!(truncated.len() <= max_size);
+                assert!(max_size < TRUNCATION_MARKER.len() || truncated.ends_with(TRUNCATION_MARKER));
+
+                let (message, _) = filter.apply(std::mem::take(&mut frame), complete);
+                assert!(message.is_none_or(|message| message.len() <= max_size));
+            }
+        });
+
+        // Every byte other than the line terminators ends up in exactly one frame
+        if lines_only {
+            let newlines = stream.iter().filter(|&&b| b == b'\n').count();
+            assert_eq!(frame_bytes + newlines, stream.len());
+        }
+    }
+});
@@ -0,0 +1,374 @@
+use crate::{
+    signing::SignatureStatus,
+    store::{LogEntry, LogFilter, LogStore},
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration as ChronoDuration, DurationRound, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use syslog_parser::SyslogMessage;
+use tokio::sync::Mutex;
+use tracing::info;
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// zstd level for archive files; they are written once and read rarely
+const COMPRESSION_LEVEL: i32 = 9;
+
+/// Messages inserted per transaction while rehydrating
+const REHYDRATE_BATCH: usize = 1000;
+
+/// Time span covered by each archive file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ArchivePeriod {
+    Hour,
+    Day,
+}
+
+impl ArchivePeriod {
+    fn length(self) -> ChronoDuration {
+        match self {
+            ArchivePeriod::Hour => ChronoDuration::hours(1),
+            ArchivePeriod::Day => ChronoDuration::days(1),
+        }
+    }
+
+    fn start(self, timestamp: DateTime<Utc>) -> DateTime<Utc> {
+        timestamp.duration_trunc(self.length()).unwrap_or(timestamp)
+    }
+
+    fn file_name(self, start: DateTime<Utc>) -> String {
+        match self {
+            ArchivePeriod::Hour => format!("{}.ndjson.zst", start.format("%Y-%m-%dT%H")),
+            ArchivePeriod::Day => format!("{}.ndjson.zst", start.format("%Y-%m-%d")),
+        }
+    }
+}
+
+/// One compressed NDJSON file, covering `[period_start, period_end)`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveFile {
+    pub file: String,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub messages: u64,
+    pub first_timestamp: DateTime<Utc>,
+    pub last_timestamp: DateTime<Utc>,
+    pub compressed_bytes: u64,
+}
+
+/// A line of an archive file. Lines written before the seq, signature status and
+/// trash state were archived hold only the message.
+#[derive(Debug, Serialize, Deserialize)]
+struct ArchivedEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seq: Option<i64>,
+    #[serde(flatten)]
+    message: SyslogMessage,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature_status: Option<SignatureStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature_detail: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<DateTime<Utc>>,
+}
+
+impl From<LogEntry> for ArchivedEntry {
+    fn from(entry: LogEntry) -> ArchivedEntry {
+        ArchivedEntry {
+            seq: Some(entry.seq),
+            message: entry.message,
+            signature_status: entry.signature_status,
+            signature_detail: entry.signature_detail,
+            deleted_at: entry.deleted_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub archives: Vec<ArchiveFile>,
+}
+
+/// Cold storage for messages removed by retention: one zstd-compressed NDJSON
+/// file per period, listed in `manifest.json`.
+///
+/// Each write appends a new zstd frame, so a file may hold several frames that
+/// decompress as one stream.
+#[derive(Debug)]
+pub struct Archive {
+    dir: PathBuf,
+    period: ArchivePeriod,
+    /// Serializes writes so that files and the manifest stay consistent
+    lock: Mutex<()>,
+}
+
+impl Archive {
+    pub fn new(dir: PathBuf, period: ArchivePeriod) -> Result<Archive> {
+        fs::create_dir_all(&dir).with_context(|| format!("failed to create archive directory {}", dir.display()))?;
+        Ok(Archive {
+            dir,
+            period,
+            lock: Mutex::new(()),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Appends messages to their period's file and records them in the
+    /// manifest. Files are synced before this returns, so the messages can be
+    /// deleted afterwards.
+    pub async fn write(&self, entries: Vec<LogEntry>) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let _guard = self.lock.lock().await;
+        let dir = self.dir.clone();
+        let period = self.period;
+        let entries = entries.into_iter().map(ArchivedEntry::from).collect();
+        tokio::task::spawn_blocking(move || write_blocking(&dir, period, entries)).await?
+    }
+
+    pub async fn manifest(&self) -> Result<Manifest> {
+        let _guard = self.lock.lock().await;
+        read_manifest(&self.dir)
+    }
+
+    /// Loads archived messages timestamped in `[since, until)` into `store`,
+    /// replacing whatever it already held for that range. Messages keep their
+    /// seq, signature status and trash state, except those archived before
+    /// these were, which are numbered afresh.
+    pub async fn rehydrate(&self, since: DateTime<Utc>, until: DateTime<Utc>, store: &dyn LogStore) -> Result<u64> {
+        let files: Vec<PathBuf> = self
+            .manifest()
+            .await?
+            .archives
+            .into_iter()
+            .filter(|archive| archive.period_start < until && archive.period_end > since)
+            .map(|archive| self.dir.join(archive.file))
+            .collect();
+
+        let range = LogFilter::Since(since).and(LogFilter::Before(until));
+        store.delete(&range, None).await?;
+
+        // Rows archived just before a crash may be archived again on the next run
+        let mut seen = HashSet::new();
+        let mut rehydrated = 0;
+        // Numbered once every original seq is taken, so the fresh ones cannot clash
+        let mut unnumbered = Vec::new();
+        for path in files {
+            let entries = tokio::task::spawn_blocking(move || read_archive(&path, since, until)).await??;
+            let mut numbered = Vec::new();
+            for entry in entries.into_iter().filter(|entry| seen.insert(entry.message.id.clone())) {
+                match entry.seq {
+                    Some(seq) => numbered.push(LogEntry {
+                        seq,
+                        message: entry.message,
+                        snippet: None,
+                        signature_status: entry.signature_status,
+                        signature_detail: entry.signature_detail,
+                        deleted_at: entry.deleted_at,
+                    }),
+                    None => unnumbered.push(entry.message),
+                }
+            }
+
+            for batch in numbered.chunks(REHYDRATE_BATCH) {
+                store.insert_archived(batch).await?;
+            }
+            rehydrated += numbered.len() as u64;
+        }
+        for batch in unnumbered.chunks(REHYDRATE_BATCH) {
+            store.insert_batch(batch).await?;
+        }
+        rehydrated += unnumbered.len() as u64;
+
+        info!("Rehydrated {} archived messages from {} to {}", rehydrated, since, until);
+        Ok(rehydrated)
+    }
+}
+
+fn write_blocking(dir: &Path, period: ArchivePeriod, entries: Vec<ArchivedEntry>) -> Result<()> {
+    let mut by_period: BTreeMap<DateTime<Utc>, Vec<ArchivedEntry>> = BTreeMap::new();
+    for entry in entries {
+        by_period.entry(period.start(entry.message.timestamp)).or_default().push(entry);
+    }
+
+    let mut manifest = read_manifest(dir)?;
+    for (start, entries) in by_period {
+        let file = period.file_name(start);
+        let path = dir.join(&file);
+
+        let output = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("failed to open archive {}", path.display()))?;
+        let mut encoder = zstd::Encoder::new(BufWriter::new(output), COMPRESSION_LEVEL)?;
+        for entry in &entries {
+            serde_json::to_writer(&mut encoder, entry)?;
+            encoder.write_all(b"\n")?;
+        }
+        let output = encoder
+            .finish()?
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .with_context(|| format!("failed to write archive {}", path.display()))?;
+        output.sync_all()?;
+        let compressed_bytes = output.metadata()?.len();
+
+        let first = entries.iter().map(|entry| entry.message.timestamp).min().unwrap_or(start);
+        let last = entries.iter().map(|entry| entry.message.timestamp).max().unwrap_or(start);
+        match manifest.archives.iter_mut().find(|archive| archive.file == file) {
+            Some(archive) => {
+                archive.messages += entries.len() as u64;
+                archive.first_timestamp = archive.first_timestamp.min(first);
+                archive.last_timestamp = archive.last_timestamp.max(last);
+                archive.compressed_bytes = compressed_bytes;
+            }
+            None => manifest.archives.push(ArchiveFile {
+                file,
+                period_start: start,
+                period_end: start + period.length(),
+                messages: entries.len() as u64,
+                first_timestamp: first,
+                last_timestamp: last,
+                compressed_bytes,
+            }),
+        }
+    }
+
+    manifest.archives.sort_by_key(|archive| archive.period_start);
+    write_manifest(dir, &manifest)
+}
+
+fn read_manifest(dir: &Path) -> Result<Manifest> {
+    let path = dir.join(MANIFEST_FILE);
+    if !path.exists() {
+        return Ok(Manifest::default());
+    }
+
+    let file = File::open(&path).with_context(|| format!("failed to open {}", path.display()))?;
+    serde_json::from_reader(BufReader::new(file)).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+/// Replaces the manifest atomically, so a crash never leaves it half written
+fn write_manifest(dir: &Path, manifest: &Manifest) -> Result<()> {
+    let path = dir.join(MANIFEST_FILE);
+    let temporary = dir.join(format!("{}.tmp", MANIFEST_FILE));
+
+    let mut file = File::create(&temporary)?;
+    serde_json::to_writer_pretty(&mut file, manifest)?;
+    file.sync_all()?;
+    fs::rename(&temporary, &path).with_context(|| format!("failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+fn read_archive(path: &Path, since: DateTime<Utc>, until: DateTime<Utc>) -> Result<Vec<ArchivedEntry>> {
+    let file = File::open(path).with_context(|| format!("failed to open archive {}", path.display()))?;
+    let reader = BufReader::new(zstd::Decoder::new(file)?);
+
+    let mut entries = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.with_context(|| format!("failed to decompress {}", path.display()))?;
+        if line.is_empty() {
+            continue;
+        }
+        let entry: ArchivedEntry = serde_json::from_str(&line)
+            .with_context(|| format!("invalid message on line {} of {}", index + 1, path.display()))?;
+        if entry.message.timestamp >= since && entry.message.timestamp < until {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        signing::SignatureUpdate,
+        store::{
+            testing::{day, message, messages, TempDir},
+            DatabaseConfig, Page, SqliteStore,
+        },
+    };
+
+    /// What rehydration must bring back of each message
+    type Kept = (i64, String, Option<SignatureStatus>, Option<String>, Option<DateTime<Utc>>);
+
+    async fn everything(store: &dyn LogStore) -> Vec<Kept> {
+        let page = Page { limit: 100, offset: 0, cursor: None };
+        let mut entries = store.query(&LogFilter::All, page).await.unwrap();
+        entries.sort_by_key(|entry| entry.seq);
+        entries
+            .into_iter()
+            .map(|entry| (entry.seq, entry.message.id, entry.signature_status, entry.signature_detail, entry.deleted_at))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn rehydrates_messages_as_they_were_archived() {
+        let dir = TempDir::new();
+        let live = SqliteStore::open(&dir.config()).await.unwrap();
+        let sent = messages(day(0), 5);
+        live.insert_batch(&sent).await.unwrap();
+        live.set_signatures(&[SignatureUpdate {
+            id: sent[1].id.clone(),
+            timestamp: sent[1].timestamp,
+            status: SignatureStatus::Invalid,
+            detail: Some("bad signature".to_string()),
+        }])
+        .await
+        .unwrap();
+        live.trash(&LogFilter::Id(vec![sent[3].id.clone()]), None).await.unwrap();
+        // Gaps in seq must survive too
+        live.delete(&LogFilter::Id(vec![sent[0].id.clone()]), None).await.unwrap();
+        let expected = everything(&live).await;
+
+        let archive = Archive::new(dir.path().join("archive"), ArchivePeriod::Day).unwrap();
+        archive.write(live.oldest(&LogFilter::All, 100).await.unwrap()).await.unwrap();
+        // A line from before seqs were archived, in the same file
+        let legacy = message(day(0) + ChronoDuration::hours(1), Some("old"), "archived long ago");
+        let line = ArchivedEntry {
+            seq: None,
+            message: legacy.clone(),
+            signature_status: None,
+            signature_detail: None,
+            deleted_at: None,
+        };
+        write_blocking(archive.dir(), ArchivePeriod::Day, vec![line]).unwrap();
+
+        let config = DatabaseConfig {
+            path: dir.path().join("rehydrated.db"),
+            ..dir.config()
+        };
+        let rehydrated = SqliteStore::open(&config).await.unwrap();
+        // Twice, as the second run must replace what the first loaded
+        for _ in 0..2 {
+            assert_eq!(archive.rehydrate(day(0), day(1), &rehydrated).await.unwrap(), 5);
+
+            let found = everything(&rehydrated).await;
+            assert_eq!(found[..4], expected[..]);
+            let (seq, id, status, _, deleted_at) = &found[4];
+            assert!(*seq > expected[3].0);
+            assert_eq!((id, *status, *deleted_at), (&legacy.id, Some(SignatureStatus::Unsigned), None));
+
+            let stats = rehydrated.stats().await.unwrap();
+            assert_eq!((stats.stored_messages, stats.trashed_messages), (4, 1));
+        }
+
+        // New messages are numbered after every rehydrated one
+        rehydrated.insert_batch(&messages(day(2), 1)).await.unwrap();
+        let found = everything(&rehydrated).await;
+        assert!(found[5].0 > found[4].0);
+    }
+}
@@ -0,0 +1,355 @@
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+/// Appended to messages cut down to the listener's size limit
+pub const TRUNCATION_MARKER: &[u8] = b" [truncated]";
+
+/// Longest MSG-LEN taken as an octet count; a longer run of digits is message text
+const MAX_LENGTH_DIGITS: usize = 9;
+
+/// What a listener does with a message longer than its size limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OversizePolicy {
+    /// Discard the whole message
+    Drop,
+    /// Keep the first part of the message and append a truncation marker
+    Truncate,
+    /// Deliver the message as several consecutive messages
+    Split,
+}
+
+impl OversizePolicy {
+    /// Prefix of the per-source counters in `/api/stats`
+    pub fn stats_prefix(self) -> &'static str {
+        match self {
+            OversizePolicy::Drop => "dropped_",
+            OversizePolicy::Truncate => "truncated_",
+            OversizePolicy::Split => "split_",
+        }
+    }
+
+    /// The messages to parse from an oversized UDP datagram
+    pub fn apply_to_datagram(self, data: &[u8], max_size: usize) -> Vec<Vec<u8>> {
+        match self {
+            OversizePolicy::Drop => Vec::new(),
+            OversizePolicy::Truncate => vec![truncate_with_marker(data, max_size)],
+            OversizePolicy::Split => data.chunks(max_size.max(1)).map(<[u8]>::to_vec).collect(),
+        }
+    }
+}
+
+/// Splits a TCP byte stream into frames as RFC 6587 allows: `MSG-LEN SP MSG`
+/// with an octet count, or anything else up to a newline. The two can be mixed,
+/// since a syslog message never starts with a digit.
+pub struct FrameReader<R> {
+    reader: R,
+    max_size: usize,
+    /// Octets still to come of a counted frame cut short by the size limit
+    remaining: Option<usize>,
+    /// Set when the last frame stopped in the middle of a line at the size limit
+    in_line: bool,
+}
+
+impl<R> FrameReader<R>
+where
+    R: AsyncBufRead + Unpin,
+{
+    pub fn new(reader: R, max_size: usize) -> FrameReader<R> {
+        FrameReader {
+            reader,
+            // A zero limit could never make progress
+            max_size: max_size.max(1),
+            remaining: None,
+            in_line: false,
+        }
+    }
+
+    /// Reads the next frame into `frame`, which must be empty, without ever
+    /// buffering more than `max_size` bytes. Returns `Some(true)` for a whole
+    /// message, `Some(false)` when the limit was hit first (the rest follows in
+    /// later frames) and `None` at EOF. A message cut off by EOF is returned whole.
+    pub async fn read_frame(&mut self, frame: &mut Vec<u8>) -> std::io::Result<Option<bool>> {
+        if self.remaining.is_none() && !self.in_line {
+            self.remaining = self.read_length(frame).await?;
+        }
+        match self.remaining {
+            Some(remaining) => self.read_counted(frame, remaining).await,
+            None => {
+                let complete = self.read_line(frame).await?;
+                self.in_line = complete == Some(false);
+                Ok(complete)
+            }
+        }
+    }
+
+    /// Reads an octet count and the space after it. Digits that turn out not to
+    /// be one are left in `frame` as the start of a line.
+    async fn read_length(&mut self, frame: &mut Vec<u8>) -> std::io::Result<Option<usize>> {
+        loop {
+            let Some(&byte) = self.reader.fill_buf().await?.first() else {
+                return Ok(None);
+            };
+            match byte {
+                b'1'..=b'9' if frame.is_empty() => {}
+                b'0'..=b'9' if !frame.is_empty() && frame.len() < MAX_LENGTH_DIGITS => {}
+                b' ' if !frame.is_empty() => {
+                    self.reader.consume(1);
+                    let length = std::str::from_utf8(frame).ok().and_then(|digits| digits.parse().ok());
+                    frame.clear();
+                    return Ok(length);
+                }
+                _ => return Ok(None),
+            }
+            if frame.len() >= self.max_size {
+                return Ok(None);
+            }
+            frame.push(byte);
+            self.reader.consume(1);
+        }
+    }
+
+    async fn read_counted(&mut self, frame: &mut Vec<u8>, mut remaining: usize) -> std::io::Result<Option<bool>> {
+        loop {
+            let available = self.reader.fill_buf().await?;
+            if available.is_empty() {
+                self.remaining = None;
+                return Ok((!frame.is_empty()).then_some(true));
+            }
+
+            let take = available.len().min(remaining).min(self.max_size - frame.len());
+            frame.extend_from_slice(&available[..take]);
+            self.reader.consume(take);
+            remaining -= take;
+            if remaining == 0 {
+                self.remaining = None;
+                return Ok(Some(true));
+            }
+            if frame.len() >= self.max_size {
+                self.remaining = Some(remaining);
+                return Ok(Some(false));
+            }
+        }
+    }
+
+    async fn read_line(&mut self, frame: &mut Vec<u8>) -> std::io::Result<Option<bool>> {
+        loop {
+            let available = self.reader.fill_buf().await?;
+            if available.is_empty() {
+                return Ok((!frame.is_empty()).then_some(true));
+            }
+
+            // A newline right after the last allowed byte still ends the line in time
+            let room = self.max_size.saturating_sub(frame.len());
+            let window = &available[..available.len().min(room.saturating_add(1))];
+            if let Some(newline) = window.iter().position(|&b| b == b'\n') {
+                frame.extend_from_slice(&available[..newline]);
+                self.reader.consume(newline + 1);
+                return Ok(Some(true));
+            }
+
+            let take = available.len().min(room);
+            frame.extend_from_slice(&available[..take]);
+            self.reader.consume(take);
+            if frame.len() >= self.max_size {
+                // A line of exactly `max_size` bytes is still whole
+                return match self.reader.fill_buf().await?.first() {
+                    Some(b'\n') => {
+                        self.reader.consume(1);
+                        Ok(Some(true))
+                    }
+                    Some(_) => Ok(Some(false)),
+                    None => Ok(Some(true)),
+                };
+            }
+        }
+    }
+}
+
+/// Applies an oversize policy to the frames of one TCP connection
+#[derive(Debug)]
+pub struct OversizeFilter {
+    policy: OversizePolicy,
+    max_size: usize,
+    /// Set while the rest of an oversized message is being skipped
+    discarding: bool,
+    /// Set while the pieces of an oversized message are being delivered
+    splitting: bool,
+}
+
+impl OversizeFilter {
+    pub fn new(policy: OversizePolicy, max_size: usize) -> OversizeFilter {
+        OversizeFilter {
+            policy,
+            max_size,
+            discarding: false,
+            splitting: false,
+        }
+    }
+
+    /// Takes a frame as `FrameReader` returned it, and gives back the message to
+    /// parse from it, if any, and whether the frame began an oversized message
+    pub fn apply(&mut self, frame: Vec<u8>, complete: bool) -> (Option<Vec<u8>>, bool) {
+        if self.discarding {
+            self.discarding = !complete;
+            return (None, false);
+        }
+
+        let oversized = !complete && !self.splitting;
+        self.splitting = !complete && self.policy == OversizePolicy::Split;
+        if !oversized {
+            return (Some(frame), false);
+        }
+        match self.policy {
+            OversizePolicy::Drop => {
+                self.discarding = true;
+                (None, true)
+            }
+            OversizePolicy::Truncate => {
+                self.discarding = true;
+                (Some(truncate_with_marker(&frame, self.max_size)), true)
+            }
+            OversizePolicy::Split => (Some(frame), true),
+        }
+    }
+}
+
+/// Cuts `data` down to `max_size` bytes including the truncation marker, backing off
+/// to a character boundary when the cut would split a UTF-8 sequence. A limit too
+/// small for the marker keeps only what fits.
+pub fn truncate_with_marker(data: &[u8], max_size: usize) -> Vec<u8> {
+    let marker = if max_size >= TRUNCATION_MARKER.len() { TRUNCATION_MARKER } else { &[] };
+    let mut end = (max_size - marker.len()).min(data.len());
+    if let Err(e) = std::str::from_utf8(&data[..end]) {
+        if e.error_len().is_none() {
+            end = e.valid_up_to();
+        }
+    }
+
+    let mut truncated = data[..end].to_vec();
+    truncated.extend_from_slice(marker);
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::BufReader;
+
+    /// Every frame in `stream`, read through a tiny buffer so frames straddle refills
+    async fn frames(stream: &[u8], max_size: usize) -> Vec<(String, bool)> {
+        let mut reader = FrameReader::new(BufReader::with_capacity(3, stream), max_size);
+        let mut frames = Vec::new();
+        let mut frame = Vec::new();
+        while let Some(complete) = reader.read_frame(&mut frame).await.unwrap() {
+            frames.push((String::from_utf8(std::mem::take(&mut frame)).unwrap(), complete));
+        }
+        frames
+    }
+
+    fn whole(texts: &[&str]) -> Vec<(String, bool)> {
+        texts.iter().map(|text| (text.to_string(), true)).collect()
+    }
+
+    #[tokio::test]
+    async fn reads_newline_delimited_frames() {
+        assert_eq!(frames(b"<13>one\n<13>two\n\n<13>three\n", 100).await, whole(&["<13>one", "<13>two", "", "<13>three"]));
+    }
+
+    #[tokio::test]
+    async fn reads_octet_counted_frames() {
+        // Counted messages may hold newlines and need no terminator
+        assert_eq!(frames(b"7 <13>one9 <13>t\nwo\n12 <13>three\n\n", 100).await, whole(&["<13>one", "<13>t\nwo\n", "<13>three\n\n"]));
+        // Mixed with newline framing, and digits that are not a count
+        assert_eq!(frames(b"7 <13>one<13>two\n42\n1234567890 x\n", 100).await, whole(&["<13>one", "<13>two", "42", "1234567890 x"]));
+    }
+
+    #[tokio::test]
+    async fn cuts_oversized_frames_at_the_limit() {
+        assert_eq!(
+            frames(b"<13>abcdefgh\n<13>ok\n", 6).await,
+            vec![("<13>ab".to_string(), false), ("cdefgh".to_string(), true), ("<13>ok".to_string(), true)]
+        );
+        assert_eq!(
+            frames(b"12 <13>abcdefgh<13>ok\n", 6).await,
+            vec![("<13>ab".to_string(), false), ("cdefgh".to_string(), true), ("<13>ok".to_string(), true)]
+        );
+        // A count longer than the limit is read as text
+        assert_eq!(frames(b"123 x\n", 2).await, vec![("12".to_string(), false), ("3 ".to_string(), false), ("x".to_string(), true)]);
+    }
+
+    #[tokio::test]
+    async fn returns_what_arrived_before_eof() {
+        assert_eq!(frames(b"<13>one\n<13>tw", 100).await, whole(&["<13>one", "<13>tw"]));
+        assert_eq!(frames(b"20 <13>short", 100).await, whole(&["<13>short"]));
+        assert_eq!(frames(b"20 ", 100).await, whole(&[]));
+        assert_eq!(frames(b"", 100).await, whole(&[]));
+    }
+
+    /// What a connection delivers, and how many messages it counted as oversized
+    async fn filtered(stream: &[u8], max_size: usize, policy: OversizePolicy) -> (Vec<String>, usize) {
+        let mut filter = OversizeFilter::new(policy, max_size);
+        let mut messages = Vec::new();
+        let mut oversized = 0;
+        let mut reader = FrameReader::new(stream, max_size);
+        let mut frame = Vec::new();
+        while let Some(complete) = reader.read_frame(&mut frame).await.unwrap() {
+            let (message, counted) = filter.apply(std::mem::take(&mut frame), complete);
+            messages.extend(message.map(|message| String::from_utf8(message).unwrap()));
+            oversized += usize::from(counted);
+        }
+        (messages, oversized)
+    }
+
+    #[tokio::test]
+    async fn applies_each_oversize_policy() {
+        let stream = b"short\nthis line is much too long\n20 counted but too longlast\n";
+        assert_eq!(filtered(stream, 16, OversizePolicy::Drop).await, (vec!["short".into(), "last".into()], 2));
+        assert_eq!(
+            filtered(stream, 16, OversizePolicy::Truncate).await,
+            (vec!["short".into(), "this [truncated]".into(), "coun [truncated]".into(), "last".into()], 2)
+        );
+        assert_eq!(
+            filtered(stream, 16, OversizePolicy::Split).await,
+            (
+                vec![
+                    "short".into(),
+                    "this line is muc".into(),
+                    "h too long".into(),
+                    "counted but too ".into(),
+                    "long".into(),
+                    "last".into(),
+                ],
+                2
+            )
+        );
+    }
+
+    #[test]
+    fn applies_each_oversize_policy_to_datagrams() {
+        let data = b"0123456789abcdefghij";
+        assert!(OversizePolicy::Drop.apply_to_datagram(data, 16).is_empty());
+        assert_eq!(OversizePolicy::Truncate.apply_to_datagram(data, 16), vec![b"0123 [truncated]".to_vec()]);
+        assert_eq!(OversizePolicy::Split.apply_to_datagram(data, 16), vec![b"0123456789abcdef".to_vec(), b"ghij".to_vec()]);
+    }
+
+    #[test]
+    fn truncation_never_exceeds_the_limit() {
+        let data = b"0123456789abcdefghij";
+        let marker = TRUNCATION_MARKER.len();
+        assert_eq!(truncate_with_marker(data, 0), b"");
+        assert_eq!(truncate_with_marker(data, 5), b"01234");
+        assert_eq!(truncate_with_marker(data, marker - 1), &data[..marker - 1]);
+        assert_eq!(truncate_with_marker(data, marker), TRUNCATION_MARKER);
+        assert_eq!(truncate_with_marker(data, marker + 2), b"01 [truncated]");
+        assert_eq!(truncate_with_marker(data, data.len()), b"01234567 [truncated]");
+        assert_eq!(truncate_with_marker(b"short", 100), b"short [truncated]");
+    }
+
+    #[test]
+    fn truncation_keeps_whole_characters() {
+        // "é" is two bytes, so cutting after its first leaves it out
+        let data = "aé bcdefghijklmnop".as_bytes();
+        assert_eq!(truncate_with_marker(data, 14), b"a [truncated]");
+        assert_eq!(truncate_with_marker(data, 15), "aé [truncated]".as_bytes());
+        assert_eq!(truncate_with_marker(data, 2), b"a");
+    }
+}
@@ -0,0 +1,214 @@
+use crate::store::{without_held, LogFilter, LogStore};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+use tracing::{error, info};
+
+/// Rows deleted per statement, so ingestion keeps going while a job runs
+const DELETE_CHUNK: u64 = 5000;
+
+/// Finished jobs remembered for `/api/jobs`, newest kept
+const FINISHED_JOBS_KEPT: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// Progress of a delete running in the background
+#[derive(Debug, Clone, Serialize)]
+pub struct DeleteJob {
+    pub id: u64,
+    pub status: JobStatus,
+    /// Whether messages go to the trash rather than being deleted outright
+    pub to_trash: bool,
+    /// Messages that matched when the job started, held ones included
+    pub matched: u64,
+    /// Matching messages a legal hold kept from being deleted when the job started
+    pub held: u64,
+    pub deleted: u64,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Delete jobs started with `background=true` on `DELETE /api/logs` or `/api/trash`
+#[derive(Debug, Default)]
+pub struct DeleteJobs {
+    jobs: Mutex<BTreeMap<u64, DeleteJob>>,
+}
+
+impl DeleteJobs {
+    /// Starts deleting (or trashing) every message matching `filter` that no
+    /// legal hold protects, a chunk at a time. Only messages received before the
+    /// job started are deleted, so one that matches keeps arriving cannot run forever.
+    pub fn start(
+        self: &Arc<Self>,
+        store: Arc<dyn LogStore>,
+        filter: LogFilter,
+        matched: u64,
+        held: u64,
+        to_trash: bool,
+    ) -> DeleteJob {
+        let job = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let id = jobs.keys().next_back().map_or(1, |id| id + 1);
+            let job = DeleteJob {
+                id,
+                status: JobStatus::Running,
+                to_trash,
+                matched,
+                held,
+                deleted: 0,
+                started_at: Utc::now(),
+                finished_at: None,
+                error: None,
+            };
+            jobs.insert(id, job.clone());
+
+            let finished: Vec<u64> = jobs
+                .values()
+                .filter(|job| job.status != JobStatus::Running)
+                .map(|job| job.id)
+                .collect();
+            for id in &finished[..finished.len().saturating_sub(FINISHED_JOBS_KEPT)] {
+                jobs.remove(id);
+            }
+            job
+        };
+
+        info!("Delete job {} started for {} messages", job.id, matched);
+        let filter = filter.and(LogFilter::Before(job.started_at));
+        tokio::spawn(run(self.clone(), store, filter, job.id, to_trash));
+        job
+    }
+
+    pub fn get(&self, id: u64) -> Option<DeleteJob> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Newest first
+    pub fn list(&self) -> Vec<DeleteJob> {
+        self.jobs.lock().unwrap().values().rev().cloned().collect()
+    }
+
+    fn update(&self, id: u64, update: impl FnOnce(&mut DeleteJob)) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            update(job);
+        }
+    }
+}
+
+async fn run(jobs: Arc<DeleteJobs>, store: Arc<dyn LogStore>, filter: LogFilter, id: u64, to_trash: bool) {
+    loop {
+        // Holds placed while the job runs apply from its next chunk
+        let result = match without_held(store.as_ref(), filter.clone()).await {
+            Ok(filter) if to_trash => store.trash(&filter, Some(DELETE_CHUNK)).await,
+            Ok(filter) => store.delete(&filter, Some(DELETE_CHUNK)).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(deleted) => {
+                let done = deleted < DELETE_CHUNK;
+                jobs.update(id, |job| {
+                    job.deleted += deleted;
+                    if done {
+                        job.status = JobStatus::Completed;
+                        job.finished_at = Some(Utc::now());
+                    }
+                });
+                if done {
+                    info!("Delete job {} completed", id);
+                    return;
+                }
+            }
+            Err(e) => {
+                error!("Delete job {} failed: {}", id, e);
+                jobs.update(id, |job| {
+                    job.status = JobStatus::Failed;
+                    job.finished_at = Some(Utc::now());
+                    job.error = Some(e.to_string());
+                });
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{
+        testing::{messages, TempDir},
+        HoldCriteria, Page, SqliteStore,
+    };
+    use chrono::Duration;
+
+    /// The job once it has stopped running
+    async fn finished(jobs: &DeleteJobs, id: u64) -> DeleteJob {
+        loop {
+            let job = jobs.get(id).unwrap();
+            if job.status != JobStatus::Running {
+                return job;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn jobs_stop_at_messages_received_after_they_start() {
+        let dir = TempDir::new();
+        let store: Arc<dyn LogStore> = Arc::new(SqliteStore::open(&dir.config()).await.unwrap());
+        store.insert_batch(&messages(Utc::now() - Duration::hours(1), 3)).await.unwrap();
+        let later = messages(Utc::now() + Duration::hours(1), 2);
+        store.insert_batch(&later).await.unwrap();
+
+        let jobs = Arc::new(DeleteJobs::default());
+        let job = jobs.start(store.clone(), LogFilter::All, 5, 0, false);
+        let job = finished(&jobs, job.id).await;
+        assert_eq!(job.status, JobStatus::Completed);
+        assert_eq!(job.deleted, 3);
+
+        let page = Page { limit: 10, offset: 0, cursor: None };
+        let mut left: Vec<String> =
+            store.query(&LogFilter::All, page).await.unwrap().into_iter().map(|entry| entry.message.id).collect();
+        left.sort();
+        let mut expected: Vec<String> = later.into_iter().map(|message| message.id).collect();
+        expected.sort();
+        assert_eq!(left, expected);
+    }
+
+    #[tokio::test]
+    async fn jobs_skip_held_messages() {
+        let dir = TempDir::new();
+        let store: Arc<dyn LogStore> = Arc::new(SqliteStore::open(&dir.config()).await.unwrap());
+        let mut batch = messages(Utc::now() - Duration::hours(1), 6);
+        for message in &mut batch[..2] {
+            message.hostname = Some("db".to_string());
+        }
+        store.insert_batch(&batch).await.unwrap();
+        let held = HoldCriteria {
+            hostname: vec!["db".to_string()],
+            ..HoldCriteria::default()
+        };
+        store.add_legal_hold("audit", "legal", &held).await.unwrap();
+
+        let jobs = Arc::new(DeleteJobs::default());
+        for to_trash in [true, false] {
+            let filter = LogFilter::Trashed(!to_trash);
+            let job = jobs.start(store.clone(), filter.clone(), 6, 2, to_trash);
+            let job = finished(&jobs, job.id).await;
+            assert_eq!((job.status, job.to_trash, job.deleted), (JobStatus::Completed, to_trash, 4));
+        }
+        let db = LogFilter::Hostname(vec!["db".to_string()]);
+        assert_eq!(store.count(&LogFilter::All).await.unwrap(), 2);
+        assert_eq!(store.count(&db.and(LogFilter::Trashed(false))).await.unwrap(), 2);
+    }
+}
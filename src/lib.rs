@@ -0,0 +1,7 @@
+//! Syslog message parsing, shared by the server binary and the integration tests.
+
+mod framing;
+mod parser;
+
+pub use framing::{truncate_with_marker, FrameReader, OversizeFilter, OversizePolicy, TRUNCATION_MARKER};
+pub use parser::{ParseMode, SyslogMessage, SyslogParser, DEFAULT_PRIORITY};
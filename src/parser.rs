@@ -0,0 +1,337 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use syslog_format::{MAX_PRIORITY, NILVALUE};
+use uuid::Uuid;
+
+/// PRI assigned to messages without a usable one (local0.info, as plain-text lines always were)
+pub const DEFAULT_PRIORITY: u8 = 134;
+
+/// Byte order mark that RFC 5424 allows in front of a UTF-8 MSG
+const BOM: char = '\u{FEFF}';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ParseMode {
+    /// Reject messages whose PRI is missing or out of range
+    Strict,
+    /// Keep such messages, but flag them and record the bytes as received
+    Lenient,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyslogMessage {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    pub facility: u8,
+    pub severity: u8,
+    pub hostname: Option<String>,
+    pub app_name: Option<String>,
+    pub proc_id: Option<String>,
+    pub msg_id: Option<String>,
+    /// RFC 5424 structured data elements, exactly as they appeared on the wire
+    #[serde(default)]
+    pub structured_data: Option<String>,
+    pub message: String,
+    pub raw_message: String,
+    pub source_ip: String,
+    /// Why the message was only accepted in lenient mode, if it was malformed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parse_error: Option<String>,
+    /// Bytes exactly as received, kept for flagged messages so broken senders can be audited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_bytes: Option<Vec<u8>>,
+}
+
+pub struct SyslogParser {
+    rfc3164_regex: Regex,
+    rfc5424_regex: Regex,
+}
+
+/// Parses a PRI field, accepting only values in the valid 0-191 range written
+/// without leading zeros, as RFC 5424 and RFC 3164 require
+fn parse_priority(pri: &str) -> Option<u8> {
+    if pri.len() > 1 && pri.starts_with('0') {
+        return None;
+    }
+    pri.parse::<u16>()
+        .ok()
+        .filter(|priority| *priority <= u16::from(MAX_PRIORITY))
+        .map(|priority| priority as u8)
+}
+
+/// Resolves the PRI of a message according to `mode`. In lenient mode a missing or
+/// invalid PRI is replaced by `DEFAULT_PRIORITY` and the reason is returned alongside.
+fn resolve_priority(pri: Option<&str>, mode: ParseMode) -> Result<(u8, Option<String>)> {
+    let error = match pri {
+        Some(pri) => match parse_priority(pri) {
+            Some(priority) => return Ok((priority, None)),
+            None => format!("invalid PRI <{}>", pri),
+        },
+        None => "missing PRI".to_string(),
+    };
+    
+    match mode {
+        ParseMode::Strict => Err(anyhow!(error)),
+        ParseMode::Lenient => Ok((DEFAULT_PRIORITY, Some(error))),
+    }
+}
+
+/// Maps an RFC 5424 header field to `None` when it holds the NILVALUE
+fn non_nil(field: &str) -> Option<String> {
+    (field != NILVALUE).then(|| field.to_string())
+}
+
+/// Returns the length of the `[SD-ID param="value"]...` elements at the start of
+/// `input`, honouring quoted values and backslash escapes inside them.
+fn structured_data_len(input: &str) -> Option<usize> {
+    let bytes = input.as_bytes();
+    let mut pos = 0;
+    
+    while bytes.get(pos) == Some(&b'[') {
+        pos += 1;
+        let mut in_quotes = false;
+        loop {
+            match *bytes.get(pos)? {
+                b'\\' if in_quotes => pos += 1,
+                b'"' => in_quotes = !in_quotes,
+                b']' if !in_quotes => break,
+                _ => {}
+            }
+            pos += 1;
+        }
+        pos += 1;
+    }
+    
+    (pos > 0).then_some(pos)
+}
+
+/// Splits the part of an RFC 5424 message after MSGID into structured data and MSG.
+/// Anything that is not well-formed structured data is treated as MSG.
+fn split_structured_data(rest: &str) -> (Option<&str>, &str) {
+    let sd_len = if rest.starts_with(NILVALUE) {
+        Some(NILVALUE.len())
+    } else {
+        structured_data_len(rest)
+    };
+    
+    match sd_len {
+        Some(len) if len == rest.len() || rest[len..].starts_with(' ') => {
+            let structured_data = &rest[..len];
+            let message = rest[len..].strip_prefix(' ').unwrap_or_default();
+            ((structured_data != NILVALUE).then_some(structured_data), message)
+        }
+        _ => (None, rest),
+    }
+}
+
+impl SyslogParser {
+    pub fn new() -> Result<Self> {
+        // HOSTNAME is optional: journald and local /dev/log writers leave it out
+        let rfc3164_regex = Regex::new(
+            r"(?s)^<(\d{1,3})>(\w{3}\s+\d{1,2}\s+\d{2}:\d{2}:\d{2})\s+(?:(\S+)\s+)?([^\s\[:]+)(?:\[([^\s\]]+)\])?:\s?(.*)$"
+        )?;
+        
+        // Header up to MSGID; structured data and MSG are split off by hand
+        let rfc5424_regex = Regex::new(
+            r"^<(\d{1,3})>(\d{1,2}) (\S+) (\S+) (\S+) (\S+) (\S+)(?: |$)"
+        )?;
+        
+        Ok(Self {
+            rfc3164_regex,
+            rfc5424_regex,
+        })
+    }
+
+    /// Parses a datagram or frame as received. Invalid UTF-8 is decoded lossily and
+    /// flagged; flagged messages keep a copy of the original bytes.
+    pub fn parse_bytes(&self, data: &[u8], source_ip: &str, mode: ParseMode) -> Result<SyslogMessage> {
+        let (text, utf8_error) = match std::str::from_utf8(data) {
+            Ok(text) => (text.into(), None),
+            Err(e) => (String::from_utf8_lossy(data), Some(format!("invalid UTF-8: {}", e))),
+        };
+        
+        let mut message = self.parse(text.trim(), source_ip, mode)?;
+        if let Some(error) = utf8_error {
+            message.parse_error = Some(match message.parse_error.take() {
+                Some(existing) => format!("{}; {}", existing, error),
+                None => error,
+            });
+        }
+        if message.parse_error.is_some() {
+            message.original_bytes = Some(data.to_vec());
+        }
+        
+        Ok(message)
+    }
+
+    pub fn parse(&self, raw_message: &str, source_ip: &str, mode: ParseMode) -> Result<SyslogMessage> {
+        let id = Uuid::new_v4().to_string();
+        let timestamp = Utc::now();
+        
+        // Try RFC 5424 format first
+        if let Some(captures) = self.rfc5424_regex.captures(raw_message) {
+            let (priority, parse_error) = resolve_priority(Some(&captures[1]), mode)?;
+            let facility = priority >> 3;
+            let severity = priority & 0x07;
+            
+            let rest = &raw_message[captures[0].len()..];
+            let (structured_data, message) = split_structured_data(rest);
+            let message = message.strip_prefix(BOM).unwrap_or(message);
+            
+            return Ok(SyslogMessage {
+                id,
+                timestamp,
+                facility,
+                severity,
+                hostname: non_nil(&captures[4]),
+                app_name: non_nil(&captures[5]),
+                proc_id: non_nil(&captures[6]),
+                msg_id: non_nil(&captures[7]),
+                structured_data: structured_data.map(str::to_string),
+                message: message.to_string(),
+                raw_message: raw_message.to_string(),
+                source_ip: source_ip.to_string(),
+                parse_error,
+                original_bytes: None,
+            });
+        }
+        
+        // Try RFC 3164 format
+        if let Some(captures) = self.rfc3164_regex.captures(raw_message) {
+            let (priority, parse_error) = resolve_priority(Some(&captures[1]), mode)?;
+            let facility = priority >> 3;
+            let severity = priority & 0x07;
+            
+            return Ok(SyslogMessage {
+                id,
+                timestamp,
+                facility,
+                severity,
+                hostname: captures.get(3).map(|m| m.as_str().to_string()),
+                app_name: Some(captures[4].to_string()),
+                proc_id: captures.get(5).map(|m| m.as_str().to_string()),
+                msg_id: None,
+                structured_data: None,
+                message: captures[6].to_string(),
+                raw_message: raw_message.to_string(),
+                source_ip: source_ip.to_string(),
+                parse_error,
+                original_bytes: None,
+            });
+        }
+        
+        // Fallback: a bare `<PRI>` header followed by free text
+        if let Some((pri, rest)) = raw_message
+            .strip_prefix('<')
+            .and_then(|header| header.split_once('>'))
+            .filter(|(pri, _)| pri.bytes().all(|b| b.is_ascii_digit()))
+        {
+            let (priority, parse_error) = resolve_priority(Some(pri), mode)?;
+            let facility = priority >> 3;
+            let severity = priority & 0x07;
+            
+            return Ok(SyslogMessage {
+                id,
+                timestamp,
+                facility,
+                severity,
+                hostname: None,
+                app_name: None,
+                proc_id: None,
+                msg_id: None,
+                structured_data: None,
+                message: rest.to_string(),
+                raw_message: raw_message.to_string(),
+                source_ip: source_ip.to_string(),
+                parse_error,
+                original_bytes: None,
+            });
+        }
+        
+        // If no valid syslog format found, treat as plain message
+        let (priority, parse_error) = resolve_priority(None, mode)?;
+        Ok(SyslogMessage {
+            id,
+            timestamp,
+            facility: priority >> 3,
+            severity: priority & 0x07,
+            hostname: None,
+            app_name: None,
+            proc_id: None,
+            msg_id: None,
+            structured_data: None,
+            message: raw_message.to_string(),
+            raw_message: raw_message.to_string(),
+            source_ip: source_ip.to_string(),
+            parse_error,
+            original_bytes: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str, mode: ParseMode) -> Result<SyslogMessage> {
+        SyslogParser::new().unwrap().parse_bytes(line.as_bytes(), "192.0.2.1", mode)
+    }
+
+    /// Strict mode rejects the line, and lenient mode keeps it as local0.info,
+    /// flagged with the same reason and the bytes as received
+    fn assert_fallback(line: &str, error: &str, text: &str) {
+        assert_eq!(parse(line, ParseMode::Strict).unwrap_err().to_string(), error, "{line}");
+
+        let message = parse(line, ParseMode::Lenient).unwrap();
+        assert_eq!((message.facility, message.severity), (16, 6), "{line}");
+        assert_eq!(message.parse_error.as_deref(), Some(error), "{line}");
+        assert_eq!(message.original_bytes.as_deref(), Some(line.as_bytes()), "{line}");
+        assert_eq!(message.message, text, "{line}");
+    }
+
+    #[test]
+    fn missing_pri() {
+        assert_fallback("hello", "missing PRI", "hello");
+    }
+
+    #[test]
+    fn empty_pri() {
+        assert_fallback("<>x", "invalid PRI <>", "x");
+    }
+
+    #[test]
+    fn pri_out_of_range() {
+        assert_fallback("<192>hello", "invalid PRI <192>", "hello");
+        assert_fallback("<99999999999>hello", "invalid PRI <99999999999>", "hello");
+    }
+
+    #[test]
+    fn pri_with_leading_zero() {
+        assert_fallback("<01>hello", "invalid PRI <01>", "hello");
+        assert_fallback("<006>hello", "invalid PRI <006>", "hello");
+    }
+
+    #[test]
+    fn pri_in_range() {
+        for (line, facility, severity) in [("<191>hello", 23, 7), ("<0>hello", 0, 0), ("<13>hello", 1, 5)] {
+            for mode in [ParseMode::Strict, ParseMode::Lenient] {
+                let message = parse(line, mode).unwrap();
+                assert_eq!((message.facility, message.severity), (facility, severity), "{line}");
+                assert_eq!(message.parse_error, None, "{line}");
+                assert_eq!(message.original_bytes, None, "{line}");
+                assert_eq!(message.message, "hello", "{line}");
+            }
+        }
+    }
+
+    #[test]
+    fn pri_checked_in_every_format() {
+        assert_fallback(
+            "<192>1 2024-06-01T00:00:00Z host app - - - hello",
+            "invalid PRI <192>",
+            "hello",
+        );
+        assert_fallback("<192>Jun  1 00:00:00 host app: hello", "invalid PRI <192>", "hello");
+    }
+}
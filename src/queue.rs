@@ -0,0 +1,263 @@
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+use syslog_parser::SyslogMessage;
+use tokio::{
+    sync::Notify,
+    time::{timeout_at, Instant},
+};
+
+/// What happens to a new message when the ingest queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OverflowPolicy {
+    /// Make TCP readers wait for room, pushing back on senders. UDP cannot be
+    /// paused, so UDP messages are dropped as with `drop-newest`.
+    Block,
+    /// Evict the message that has waited longest
+    DropOldest,
+    /// Discard the incoming message
+    DropNewest,
+    /// Evict the least severe queued message, oldest first among equals, or
+    /// discard the incoming one if nothing queued is less severe than it. Each
+    /// message arriving while the queue is full scans the whole queue.
+    DropLowestSeverity,
+}
+
+/// Messages discarded because the queue was full, by which message was given up
+#[derive(Debug, Default)]
+struct DropCounters {
+    newest: AtomicU64,
+    oldest: AtomicU64,
+    lowest_severity: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DropStats {
+    pub newest: u64,
+    pub oldest: u64,
+    pub lowest_severity: u64,
+}
+
+/// Bounded queue between the listeners and the storage writer
+#[derive(Debug)]
+pub struct IngestQueue {
+    messages: Mutex<VecDeque<SyslogMessage>>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    not_empty: Notify,
+    not_full: Notify,
+    drops: DropCounters,
+}
+
+impl IngestQueue {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            messages: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            policy,
+            not_empty: Notify::new(),
+            not_full: Notify::new(),
+            drops: DropCounters::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.lock().unwrap().len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Adds a message, applying the overflow policy when the queue is full.
+    /// `may_block` is false for listeners that cannot push back (UDP).
+    pub async fn push(&self, message: SyslogMessage, may_block: bool) {
+        loop {
+            // Register for wakeups before checking, so a batch taken in between is not missed
+            let room = self.not_full.notified();
+            {
+                let mut messages = self.messages.lock().unwrap();
+                if messages.len() < self.capacity {
+                    messages.push_back(message);
+                    drop(messages);
+                    self.not_empty.notify_one();
+                    return;
+                }
+
+                match self.policy {
+                    OverflowPolicy::Block if may_block => {}
+                    OverflowPolicy::Block | OverflowPolicy::DropNewest => {
+                        self.drops.newest.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                    OverflowPolicy::DropOldest => {
+                        messages.pop_front();
+                        messages.push_back(message);
+                        self.drops.oldest.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                    OverflowPolicy::DropLowestSeverity => {
+                        // Higher severity numbers are less severe (7 = debug). `max_by_key`
+                        // takes the last of equals, so walking backwards finds the oldest
+                        let least_severe = messages
+                            .iter()
+                            .enumerate()
+                            .rev()
+                            .max_by_key(|(_, queued)| queued.severity)
+                            .map(|(index, queued)| (index, queued.severity));
+                        if let Some((index, severity)) = least_severe {
+                            if severity > message.severity {
+                                messages.remove(index);
+                                messages.push_back(message);
+                            }
+                        }
+                        self.drops.lowest_severity.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                }
+            }
+            room.await;
+        }
+    }
+
+    /// Waits for at least one message, then keeps collecting into `batch` until it
+    /// holds `max` messages or `flush_interval` has passed since the first arrived.
+    pub async fn recv_batch(&self, batch: &mut Vec<SyslogMessage>, max: usize, flush_interval: Duration) {
+        loop {
+            let arrived = self.not_empty.notified();
+            if self.take_into(batch, max) > 0 {
+                break;
+            }
+            arrived.await;
+        }
+
+        let deadline = Instant::now() + flush_interval;
+        while batch.len() < max {
+            let arrived = self.not_empty.notified();
+            if self.take_into(batch, max) > 0 {
+                continue;
+            }
+            if timeout_at(deadline, arrived).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    fn take_into(&self, batch: &mut Vec<SyslogMessage>, max: usize) -> usize {
+        let mut messages = self.messages.lock().unwrap();
+        let count = messages.len().min(max - batch.len());
+        batch.extend(messages.drain(..count));
+        drop(messages);
+
+        if count > 0 {
+            self.not_full.notify_waiters();
+        }
+        count
+    }
+
+    pub fn drop_stats(&self) -> DropStats {
+        DropStats {
+            newest: self.drops.newest.load(Ordering::Relaxed),
+            oldest: self.drops.oldest.load(Ordering::Relaxed),
+            lowest_severity: self.drops.lowest_severity.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::testing::{day, message};
+    use std::sync::Arc;
+
+    fn queued(text: &str, severity: u8) -> SyslogMessage {
+        let mut message = message(day(0), Some("host"), text);
+        message.severity = severity;
+        message
+    }
+
+    /// Fills a queue of two with `first` and `second`, then pushes `third` without blocking
+    async fn overflow(policy: OverflowPolicy, severities: [u8; 3]) -> (Vec<String>, DropStats) {
+        let queue = IngestQueue::new(2, policy);
+        for (text, severity) in ["first", "second", "third"].into_iter().zip(severities) {
+            queue.push(queued(text, severity), false).await;
+        }
+        let mut batch = Vec::new();
+        queue.recv_batch(&mut batch, 10, Duration::ZERO).await;
+        (batch.into_iter().map(|message| message.message).collect(), queue.drop_stats())
+    }
+
+    fn drops(stats: &DropStats) -> (u64, u64, u64) {
+        (stats.newest, stats.oldest, stats.lowest_severity)
+    }
+
+    #[tokio::test]
+    async fn drop_newest_discards_the_incoming_message() {
+        let (kept, stats) = overflow(OverflowPolicy::DropNewest, [6, 6, 6]).await;
+        assert_eq!(kept, ["first", "second"]);
+        assert_eq!(drops(&stats), (1, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_evicts_the_longest_waiting() {
+        let (kept, stats) = overflow(OverflowPolicy::DropOldest, [6, 6, 6]).await;
+        assert_eq!(kept, ["second", "third"]);
+        assert_eq!(drops(&stats), (0, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn drop_lowest_severity_evicts_the_least_severe() {
+        let (kept, stats) = overflow(OverflowPolicy::DropLowestSeverity, [7, 3, 0]).await;
+        assert_eq!(kept, ["second", "third"]);
+        assert_eq!(drops(&stats), (0, 0, 1));
+
+        // Nothing queued is less severe, so the incoming message goes
+        let (kept, stats) = overflow(OverflowPolicy::DropLowestSeverity, [3, 3, 7]).await;
+        assert_eq!(kept, ["first", "second"]);
+        assert_eq!(drops(&stats), (0, 0, 1));
+
+        // As severe as the least severe queued, which is kept
+        let (kept, _) = overflow(OverflowPolicy::DropLowestSeverity, [3, 6, 6]).await;
+        assert_eq!(kept, ["first", "second"]);
+    }
+
+    #[tokio::test]
+    async fn drop_lowest_severity_evicts_the_oldest_of_equals() {
+        let (kept, stats) = overflow(OverflowPolicy::DropLowestSeverity, [7, 7, 4]).await;
+        assert_eq!(kept, ["second", "third"]);
+        assert_eq!(drops(&stats), (0, 0, 1));
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_room_unless_it_cannot() {
+        let queue = Arc::new(IngestQueue::new(2, OverflowPolicy::Block));
+        queue.push(queued("first", 6), true).await;
+        queue.push(queued("second", 6), true).await;
+
+        // UDP cannot wait, so its message is dropped as the newest
+        queue.push(queued("dropped", 6), false).await;
+        assert_eq!(drops(&queue.drop_stats()), (1, 0, 0));
+
+        let blocked = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.push(queued("third", 6), true).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!blocked.is_finished());
+        assert_eq!(queue.len(), 2);
+
+        let mut batch = Vec::new();
+        queue.recv_batch(&mut batch, 1, Duration::ZERO).await;
+        blocked.await.unwrap();
+        queue.recv_batch(&mut batch, 3, Duration::ZERO).await;
+        let kept: Vec<&str> = batch.iter().map(|message| message.message.as_str()).collect();
+        assert_eq!(kept, ["first", "second", "third"]);
+        assert_eq!(drops(&queue.drop_stats()), (1, 0, 0));
+    }
+}
@@ -0,0 +1,592 @@
+use crate::{
+    archive::Archive,
+    store::{exempt, LogFilter, LogStore},
+};
+use anyhow::Result;
+use chrono::{Duration as ChronoDuration, Utc};
+use std::{fmt, sync::Arc, time::Duration};
+use tracing::{error, info, warn};
+
+/// Rows deleted per statement, so a large cleanup never holds the write lock for long
+const DELETE_CHUNK: u64 = 5000;
+
+const FACILITY_NAMES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv", "ftp",
+    "ntp", "security", "console", "solaris-cron", "local0", "local1", "local2", "local3", "local4", "local5",
+    "local6", "local7",
+];
+
+const SEVERITY_NAMES: [&str; 8] = ["emerg", "alert", "crit", "err", "warning", "notice", "info", "debug"];
+
+/// Limits enforced by the background retention job. Rows are always removed
+/// oldest first by their `timestamp`, the same order queries use.
+#[derive(Debug, Clone)]
+pub struct RetentionConfig {
+    /// Per-stream limits; each message belongs to the first rule it matches
+    pub rules: Vec<RetentionRule>,
+    /// Age limit for messages that match no rule
+    pub default_max_age_days: Option<u32>,
+    /// Count limit for messages that match no rule
+    pub default_max_count: Option<usize>,
+    /// Keep the space used by the database under this many bytes
+    pub max_size_bytes: Option<u64>,
+    /// Time between cleanup runs
+    pub interval: Duration,
+    /// How long deleted rows stay in the trash before they are purged
+    pub trash_grace: Duration,
+    /// Where rows are exported before they are deleted, if anywhere
+    pub archive: Option<Arc<Archive>>,
+}
+
+/// A retention limit for the messages selected by its matchers, written as
+/// `facility=auth,authpriv;max-age-days=365`. Values within a field are
+/// alternatives, fields must all match. `hostname` and `app_name` accept `*`
+/// wildcards and match case-insensitively.
+#[derive(Debug, Clone)]
+pub struct RetentionRule {
+    spec: String,
+    facilities: Vec<u8>,
+    severities: Vec<u8>,
+    hostnames: Vec<String>,
+    app_names: Vec<String>,
+    pub max_age_days: Option<u32>,
+    pub max_count: Option<usize>,
+}
+
+impl RetentionRule {
+    /// The messages this rule matches
+    fn filter(&self) -> LogFilter {
+        let mut filter = LogFilter::All;
+        if !self.facilities.is_empty() {
+            filter = filter.and(LogFilter::Facility(self.facilities.clone()));
+        }
+        if !self.severities.is_empty() {
+            filter = filter.and(LogFilter::Severity(self.severities.clone()));
+        }
+        if !self.hostnames.is_empty() {
+            filter = filter.and(LogFilter::Hostname(self.hostnames.clone()));
+        }
+        if !self.app_names.is_empty() {
+            filter = filter.and(LogFilter::AppName(self.app_names.clone()));
+        }
+        filter
+    }
+}
+
+impl fmt::Display for RetentionRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.spec)
+    }
+}
+
+/// Parses a rule such as `severity=debug;max-age-days=1` or
+/// `hostname=db-*;app_name=postgres;max-count=50000`
+pub fn parse_rule(input: &str) -> Result<RetentionRule, String> {
+    let mut rule = RetentionRule {
+        spec: input.trim().to_string(),
+        facilities: Vec::new(),
+        severities: Vec::new(),
+        hostnames: Vec::new(),
+        app_names: Vec::new(),
+        max_age_days: None,
+        max_count: None,
+    };
+
+    for field in input.split(';').map(str::trim).filter(|field| !field.is_empty()) {
+        let (key, value) = field
+            .split_once('=')
+            .ok_or_else(|| format!("expected key=value, got '{}'", field))?;
+        let values = value.split(',').map(str::trim).filter(|value| !value.is_empty());
+
+        match key.trim() {
+            "facility" => {
+                for value in values {
+                    rule.facilities.push(parse_code(value, &FACILITY_NAMES, "facility")?);
+                }
+            }
+            "severity" => {
+                for value in values {
+                    rule.severities.push(parse_code(value, &SEVERITY_NAMES, "severity")?);
+                }
+            }
+            "hostname" => rule.hostnames.extend(values.map(str::to_string)),
+            "app_name" | "app-name" => rule.app_names.extend(values.map(str::to_string)),
+            "max-age-days" => {
+                rule.max_age_days = Some(value.trim().parse().map_err(|_| format!("invalid max-age-days '{}'", value))?)
+            }
+            "max-count" => {
+                rule.max_count = Some(value.trim().parse().map_err(|_| format!("invalid max-count '{}'", value))?)
+            }
+            other => return Err(format!("unknown retention rule field '{}'", other)),
+        }
+    }
+
+    if rule.facilities.is_empty() && rule.severities.is_empty() && rule.hostnames.is_empty() && rule.app_names.is_empty() {
+        return Err(format!(
+            "retention rule '{}' must match on facility, severity, hostname or app_name",
+            rule.spec
+        ));
+    }
+
+    Ok(rule)
+}
+
+/// Accepts either the numeric code or its conventional name
+fn parse_code(value: &str, names: &[&str], kind: &str) -> Result<u8, String> {
+    if let Ok(code) = value.parse::<u8>() {
+        if usize::from(code) < names.len() {
+            return Ok(code);
+        }
+    }
+
+    names
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(value))
+        .map(|code| code as u8)
+        .ok_or_else(|| format!("unknown {} '{}'", kind, value))
+}
+
+/// The messages governed by one set of limits: a rule, or the default for unmatched messages
+struct Stream {
+    label: String,
+    filter: LogFilter,
+    max_age_days: Option<u32>,
+    max_count: Option<usize>,
+}
+
+impl Stream {
+    /// Builds the streams in rule order. A message only belongs to the first rule
+    /// it matches, so each stream excludes everything claimed by earlier rules.
+    fn from_config(config: &RetentionConfig) -> Vec<Stream> {
+        let mut streams = Vec::with_capacity(config.rules.len() + 1);
+        let mut unclaimed = LogFilter::All;
+
+        for rule in &config.rules {
+            streams.push(Stream {
+                label: rule.to_string(),
+                filter: unclaimed.clone().and(rule.filter()),
+                max_age_days: rule.max_age_days,
+                max_count: rule.max_count,
+            });
+            unclaimed = unclaimed.and(rule.filter().negate());
+        }
+
+        streams.push(Stream {
+            label: "default".to_string(),
+            filter: unclaimed,
+            max_age_days: config.default_max_age_days,
+            max_count: config.default_max_count,
+        });
+        streams
+    }
+}
+
+/// Deletes up to `limit` of the oldest matching rows, archiving them first
+/// when an archive is configured
+async fn remove(store: &dyn LogStore, archive: Option<&Archive>, filter: &LogFilter, limit: u64) -> Result<u64> {
+    let Some(archive) = archive else {
+        return store.delete(filter, Some(limit)).await;
+    };
+
+    let entries = store.oldest(filter, limit as usize).await?;
+    let ids = entries.iter().map(|entry| entry.message.id.clone()).collect();
+    archive.write(entries).await?;
+    store.delete(&LogFilter::Id(ids), None).await
+}
+
+#[derive(Debug, Default)]
+struct CleanupReport {
+    by_age: u64,
+    by_count: u64,
+    by_size: u64,
+    from_trash: u64,
+}
+
+impl CleanupReport {
+    fn total(&self) -> u64 {
+        self.by_age + self.by_count + self.by_size + self.from_trash
+    }
+}
+
+/// Parses a byte size such as `1048576`, `512K`, `500M` or `2G`
+pub fn parse_size(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    let (digits, multiplier) = match input.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&input[..i], 1u64 << 10),
+        Some((i, 'm' | 'M')) => (&input[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&input[..i], 1 << 30),
+        _ => (input, 1),
+    };
+
+    digits
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|value| value.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size '{}', expected e.g. 500M or 2G", input))
+}
+
+pub async fn run_retention(store: Arc<dyn LogStore>, config: RetentionConfig) {
+    let streams = Stream::from_config(&config);
+    info!("Retention cleanup scheduled every {:?}", config.interval);
+    info!("Deleted rows stay in the trash for {:?}", config.trash_grace);
+    if let Some(archive) = &config.archive {
+        info!("Deleted rows are archived to {}", archive.dir().display());
+    }
+    for stream in &streams {
+        info!(
+            "Retention for {}: max age {:?} days, max count {:?}",
+            stream.label, stream.max_age_days, stream.max_count
+        );
+    }
+
+    let mut max_size_bytes = config.max_size_bytes;
+    if max_size_bytes.is_some() {
+        match store.stats().await {
+            Ok(stats) if stats.used_bytes.is_none() => {
+                warn!("--retention-size is not supported by the {} backend and will be ignored", stats.backend);
+                max_size_bytes = None;
+            }
+            Ok(_) => {}
+            Err(e) => error!("Failed to read storage stats: {}", e),
+        }
+    }
+
+    let mut ticker = tokio::time::interval(config.interval);
+    loop {
+        ticker.tick().await;
+
+        match cleanup(store.as_ref(), &config, &streams, max_size_bytes).await {
+            Ok(report) => info!(
+                "Retention cleanup deleted {} rows ({} by age, {} by count, {} by size, {} from the trash)",
+                report.total(),
+                report.by_age,
+                report.by_count,
+                report.by_size,
+                report.from_trash
+            ),
+            Err(e) => error!("Retention cleanup failed: {}", e),
+        }
+    }
+}
+
+async fn cleanup(
+    store: &dyn LogStore,
+    config: &RetentionConfig,
+    streams: &[Stream],
+    max_size_bytes: Option<u64>,
+) -> Result<CleanupReport> {
+    let archive = config.archive.as_deref();
+    let mut report = CleanupReport::default();
+    // Nothing under a legal hold goes, however old it is or whatever the limits say
+    let holds = store.legal_holds().await?;
+
+    // Rows deleted through the API were already chosen to go, so they are not archived
+    let grace = ChronoDuration::from_std(config.trash_grace).unwrap_or(ChronoDuration::MAX);
+    if let Some(cutoff) = Utc::now().checked_sub_signed(grace) {
+        let expired = exempt(LogFilter::TrashedBefore(cutoff), &holds);
+        report.from_trash += store.drop_partitions(&expired, None).await?;
+        loop {
+            let deleted = store.delete(&expired, Some(DELETE_CHUNK)).await?;
+            report.from_trash += deleted;
+            if deleted < DELETE_CHUNK {
+                break;
+            }
+        }
+    }
+
+    for stream in streams {
+        let mut by_age = 0;
+        if let Some(days) = stream.max_age_days {
+            let cutoff = Utc::now() - ChronoDuration::days(i64::from(days));
+            // Trashed rows wait out their grace period, so they can still be restored
+            let expired = stream
+                .filter
+                .clone()
+                .and(LogFilter::Before(cutoff))
+                .and(LogFilter::Trashed(false));
+            let expired = exempt(expired, &holds);
+            if archive.is_none() {
+                by_age += store.drop_partitions(&expired, None).await?;
+            }
+            loop {
+                let deleted = remove(store, archive, &expired, DELETE_CHUNK).await?;
+                by_age += deleted;
+                if deleted < DELETE_CHUNK {
+                    break;
+                }
+            }
+        }
+
+        let mut by_count = 0;
+        if let Some(max_count) = stream.max_count {
+            // Trashed rows leave on their own schedule, so they do not count towards the limit.
+            // Held rows do, which can leave the stream over it
+            let live = stream.filter.clone().and(LogFilter::Trashed(false));
+            let unheld = exempt(live.clone(), &holds);
+            let mut excess = store.count(&live).await?.saturating_sub(max_count as u64);
+            while excess > 0 {
+                let deleted = remove(store, archive, &unheld, excess.min(DELETE_CHUNK)).await?;
+                if deleted == 0 {
+                    break;
+                }
+                by_count += deleted;
+                excess = excess.saturating_sub(deleted);
+            }
+        }
+
+        if by_age + by_count > 0 {
+            info!(
+                "Retention for {} deleted {} rows by age, {} by count",
+                stream.label, by_age, by_count
+            );
+        }
+        report.by_age += by_age;
+        report.by_count += by_count;
+    }
+
+    if let Some(max_size) = max_size_bytes {
+        let unheld = exempt(LogFilter::Trashed(false), &holds);
+        loop {
+            let stats = store.stats().await?;
+            if stats.used_bytes.is_none_or(|used| used <= max_size) {
+                break;
+            }
+            // Whole days go first, but the newest partition is trimmed row by row
+            // rather than emptied
+            let deleted = if archive.is_none() && stats.partitions.is_some_and(|partitions| partitions > 1) {
+                store.drop_partitions(&unheld, Some(1)).await?
+            } else {
+                remove(store, archive, &unheld, DELETE_CHUNK / 5).await?
+            };
+            if deleted == 0 {
+                break;
+            }
+            report.by_size += deleted;
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{
+        testing::{day, for_each_backend, messages, Backend},
+        GroupBy, HoldCriteria, Resolution, TimeSeriesQuery,
+    };
+    use serde_json::{json, Value};
+
+    fn config(trash_grace: Duration) -> RetentionConfig {
+        RetentionConfig {
+            rules: Vec::new(),
+            default_max_age_days: None,
+            default_max_count: None,
+            max_size_bytes: None,
+            interval: Duration::from_secs(60),
+            trash_grace,
+            archive: None,
+        }
+    }
+
+    async fn run(store: &dyn LogStore, config: &RetentionConfig) -> CleanupReport {
+        cleanup(store, config, &Stream::from_config(config), config.max_size_bytes).await.unwrap()
+    }
+
+    /// Stored and all-time counts, and the hourly rollup by facility
+    async fn totals(store: &dyn LogStore) -> (Value, Value, Value) {
+        let counts = store.counts().await.unwrap();
+        let query = TimeSeriesQuery {
+            resolution: Resolution::Hour,
+            since: day(-1),
+            until: day(3),
+            group_by: Some(GroupBy::Facility),
+            filter: LogFilter::All,
+        };
+        let points = store.timeseries(&query).await.unwrap();
+        (
+            serde_json::to_value(counts.stored).unwrap(),
+            serde_json::to_value(counts.all_time).unwrap(),
+            serde_json::to_value(points).unwrap(),
+        )
+    }
+
+    /// Four messages on the first day, and two flagged `auth` ones from another
+    /// source on the next, which fill a partition of their own
+    async fn fill(store: &dyn LogStore) {
+        let mut batch = messages(day(0), 4);
+        for mut message in messages(day(1), 2) {
+            message.facility = 4;
+            message.source_ip = "192.0.2.2".to_string();
+            message.parse_error = Some("no timestamp".to_string());
+            batch.push(message);
+        }
+        store.insert_batch(&batch).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn counts_survive_trash_restore_and_purge() {
+        for_each_backend(async |store, backend| {
+            fill(store).await;
+            let (stored, all_time, rollup) = totals(store).await;
+            assert_eq!(stored["total_messages"], json!(6));
+            assert_eq!(stored["flagged_messages"], json!(2));
+            assert_eq!(stored["messages_per_facility"], json!({"1": 4, "4": 2}));
+            assert_eq!(all_time, stored);
+
+            let auth = LogFilter::Facility(vec![4]);
+            assert_eq!(store.trash(&auth, None).await.unwrap(), 2);
+            let (trashed, trashed_all_time, trashed_rollup) = totals(store).await;
+            assert_eq!(trashed["total_messages"], json!(4));
+            assert_eq!(trashed["flagged_messages"], json!(0));
+            assert_eq!(trashed["messages_per_facility"], json!({"1": 4}));
+            assert_eq!(trashed["messages_per_source"], json!({"192.0.2.1": 4}));
+            assert_eq!(trashed_all_time, all_time);
+            assert_eq!(trashed_rollup, rollup);
+            assert_eq!(store.count(&LogFilter::Trashed(true)).await.unwrap(), 2);
+            assert_eq!(store.stats().await.unwrap().trashed_messages, 2);
+
+            // Restoring is the exact reverse, and trashing twice counts once
+            assert_eq!(store.trash(&auth, None).await.unwrap(), 0);
+            assert_eq!(store.restore(&LogFilter::Trashed(true)).await.unwrap(), 2);
+            assert_eq!(totals(store).await, (stored, all_time.clone(), rollup.clone()));
+
+            // Nothing is purged within the grace period
+            store.trash(&auth, None).await.unwrap();
+            assert_eq!(run(store, &config(Duration::from_secs(3600))).await.from_trash, 0);
+            assert_eq!(store.count(&LogFilter::Trashed(true)).await.unwrap(), 2);
+
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            assert_eq!(run(store, &config(Duration::ZERO)).await.from_trash, 2);
+            assert_eq!(store.count(&LogFilter::Trashed(true)).await.unwrap(), 0);
+            assert_eq!(store.count(&LogFilter::All).await.unwrap(), 4);
+            assert_eq!(store.stats().await.unwrap().trashed_messages, 0);
+            let (purged, purged_all_time, purged_rollup) = totals(store).await;
+            assert_eq!(purged, trashed);
+            assert_eq!(purged_all_time, all_time);
+            match backend {
+                // Its series is counted from the messages it still holds
+                Backend::Memory => assert_eq!(
+                    purged_rollup,
+                    json!([{"bucket": "2024-06-01T00:00:00Z", "group": "1", "count": 4}])
+                ),
+                _ => assert_eq!(purged_rollup, rollup),
+            }
+            if backend == Backend::Partitioned {
+                // The trashed day went by dropping its whole partition
+                assert_eq!(store.stats().await.unwrap().partitions, Some(1));
+            }
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn age_limits_leave_the_trash_to_its_grace_period() {
+        for_each_backend(async |store, _| {
+            fill(store).await;
+            let auth = LogFilter::Facility(vec![4]);
+            store.trash(&auth, None).await.unwrap();
+
+            let mut config = config(Duration::from_secs(3600));
+            config.default_max_age_days = Some(1);
+            let report = run(store, &config).await;
+            assert_eq!((report.by_age, report.from_trash), (4, 0));
+
+            assert_eq!(store.restore(&LogFilter::Trashed(true)).await.unwrap(), 2);
+            assert_eq!(store.count(&LogFilter::All).await.unwrap(), 2);
+            assert_eq!(store.count(&auth).await.unwrap(), 2);
+        })
+        .await;
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("1048576"), Ok(1048576));
+        assert_eq!(parse_size("512K"), Ok(512 << 10));
+        assert_eq!(parse_size(" 500m "), Ok(500 << 20));
+        assert_eq!(parse_size("2G"), Ok(2 << 30));
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("0G"), Ok(0));
+        assert_eq!(parse_size("18446744073709551615"), Ok(u64::MAX));
+
+        for bad in ["", "G", "2T", "2GB", "-1", "1.5G", "18446744073709551616", "17179869184G"] {
+            assert_eq!(
+                parse_size(bad),
+                Err(format!("invalid size '{}', expected e.g. 500M or 2G", bad.trim())),
+                "{bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rule = parse_rule("facility=auth,10; severity=debug;max-age-days=30").unwrap();
+        assert_eq!(rule.to_string(), "facility=auth,10; severity=debug;max-age-days=30");
+        assert_eq!((rule.facilities, rule.severities), (vec![4, 10], vec![7]));
+        assert_eq!((rule.max_age_days, rule.max_count), (Some(30), None));
+
+        let rule = parse_rule("hostname=db-*,web;app-name=postgres;max-count=0").unwrap();
+        assert_eq!(rule.hostnames, vec!["db-*", "web"]);
+        assert_eq!(rule.app_names, vec!["postgres"]);
+        assert_eq!(rule.max_count, Some(0));
+        assert!(matches!(rule.filter(), LogFilter::And(filters) if filters.len() == 2));
+
+        for (bad, error) in [
+            ("max-age-days=1", "retention rule 'max-age-days=1' must match on facility, severity, hostname or app_name"),
+            ("facility=24", "unknown facility '24'"),
+            ("severity=loud", "unknown severity 'loud'"),
+            ("facility=auth;max-age-days=-1", "invalid max-age-days '-1'"),
+            ("facility=auth;max-age-days=4294967296", "invalid max-age-days '4294967296'"),
+            ("facility=auth;max-count=1k", "invalid max-count '1k'"),
+            ("facility=auth;ttl=1", "unknown retention rule field 'ttl'"),
+            ("facility", "expected key=value, got 'facility'"),
+        ] {
+            assert_eq!(parse_rule(bad).map(|rule| rule.to_string()), Err(error.to_string()), "{bad}");
+        }
+    }
+
+    #[tokio::test]
+    async fn retention_skips_held_messages() {
+        for_each_backend(async |store, backend| {
+            // Three hosts over three days
+            let mut batch = Vec::new();
+            for offset in 0..3 {
+                for host in ["web", "db", "mail"] {
+                    let mut message = messages(day(offset), 1).remove(0);
+                    message.hostname = Some(host.to_string());
+                    batch.push(message);
+                }
+            }
+            store.insert_batch(&batch).await.unwrap();
+            let held = HoldCriteria {
+                hostname: vec!["db".to_string()],
+                ..HoldCriteria::default()
+            };
+            store.add_legal_hold("audit", "legal", &held).await.unwrap();
+            let db = LogFilter::Hostname(vec!["db".to_string()]);
+
+            // Everything is past its age, and the mail stream is over its count
+            let mut config = config(Duration::ZERO);
+            config.rules = vec![parse_rule("hostname=mail;max-count=1").unwrap()];
+            config.default_max_age_days = Some(1);
+            let report = run(store, &config).await;
+            assert_eq!((report.by_age, report.by_count), (3, 2));
+            assert_eq!(store.count(&db).await.unwrap(), 3);
+            assert_eq!(store.count(&LogFilter::All).await.unwrap(), 4);
+
+            // Nor does the trash purge take them
+            assert_eq!(store.trash(&LogFilter::All, None).await.unwrap(), 4);
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            assert_eq!(run(store, &config).await.from_trash, 1);
+            assert_eq!(store.count(&db.clone().and(LogFilter::Trashed(true))).await.unwrap(), 3);
+            assert_eq!(store.count(&LogFilter::All).await.unwrap(), 3);
+
+            if backend == Backend::Partitioned {
+                // No partition can be dropped whole while it holds a protected message
+                assert_eq!(store.stats().await.unwrap().partitions, Some(3));
+            }
+        })
+        .await;
+    }
+}
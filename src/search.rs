@@ -0,0 +1,345 @@
+/// Characters wrapped around matched terms in search snippets, swapped for
+/// `<mark>` tags once the rest of the excerpt has been HTML-escaped
+pub const MATCH_START: char = '\u{2}';
+pub const MATCH_END: char = '\u{3}';
+
+/// Words of context shown around the first match in a snippet
+pub const SNIPPET_WORDS: i32 = 32;
+
+/// A word or `"quoted phrase"` to look for, possibly as a prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchTerm {
+    pub text: String,
+    pub prefix: bool,
+    /// Matching messages are excluded
+    pub negated: bool,
+}
+
+/// A parsed full-text search: any group may match, and every term in a group must
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchQuery {
+    pub groups: Vec<Vec<SearchTerm>>,
+}
+
+impl SearchQuery {
+    /// Parses a user search, which can never be a syntax error.
+    ///
+    /// Words and `"quoted phrases"` must all match unless joined by `OR`; `NOT`
+    /// excludes the term after it and a trailing `*` makes a prefix search, as in
+    /// `"connection refused" OR timeout* NOT ssh`. Everything else is matched
+    /// literally, so IP addresses and paths need no quoting.
+    pub fn parse(input: &str) -> Option<SearchQuery> {
+        let mut groups: Vec<Vec<SearchTerm>> = vec![Vec::new()];
+        let mut pending_operator: Option<&'static str> = None;
+        let mut chars = input.chars().peekable();
+
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+            let (text, quoted) = match chars.peek() {
+                None => break,
+                Some('"') => {
+                    chars.next();
+                    let phrase: String = chars.by_ref().take_while(|&c| c != '"').collect();
+                    (phrase, true)
+                }
+                Some(_) => {
+                    let mut word = String::new();
+                    while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                        word.push(c);
+                    }
+                    (word, false)
+                }
+            };
+
+            if !quoted {
+                let operator = match text.as_str() {
+                    "AND" => Some("AND"),
+                    "OR" => Some("OR"),
+                    "NOT" => Some("NOT"),
+                    _ => None,
+                };
+                if operator.is_some() {
+                    // An operator needs a term on both sides; a leading one is ignored, and so
+                    // is NOT right after OR, as a group must start with something to match
+                    if groups.iter().any(|group| !group.is_empty()) && pending_operator != Some("OR") {
+                        pending_operator = operator;
+                    }
+                    continue;
+                }
+            }
+
+            let (text, prefix) = if quoted {
+                (text, chars.next_if_eq(&'*').is_some())
+            } else {
+                match text.strip_suffix('*') {
+                    Some(stem) if !stem.is_empty() => (stem.to_string(), true),
+                    _ => (text, false),
+                }
+            };
+            if text.trim().is_empty() {
+                continue;
+            }
+
+            let operator = pending_operator.take();
+            if operator == Some("OR") {
+                groups.push(Vec::new());
+            }
+            let group = groups.last_mut().expect("there is always a group");
+            group.push(SearchTerm { text, prefix, negated: operator == Some("NOT") });
+        }
+
+        groups.retain(|group| !group.is_empty());
+        if groups.is_empty() {
+            None
+        } else {
+            Some(SearchQuery { groups })
+        }
+    }
+
+    /// The query in SQLite FTS5 syntax, with every term quoted
+    pub fn to_fts5(&self) -> String {
+        self.render(" AND ", " NOT ", " OR ", |term| {
+            let mut quoted = format!("\"{}\"", term.text.replace('"', "\"\""));
+            if term.prefix {
+                quoted.push('*');
+            }
+            quoted
+        })
+    }
+
+    /// The query in PostgreSQL `to_tsquery` syntax. Each term is a quoted lexeme,
+    /// which `to_tsquery` splits with the same parser used to build the index.
+    pub fn to_tsquery(&self) -> String {
+        self.render(" & ", " & !", " | ", |term| {
+            let mut quoted = format!("'{}'", term.text.replace('\\', "\\\\").replace('\'', "''"));
+            if term.prefix {
+                quoted.push_str(":*");
+            }
+            quoted
+        })
+    }
+
+    /// Whether any of the texts matches, tokenized the way FTS5 does it: runs of
+    /// letters and digits, compared case-insensitively. A phrase must appear
+    /// within a single text.
+    pub fn matches(&self, texts: &[&str]) -> bool {
+        let documents: Vec<Vec<Token>> = texts.iter().map(|text| tokenize(text)).collect();
+        self.groups.iter().any(|group| {
+            group.iter().all(|term| {
+                let found = documents.iter().any(|document| !term.positions(document).is_empty());
+                found != term.negated
+            })
+        })
+    }
+
+    /// An excerpt of `text` around the first match, with matched terms between
+    /// `MATCH_START` and `MATCH_END`, like the snippets the databases produce
+    pub fn snippet(&self, text: &str) -> String {
+        let document = tokenize(text);
+        // Token ranges of every match, merged where they overlap
+        let mut spans: Vec<(usize, usize)> = Vec::new();
+        for term in self.groups.iter().flatten().filter(|term| !term.negated) {
+            let length = tokenize(&term.text).len();
+            spans.extend(term.positions(&document).into_iter().map(|start| (start, start + length)));
+        }
+        spans.sort_unstable();
+        spans.dedup_by(|next, merged| {
+            let overlaps = next.0 < merged.1;
+            if overlaps {
+                merged.1 = merged.1.max(next.1);
+            }
+            overlaps
+        });
+
+        let words = SNIPPET_WORDS as usize;
+        let first = spans.first().map_or(0, |span| span.0);
+        let start = first.saturating_sub(words / 4).min(document.len().saturating_sub(words));
+        let end = (start + words).min(document.len());
+        let from = if start > 0 { document[start].start } else { 0 };
+        let to = if end < document.len() { document[end - 1].end } else { text.len() };
+
+        let mut snippet = String::with_capacity(to - from + 16);
+        if start > 0 {
+            snippet.push('…');
+        }
+        let mut copied = from;
+        for (first, last) in spans.into_iter().filter(|span| span.0 < end).map(|span| (span.0.max(start), span.1.min(end) - 1)) {
+            snippet.push_str(&text[copied..document[first].start]);
+            snippet.push(MATCH_START);
+            snippet.push_str(&text[document[first].start..document[last].end]);
+            snippet.push(MATCH_END);
+            copied = document[last].end;
+        }
+        snippet.push_str(&text[copied..to]);
+        if end < document.len() {
+            snippet.push('…');
+        }
+        snippet
+    }
+
+    fn render(&self, and: &str, and_not: &str, or: &str, term: impl Fn(&SearchTerm) -> String) -> String {
+        let groups: Vec<String> = self
+            .groups
+            .iter()
+            .map(|group| {
+                let mut rendered = String::new();
+                for (index, search_term) in group.iter().enumerate() {
+                    if index > 0 {
+                        rendered.push_str(if search_term.negated { and_not } else { and });
+                    }
+                    rendered.push_str(&term(search_term));
+                }
+                if self.groups.len() > 1 && group.len() > 1 {
+                    format!("({})", rendered)
+                } else {
+                    rendered
+                }
+            })
+            .collect();
+
+        groups.join(or)
+    }
+}
+
+impl SearchTerm {
+    /// Token positions in `document` where the term starts
+    fn positions(&self, document: &[Token]) -> Vec<usize> {
+        let words: Vec<String> = tokenize(&self.text).into_iter().map(|token| token.word).collect();
+        let Some((last, leading)) = words.split_last() else {
+            return Vec::new();
+        };
+        if document.len() < words.len() {
+            return Vec::new();
+        }
+
+        (0..=document.len() - words.len())
+            .filter(|&start| {
+                let window = &document[start..start + words.len()];
+                leading.iter().zip(window).all(|(word, token)| token.word == *word)
+                    && match self.prefix {
+                        true => window[leading.len()].word.starts_with(last.as_str()),
+                        false => window[leading.len()].word == *last,
+                    }
+            })
+            .collect()
+    }
+}
+
+/// A word of a searched text and where it is, in bytes
+struct Token {
+    word: String,
+    start: usize,
+    end: usize,
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (index, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(index),
+            (false, Some(from)) => {
+                tokens.push(Token { word: text[from..index].to_lowercase(), start: from, end: index });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+/// Escapes a snippet for HTML, turning the match markers into `<mark>` tags
+pub fn highlight(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len() + 16);
+    for c in snippet.chars() {
+        match c {
+            MATCH_START => html.push_str("<mark>"),
+            MATCH_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fts5(input: &str) -> Option<String> {
+        SearchQuery::parse(input).map(|query| query.to_fts5())
+    }
+
+    #[test]
+    fn quotes_every_term() {
+        assert_eq!(fts5("disk full").as_deref(), Some(r#""disk" AND "full""#));
+        assert_eq!(fts5(r#""connection refused" OR timeout"#).as_deref(), Some(r#""connection refused" OR "timeout""#));
+        // Addresses and paths are matched literally
+        assert_eq!(fts5("10.0.0.1 /var/log").as_deref(), Some(r#""10.0.0.1" AND "/var/log""#));
+    }
+
+    #[test]
+    fn parses_prefix_searches() {
+        assert_eq!(fts5("time* out").as_deref(), Some(r#""time"* AND "out""#));
+        assert_eq!(fts5(r#""connection ref"*"#).as_deref(), Some(r#""connection ref"*"#));
+        // A lone star is a word of its own, and only a trailing star makes a prefix
+        assert_eq!(fts5("*").as_deref(), Some(r#""*""#));
+        assert_eq!(fts5("*ssh").as_deref(), Some(r#""*ssh""#));
+    }
+
+    #[test]
+    fn survives_a_stray_quote() {
+        // An unterminated phrase runs to the end of the input
+        assert_eq!(fts5(r#"disk "full now"#).as_deref(), Some(r#""disk" AND "full now""#));
+        // A quote inside a word is escaped rather than ending the FTS5 string
+        assert_eq!(fts5(r#"say"hi"#).as_deref(), Some(r#""say""hi""#));
+        assert_eq!(fts5(r#"""#), None);
+    }
+
+    #[test]
+    fn keeps_fts5_syntax_out_of_terms() {
+        // FTS5 operators and column filters only ever reach it as quoted strings
+        assert_eq!(fts5("NEAR(disk full)").as_deref(), Some(r#""NEAR(disk" AND "full)""#));
+        assert_eq!(fts5("-ssh message:disk").as_deref(), Some(r#""-ssh" AND "message:disk""#));
+        assert_eq!(fts5("^start +more").as_deref(), Some(r#""^start" AND "+more""#));
+        // Only upper-case operators between terms count; the rest are words
+        assert_eq!(fts5("disk or full").as_deref(), Some(r#""disk" AND "or" AND "full""#));
+        assert_eq!(fts5(r#"disk "OR" full"#).as_deref(), Some(r#""disk" AND "OR" AND "full""#));
+    }
+
+    #[test]
+    fn applies_boolean_operators() {
+        assert_eq!(fts5("a b OR c NOT d").as_deref(), Some(r#"("a" AND "b") OR ("c" NOT "d")"#));
+        assert_eq!(fts5("a AND b").as_deref(), Some(r#""a" AND "b""#));
+        // Operators without a term on both sides are dropped
+        assert_eq!(fts5("OR a").as_deref(), Some(r#""a""#));
+        assert_eq!(fts5("NOT a").as_deref(), Some(r#""a""#));
+        assert_eq!(fts5("a OR").as_deref(), Some(r#""a""#));
+        assert_eq!(fts5("a OR NOT b").as_deref(), Some(r#""a" OR "b""#));
+        assert_eq!(fts5("a OR OR b").as_deref(), Some(r#""a" OR "b""#));
+    }
+
+    #[test]
+    fn ignores_empty_searches() {
+        assert_eq!(fts5(""), None);
+        assert_eq!(fts5("   \t"), None);
+        assert_eq!(fts5(r#""" "  ""#), None);
+        assert_eq!(fts5("AND OR NOT"), None);
+    }
+
+    #[test]
+    fn matches_like_fts5() {
+        let query = SearchQuery::parse(r#""disk full" OR time* NOT ssh"#).unwrap();
+        assert!(query.matches(&["Disk full on /dev/sda"]));
+        assert!(!query.matches(&["disk is full"]));
+        assert!(query.matches(&["connection timed out", "cron"]));
+        // Words match whole, so a prefix of one is no match without `*`
+        assert!(query.matches(&["timeout", "sshd"]));
+        assert!(!query.matches(&["timeout", "ssh"]));
+    }
+}
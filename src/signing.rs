@@ -0,0 +1,837 @@
+use crate::store::LogStore;
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Utc};
+use dsa::{pkcs8::DecodePublicKey, signature::hazmat::PrehashVerifier, BigUint, Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use syslog_parser::SyslogMessage;
+use tracing::{info, warn};
+
+/// SD-ID of an RFC 5848 signature block
+const SIGNATURE_BLOCK: &str = "ssign";
+
+/// SD-ID of an RFC 5848 certificate block
+const CERTIFICATE_BLOCK: &str = "ssign-cert";
+
+/// Recent messages remembered while waiting for the signature block that covers them
+const MAX_PENDING: usize = 100_000;
+
+/// Whether a stored message is covered by a valid RFC 5848 signature
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SignatureStatus {
+    /// Listed in a signature block whose signature checks out, or a valid
+    /// signature or certificate block itself
+    Verified,
+    /// Listed in, or itself, a signature or certificate block whose signature
+    /// does not match any configured key
+    Invalid,
+    /// From a sender that does not sign, or received before signing was configured
+    Unsigned,
+    /// From a signing sender, but no signature block listed it
+    MissingFromGroup,
+}
+
+impl SignatureStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SignatureStatus::Verified => "verified",
+            SignatureStatus::Invalid => "invalid",
+            SignatureStatus::Unsigned => "unsigned",
+            SignatureStatus::MissingFromGroup => "missing-from-group",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<SignatureStatus> {
+        match status {
+            "verified" => Some(SignatureStatus::Verified),
+            "invalid" => Some(SignatureStatus::Invalid),
+            "unsigned" => Some(SignatureStatus::Unsigned),
+            "missing-from-group" => Some(SignatureStatus::MissingFromGroup),
+            _ => None,
+        }
+    }
+}
+
+/// A status to record for a stored message, with gaps or problems found while checking it
+#[derive(Debug, Clone)]
+pub struct SignatureUpdate {
+    pub id: String,
+    /// Lets partitioned storage find the message without searching every partition
+    pub timestamp: DateTime<Utc>,
+    pub status: SignatureStatus,
+    pub detail: Option<String>,
+}
+
+/// Hash algorithm named by the second digit pair of VER
+#[derive(Debug, Clone, Copy)]
+enum HashAlgorithm {
+    Sha1,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlgorithm::Sha1 => Sha1::digest(data).to_vec(),
+            HashAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+        }
+    }
+}
+
+/// Messages from one sender are matched against that sender's signature blocks
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Sender {
+    source_ip: String,
+    hostname: Option<String>,
+}
+
+impl Sender {
+    fn of(message: &SyslogMessage) -> Sender {
+        Sender {
+            source_ip: message.source_ip.clone(),
+            hostname: message.hostname.clone(),
+        }
+    }
+}
+
+/// Reboot session, signature group and signature priority, which together
+/// identify one sequence of signature blocks
+type GroupKey = (String, String, String);
+
+struct Pending {
+    id: String,
+    timestamp: DateTime<Utc>,
+    sender: Sender,
+    received: Instant,
+    hashes: [Vec<u8>; 2],
+}
+
+#[derive(Default)]
+struct SenderState {
+    /// Tracking sequence at the last valid signature block from this sender,
+    /// so messages received before it can be told apart
+    last_block: Option<u64>,
+    /// Last global block counter and next expected message number per group
+    groups: HashMap<GroupKey, (u64, u64)>,
+    /// Certificate payload fragments received so far, by octet offset
+    certificates: HashMap<GroupKey, (usize, BTreeMap<usize, String>)>,
+}
+
+#[derive(Default)]
+struct State {
+    next: u64,
+    pending: BTreeMap<u64, Pending>,
+    by_hash: HashMap<Vec<u8>, Vec<u64>>,
+    senders: HashMap<Sender, SenderState>,
+}
+
+/// Checks RFC 5848 signature and certificate blocks against configured DSA
+/// public keys and matches the message hashes they list to received messages
+pub struct SignatureVerifier {
+    keys: Vec<VerifyingKey>,
+    /// How long a message may wait for a signature block to list it
+    window: Duration,
+    state: Mutex<State>,
+}
+
+impl fmt::Debug for SignatureVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SignatureVerifier")
+            .field("keys", &self.keys.len())
+            .field("window", &self.window)
+            .finish()
+    }
+}
+
+impl SignatureVerifier {
+    /// Loads DSA public keys from PEM or DER SubjectPublicKeyInfo files
+    pub fn load(paths: &[PathBuf], window: Duration) -> Result<SignatureVerifier> {
+        let keys = paths
+            .iter()
+            .map(|path| {
+                let bytes = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+                let key = match std::str::from_utf8(&bytes) {
+                    Ok(pem) if pem.trim_start().starts_with("-----") => VerifyingKey::from_public_key_pem(pem),
+                    _ => VerifyingKey::from_public_key_der(&bytes),
+                };
+                key.map_err(|e| anyhow::anyhow!("{} is not a DSA public key: {}", path.display(), e))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        info!("Verifying RFC 5848 signatures with {} public keys", keys.len());
+        Ok(SignatureVerifier {
+            keys,
+            window,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    /// Records newly stored messages and checks any signature or certificate
+    /// blocks among them, returning the statuses that changed
+    pub fn observe(&self, messages: &[SyslogMessage]) -> Vec<SignatureUpdate> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let mut updates = Vec::new();
+
+        for message in messages {
+            let (id, params) = match message.structured_data.as_deref().and_then(find_block) {
+                Some(block) => block,
+                None => {
+                    state.track(message, now);
+                    continue;
+                }
+            };
+
+            let (status, detail) = match id {
+                SIGNATURE_BLOCK => self.signature_block(&mut state, message, &params, &mut updates),
+                _ => self.certificate_block(&mut state, message, &params),
+            }
+            .unwrap_or_else(|e| (SignatureStatus::Invalid, Some(e.to_string())));
+            updates.push(SignatureUpdate {
+                id: message.id.clone(),
+                timestamp: message.timestamp,
+                status,
+                detail,
+            });
+        }
+
+        while state.pending.len() > MAX_PENDING {
+            state.expire_first(&mut updates);
+        }
+        state.expire(now, self.window, &mut updates);
+        updates
+    }
+
+    /// Resolves messages that waited longer than the window, for when no new
+    /// messages arrive to trigger it
+    pub fn expire(&self) -> Vec<SignatureUpdate> {
+        let mut updates = Vec::new();
+        self.state.lock().unwrap().expire(Instant::now(), self.window, &mut updates);
+        updates
+    }
+
+    fn signature_block(
+        &self,
+        state: &mut State,
+        message: &SyslogMessage,
+        params: &HashMap<String, String>,
+        updates: &mut Vec<SignatureUpdate>,
+    ) -> Result<(SignatureStatus, Option<String>)> {
+        let algorithm = version(params)?;
+        let group = group_key(params)?;
+        let gbc: u64 = number(params, "GBC")?;
+        let fmn: u64 = number(params, "FMN")?;
+        let count: u64 = number(params, "CNT")?;
+        let hashes = param(params, "HB")?
+            .split(' ')
+            .map(|hash| BASE64.decode(hash).context("HB holds a hash that is not base64"))
+            .collect::<Result<Vec<_>>>()?;
+        if hashes.len() as u64 != count {
+            bail!("CNT is {} but HB lists {} hashes", count, hashes.len());
+        }
+
+        // A forged block must not claim messages from the genuine one still to come
+        if !self.verify(&message.raw_message, algorithm, param(params, "SIGN")?) {
+            return Ok((
+                SignatureStatus::Invalid,
+                Some("signature does not match any configured key".to_string()),
+            ));
+        }
+
+        let sender = Sender::of(message);
+        let mut unmatched = 0;
+        for hash in &hashes {
+            match state.take(&sender, hash) {
+                Some(pending) => updates.push(SignatureUpdate {
+                    id: pending.id,
+                    timestamp: pending.timestamp,
+                    status: SignatureStatus::Verified,
+                    detail: None,
+                }),
+                None => unmatched += 1,
+            }
+        }
+
+        let mut problems = Vec::new();
+        let next = state.next;
+        let sender_state = state.senders.entry(sender).or_default();
+        sender_state.last_block = Some(next);
+        // RFC 5848 allows blocks to be sent more than once, so older counters are not gaps
+        // Counters come off the wire, so none of this arithmetic may overflow
+        if let Some(&(last_gbc, next_fmn)) = sender_state.groups.get(&group).filter(|(last_gbc, _)| gbc > *last_gbc) {
+            if gbc > last_gbc.saturating_add(1) {
+                problems.push(format!(
+                    "signature blocks {} to {} were not received",
+                    last_gbc.saturating_add(1),
+                    gbc.saturating_sub(1)
+                ));
+            }
+            if fmn > next_fmn {
+                problems.push(format!(
+                    "messages {} to {} are not covered by any signature block",
+                    next_fmn,
+                    fmn.saturating_sub(1)
+                ));
+            }
+        }
+        let (last_gbc, next_fmn) = sender_state.groups.entry(group).or_insert((0, 0));
+        *last_gbc = (*last_gbc).max(gbc);
+        *next_fmn = (*next_fmn).max(fmn.saturating_add(count));
+
+        if unmatched > 0 {
+            problems.push(format!("{} of {} signed messages were not received", unmatched, count));
+        }
+        Ok((SignatureStatus::Verified, (!problems.is_empty()).then(|| problems.join("; "))))
+    }
+
+    /// Checks a certificate block's signature and reassembles the payload it
+    /// carries, noting whether the announced key is one that is configured
+    fn certificate_block(
+        &self,
+        state: &mut State,
+        message: &SyslogMessage,
+        params: &HashMap<String, String>,
+    ) -> Result<(SignatureStatus, Option<String>)> {
+        let algorithm = version(params)?;
+        if !self.verify(&message.raw_message, algorithm, param(params, "SIGN")?) {
+            return Ok((
+                SignatureStatus::Invalid,
+                Some("signature does not match any configured key".to_string()),
+            ));
+        }
+
+        let total: usize = number(params, "TPBL")?;
+        let index: usize = number(params, "INDEX")?;
+        let fragment = param(params, "FRAG")?;
+        if index == 0
+            || fragment.len() != number::<usize>(params, "FLEN")?
+            || (index - 1).checked_add(fragment.len()).is_none_or(|end| end > total)
+        {
+            bail!("fragment at INDEX {} does not fit the {}-octet payload", index, total);
+        }
+
+        let fragments = state
+            .senders
+            .entry(Sender::of(message))
+            .or_default()
+            .certificates
+            .entry(group_key(params)?)
+            .or_insert_with(|| (total, BTreeMap::new()));
+        if fragments.0 != total {
+            *fragments = (total, BTreeMap::new());
+        }
+        fragments.1.insert(index - 1, fragment.to_string());
+
+        let received: usize = fragments.1.values().map(String::len).sum();
+        if received < total {
+            return Ok((SignatureStatus::Verified, None));
+        }
+        let payload: String = fragments.1.values().map(String::as_str).collect();
+        fragments.1.clear();
+        Ok((SignatureStatus::Verified, Some(self.describe_payload(&payload))))
+    }
+
+    /// Payload blocks are "timestamp key-blob-type key-blob"
+    fn describe_payload(&self, payload: &str) -> String {
+        let mut fields = payload.splitn(3, ' ');
+        let (_, kind, blob) = (fields.next(), fields.next(), fields.next());
+        match (kind, blob.and_then(|blob| BASE64.decode(blob).ok())) {
+            (Some("K"), Some(blob)) => match VerifyingKey::from_public_key_der(&blob) {
+                Ok(key) if self.keys.contains(&key) => "announced public key is configured".to_string(),
+                Ok(_) => {
+                    warn!("A syslog-sign sender announced a public key that is not configured");
+                    "announced public key is not configured".to_string()
+                }
+                Err(_) => "announced public key is not a DSA key".to_string(),
+            },
+            (Some(kind), Some(_)) => format!("announced key blob of type {}, checked against configured keys only", kind),
+            _ => "malformed certificate payload".to_string(),
+        }
+    }
+
+    /// RFC 5848 signs the block with the SIGN value left out. Implementations
+    /// differ on whether the empty parameter stays, so both forms are tried.
+    fn verify(&self, raw_message: &str, algorithm: HashAlgorithm, sign: &str) -> bool {
+        let Some(signature) = BASE64.decode(sign).ok().and_then(|bytes| decode_signature(&bytes)) else {
+            return false;
+        };
+        let Some(start) = raw_message.find(&format!(" SIGN=\"{}\"", sign)) else {
+            return false;
+        };
+        let end = start + " SIGN=\"\"".len() + sign.len();
+
+        [
+            format!("{}{}", &raw_message[..start], &raw_message[end..]),
+            format!("{} SIGN=\"\"{}", &raw_message[..start], &raw_message[end..]),
+        ]
+        .iter()
+        .any(|signed| {
+            let digest = algorithm.digest(signed.as_bytes());
+            self.keys.iter().any(|key| key.verify_prehash(&digest, &signature).is_ok())
+        })
+    }
+}
+
+impl State {
+    fn track(&mut self, message: &SyslogMessage, now: Instant) {
+        let raw = message.raw_message.as_bytes();
+        let hashes = [HashAlgorithm::Sha1.digest(raw), HashAlgorithm::Sha256.digest(raw)];
+
+        let seq = self.next;
+        self.next += 1;
+        for hash in &hashes {
+            self.by_hash.entry(hash.clone()).or_default().push(seq);
+        }
+        self.pending.insert(
+            seq,
+            Pending {
+                id: message.id.clone(),
+                timestamp: message.timestamp,
+                sender: Sender::of(message),
+                received: now,
+                hashes,
+            },
+        );
+    }
+
+    /// The oldest waiting message from `sender` with this hash
+    fn take(&mut self, sender: &Sender, hash: &[u8]) -> Option<Pending> {
+        let seq = *self
+            .by_hash
+            .get(hash)?
+            .iter()
+            .find(|seq| self.pending.get(seq).is_some_and(|pending| &pending.sender == sender))?;
+        self.remove(seq)
+    }
+
+    fn remove(&mut self, seq: u64) -> Option<Pending> {
+        let pending = self.pending.remove(&seq)?;
+        for hash in &pending.hashes {
+            if let Some(seqs) = self.by_hash.get_mut(hash) {
+                seqs.retain(|other| *other != seq);
+                if seqs.is_empty() {
+                    self.by_hash.remove(hash);
+                }
+            }
+        }
+        Some(pending)
+    }
+
+    fn expire(&mut self, now: Instant, window: Duration, updates: &mut Vec<SignatureUpdate>) {
+        while self
+            .pending
+            .first_key_value()
+            .is_some_and(|(_, pending)| now.duration_since(pending.received) >= window)
+        {
+            self.expire_first(updates);
+        }
+    }
+
+    /// Gives up on the oldest waiting message. It stays unsigned unless its
+    /// sender has sent a valid signature block since it arrived.
+    fn expire_first(&mut self, updates: &mut Vec<SignatureUpdate>) {
+        let Some(seq) = self.pending.first_key_value().map(|(seq, _)| *seq) else {
+            return;
+        };
+        let Some(pending) = self.remove(seq) else {
+            return;
+        };
+
+        let signing = self
+            .senders
+            .get(&pending.sender)
+            .and_then(|sender| sender.last_block)
+            .is_some_and(|last_block| last_block > seq);
+        if signing {
+            updates.push(SignatureUpdate {
+                id: pending.id,
+                timestamp: pending.timestamp,
+                status: SignatureStatus::MissingFromGroup,
+                detail: None,
+            });
+        }
+    }
+}
+
+/// Periodically resolves messages whose signature block never came
+pub async fn run_signature_expiry(verifier: Arc<SignatureVerifier>, store: Arc<dyn LogStore>) {
+    let mut interval = tokio::time::interval((verifier.window / 4).max(Duration::from_secs(1)));
+    loop {
+        interval.tick().await;
+        let updates = verifier.expire();
+        if let Err(e) = store.set_signatures(&updates).await {
+            warn!("Failed to record signature statuses: {}", e);
+        }
+    }
+}
+
+/// The parameters of the first signature or certificate block in structured data
+fn find_block(structured_data: &str) -> Option<(&'static str, HashMap<String, String>)> {
+    parse_elements(structured_data)?.into_iter().find_map(|(id, params)| match id.as_str() {
+        SIGNATURE_BLOCK => Some((SIGNATURE_BLOCK, params)),
+        CERTIFICATE_BLOCK => Some((CERTIFICATE_BLOCK, params)),
+        _ => None,
+    })
+}
+
+/// Splits `[id name="value" ...]...` into elements; None if it is malformed
+fn parse_elements(sd: &str) -> Option<Vec<(String, HashMap<String, String>)>> {
+    let mut elements = Vec::new();
+    let mut chars = sd.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '[' {
+            return None;
+        }
+        let id: String = std::iter::from_fn(|| chars.next_if(|&c| c != ' ' && c != ']')).collect();
+
+        let mut params = HashMap::new();
+        loop {
+            match chars.next()? {
+                ']' => break,
+                ' ' => {
+                    let name: String = chars.by_ref().take_while(|&c| c != '=').collect();
+                    if chars.next()? != '"' {
+                        return None;
+                    }
+                    let mut value = String::new();
+                    loop {
+                        match chars.next()? {
+                            '"' => break,
+                            '\\' => value.push(chars.next()?),
+                            c => value.push(c),
+                        }
+                    }
+                    params.insert(name, value);
+                }
+                _ => return None,
+            }
+        }
+        elements.push((id, params));
+    }
+
+    Some(elements)
+}
+
+fn param<'a>(params: &'a HashMap<String, String>, name: &str) -> Result<&'a str> {
+    params
+        .get(name)
+        .map(String::as_str)
+        .with_context(|| format!("block has no {} parameter", name))
+}
+
+fn number<T: std::str::FromStr>(params: &HashMap<String, String>, name: &str) -> Result<T> {
+    param(params, name)?
+        .parse()
+        .ok()
+        .with_context(|| format!("{} is not a number", name))
+}
+
+fn group_key(params: &HashMap<String, String>) -> Result<GroupKey> {
+    Ok((
+        param(params, "RSID")?.to_string(),
+        param(params, "SG")?.to_string(),
+        param(params, "SPRI")?.to_string(),
+    ))
+}
+
+/// VER is the protocol version "01", then the hash algorithm and the signature
+/// scheme, of which only 1 (OpenPGP DSA) is defined
+fn version(params: &HashMap<String, String>) -> Result<HashAlgorithm> {
+    match param(params, "VER")? {
+        "0111" => Ok(HashAlgorithm::Sha1),
+        "0121" => Ok(HashAlgorithm::Sha256),
+        other => bail!("unsupported VER {}", other),
+    }
+}
+
+/// DSA signatures arrive DER encoded, as two OpenPGP MPIs, or as r and s
+/// concatenated, depending on the signer
+fn decode_signature(bytes: &[u8]) -> Option<Signature> {
+    if let Ok(signature) = Signature::try_from(bytes) {
+        return Some(signature);
+    }
+
+    let mpi = |bytes: &[u8]| -> Option<(BigUint, usize)> {
+        let bits = u16::from_be_bytes([*bytes.first()?, *bytes.get(1)?]) as usize;
+        let length = bits.div_ceil(8);
+        Some((BigUint::from_bytes_be(bytes.get(2..2 + length)?), 2 + length))
+    };
+    if let Some((r, used)) = mpi(bytes) {
+        if let Some((s, rest)) = mpi(&bytes[used..]) {
+            if used + rest == bytes.len() {
+                return Signature::from_components(r, s).ok();
+            }
+        }
+    }
+
+    let (r, s) = bytes.split_at(bytes.len() / 2);
+    (bytes.len().is_multiple_of(2) && !r.is_empty())
+        .then(|| Signature::from_components(BigUint::from_bytes_be(r), BigUint::from_bytes_be(s)).ok())
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dsa::{
+        pkcs8::EncodePublicKey,
+        signature::{hazmat::PrehashSigner, SignatureEncoding},
+        Components, SigningKey,
+    };
+
+    /// 1024-bit group shared by the test keys, fixed so signatures are reproducible
+    const P: &str = "b900378cb79143ae92093a80e3ef1f04594016b7a8e967c6958c3aaba26bee8d73f5a4d46fd1e6596232effcd246df2ab970360b516034b011a6b9986a33731c1faa63232817510d9cf3b2a804ef68a1a15cfd44833a90fd8bc5daa989cc960a7ca0c876607703cbf487838f93db94ed4d5a95383aefc6d8c0b0a9b4064bcddd";
+    const Q: &str = "f79d050ffaa05ec0374293a0885e13a28466c350502721b3394d5135";
+    const G: &str = "b5104ce16c2cef83218b7c3dde5e5ab45b6ef5b6751fa0862c2fb481ff41af0a55dbcc55caec508b542fdbc4ea609950580d420619a3967ed1323ab18b73b58843172f2c76d5666c6c2d92790a31d6627236271ff843a548b1edf10fb9b48787a7039d3d1437d679ef9dd472d43ee40de868c902cad4cacb1156ec5db7e409c1";
+    const SIGNER_X: &str = "af22bf5066371aa79b77a2c90b2f674c013b962510815024ed6e437f";
+    const FORGER_X: &str = "0123456789abcdef0123456789abcdef0123456789abcdef01234567";
+
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    fn big(hex: &str) -> BigUint {
+        BigUint::from_bytes_be(&hex::decode(hex).unwrap())
+    }
+
+    fn key(x: &str) -> SigningKey {
+        let components = Components::from_components(big(P), big(Q), big(G)).unwrap();
+        let y = big(G).modpow(&big(x), &big(P));
+        SigningKey::from_components(VerifyingKey::from_components(components, y).unwrap(), big(x)).unwrap()
+    }
+
+    fn verifier() -> SignatureVerifier {
+        SignatureVerifier {
+            keys: vec![key(SIGNER_X).verifying_key().clone()],
+            window: WINDOW,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn received(raw_message: String, structured_data: Option<String>) -> SyslogMessage {
+        SyslogMessage {
+            id: uuid::Uuid::new_v4().to_string(),
+            timestamp: Utc::now(),
+            facility: 1,
+            severity: 6,
+            hostname: Some("signer".to_string()),
+            app_name: Some("app".to_string()),
+            proc_id: None,
+            msg_id: None,
+            structured_data,
+            message: String::new(),
+            raw_message,
+            source_ip: "192.0.2.7".to_string(),
+            parse_error: None,
+            original_bytes: None,
+        }
+    }
+
+    fn plain(text: &str) -> SyslogMessage {
+        received(format!("<14>1 2024-06-01T00:00:00Z signer app - - - {}", text), None)
+    }
+
+    /// Signs `params` as an SD element with the given ID, the way RFC 5848 does:
+    /// over the whole message with the SIGN parameter left out
+    fn signed(sd_id: &str, params: &str, key: &SigningKey) -> SyslogMessage {
+        let header = "<110>1 2024-06-01T00:00:00Z signer syslog - - ";
+        let unsigned = format!("{}[{} {}]", header, sd_id, params);
+        let signature = key.sign_prehash(&Sha1::digest(unsigned.as_bytes())).unwrap();
+        let structured_data = format!("[{} {} SIGN=\"{}\"]", sd_id, params, BASE64.encode(signature.to_vec()));
+        received(format!("{}{}", header, structured_data), Some(structured_data))
+    }
+
+    fn block(key: &SigningKey, gbc: u64, fmn: u64, listed: &[&SyslogMessage]) -> SyslogMessage {
+        let hashes: Vec<String> = listed
+            .iter()
+            .map(|message| BASE64.encode(Sha1::digest(message.raw_message.as_bytes())))
+            .collect();
+        let params = format!(
+            "VER=\"0111\" RSID=\"1\" SG=\"0\" SPRI=\"0\" GBC=\"{}\" FMN=\"{}\" CNT=\"{}\" HB=\"{}\"",
+            gbc,
+            fmn,
+            listed.len(),
+            hashes.join(" ")
+        );
+        signed(SIGNATURE_BLOCK, &params, key)
+    }
+
+    fn status_of(updates: &[SignatureUpdate], message: &SyslogMessage) -> Option<(SignatureStatus, Option<String>)> {
+        updates
+            .iter()
+            .find(|update| update.id == message.id)
+            .map(|update| (update.status, update.detail.clone()))
+    }
+
+    #[test]
+    fn valid_block_verifies_the_messages_it_lists() {
+        let verifier = verifier();
+        let (first, second) = (plain("one"), plain("two"));
+        assert!(verifier.observe(&[first.clone(), second.clone()]).is_empty());
+
+        let block = block(&key(SIGNER_X), 1, 1, &[&first, &second]);
+        let updates = verifier.observe(std::slice::from_ref(&block));
+        assert_eq!(status_of(&updates, &first), Some((SignatureStatus::Verified, None)));
+        assert_eq!(status_of(&updates, &second), Some((SignatureStatus::Verified, None)));
+        assert_eq!(status_of(&updates, &block), Some((SignatureStatus::Verified, None)));
+    }
+
+    #[test]
+    fn tampered_message_is_not_verified_and_expires_missing() {
+        let verifier = verifier();
+        let original = plain("transfer 10 EUR");
+        let tampered = received(original.raw_message.replace("10", "1000"), None);
+        verifier.observe(std::slice::from_ref(&tampered));
+
+        let block = block(&key(SIGNER_X), 1, 1, &[&original]);
+        let updates = verifier.observe(std::slice::from_ref(&block));
+        assert_eq!(status_of(&updates, &tampered), None);
+        assert_eq!(
+            status_of(&updates, &block),
+            Some((SignatureStatus::Verified, Some("1 of 1 signed messages were not received".to_string())))
+        );
+
+        // Its sender signs, so once the window passes it is missing from the group
+        let mut updates = Vec::new();
+        verifier.state.lock().unwrap().expire(Instant::now() + WINDOW, WINDOW, &mut updates);
+        assert_eq!(status_of(&updates, &tampered), Some((SignatureStatus::MissingFromGroup, None)));
+    }
+
+    #[test]
+    fn unsigned_sender_stays_unsigned_on_expiry() {
+        let verifier = verifier();
+        verifier.observe(&[plain("no signer here")]);
+
+        let mut updates = Vec::new();
+        verifier.state.lock().unwrap().expire(Instant::now() + WINDOW, WINDOW, &mut updates);
+        assert!(updates.is_empty());
+        assert!(verifier.state.lock().unwrap().pending.is_empty());
+    }
+
+    #[test]
+    fn invalid_block_leaves_pending_messages_for_the_genuine_one() {
+        let verifier = verifier();
+        let message = plain("genuine");
+        verifier.observe(std::slice::from_ref(&message));
+
+        let forged = block(&key(FORGER_X), 1, 1, &[&message]);
+        let updates = verifier.observe(std::slice::from_ref(&forged));
+        assert_eq!(updates.len(), 1);
+        assert_eq!(
+            status_of(&updates, &forged),
+            Some((SignatureStatus::Invalid, Some("signature does not match any configured key".to_string())))
+        );
+
+        let genuine = block(&key(SIGNER_X), 1, 1, &[&message]);
+        let updates = verifier.observe(&[genuine]);
+        assert_eq!(status_of(&updates, &message), Some((SignatureStatus::Verified, None)));
+    }
+
+    #[test]
+    fn counter_gaps_are_reported() {
+        let verifier = verifier();
+        let messages: Vec<SyslogMessage> = (0..3).map(|i| plain(&format!("message {}", i))).collect();
+        verifier.observe(&messages);
+
+        verifier.observe(&[block(&key(SIGNER_X), 1, 1, &[&messages[0], &messages[1]])]);
+        let late = block(&key(SIGNER_X), 4, 6, &[&messages[2]]);
+        let updates = verifier.observe(std::slice::from_ref(&late));
+        assert_eq!(
+            status_of(&updates, &late),
+            Some((
+                SignatureStatus::Verified,
+                Some("signature blocks 2 to 3 were not received; messages 3 to 5 are not covered by any signature block".to_string())
+            ))
+        );
+        assert_eq!(status_of(&updates, &messages[2]), Some((SignatureStatus::Verified, None)));
+    }
+
+    #[test]
+    fn counters_at_the_limit_do_not_overflow() {
+        let verifier = verifier();
+        let message = plain("edge");
+        verifier.observe(std::slice::from_ref(&message));
+
+        verifier.observe(&[block(&key(SIGNER_X), 1, u64::MAX, &[&message])]);
+        let updates = verifier.observe(&[block(&key(SIGNER_X), u64::MAX, u64::MAX, &[])]);
+        assert_eq!(updates.len(), 1);
+        // The state is still usable afterwards
+        assert!(verifier.expire().is_empty());
+    }
+
+    #[test]
+    fn certificate_fragments_are_reassembled() {
+        let verifier = verifier();
+        let der = key(SIGNER_X).verifying_key().to_public_key_der().unwrap();
+        let payload = format!("2024-06-01T00:00:00Z K {}", BASE64.encode(der.as_bytes()));
+        let (head, tail) = payload.split_at(payload.len() / 2);
+
+        let fragment = |index: usize, fragment: &str| {
+            let params = format!(
+                "VER=\"0111\" RSID=\"1\" SG=\"0\" SPRI=\"0\" TPBL=\"{}\" INDEX=\"{}\" FLEN=\"{}\" FRAG=\"{}\"",
+                payload.len(),
+                index,
+                fragment.len(),
+                fragment
+            );
+            signed(CERTIFICATE_BLOCK, &params, &key(SIGNER_X))
+        };
+        // Out of order, as UDP may deliver them
+        let second = fragment(head.len() + 1, tail);
+        let first = fragment(1, head);
+
+        let updates = verifier.observe(std::slice::from_ref(&second));
+        assert_eq!(status_of(&updates, &second), Some((SignatureStatus::Verified, None)));
+        let updates = verifier.observe(std::slice::from_ref(&first));
+        assert_eq!(
+            status_of(&updates, &first),
+            Some((SignatureStatus::Verified, Some("announced public key is configured".to_string())))
+        );
+    }
+
+    #[test]
+    fn certificate_fragment_past_the_payload_is_rejected() {
+        let verifier = verifier();
+        let params = format!(
+            "VER=\"0111\" RSID=\"1\" SG=\"0\" SPRI=\"0\" TPBL=\"4\" INDEX=\"{}\" FLEN=\"2\" FRAG=\"ab\"",
+            usize::MAX
+        );
+        let fragment = signed(CERTIFICATE_BLOCK, &params, &key(SIGNER_X));
+        let updates = verifier.observe(std::slice::from_ref(&fragment));
+        assert_eq!(status_of(&updates, &fragment).map(|(status, _)| status), Some(SignatureStatus::Invalid));
+    }
+
+    #[test]
+    fn signatures_decode_from_der_mpi_and_raw_forms() {
+        let signature = key(SIGNER_X).sign_prehash(&Sha1::digest(b"payload")).unwrap();
+        let (r, s) = (signature.r().to_bytes_be(), signature.s().to_bytes_be());
+
+        let mpi = |bytes: &[u8]| {
+            let bits = signature_bits(bytes);
+            [&bits.to_be_bytes()[..], bytes].concat()
+        };
+        // r and s padded to the width of q, as fixed-size encodings do
+        let width = big(Q).to_bytes_be().len();
+        let pad = |bytes: &[u8]| [vec![0; width - bytes.len()], bytes.to_vec()].concat();
+
+        for encoded in [signature.to_vec(), [mpi(&r), mpi(&s)].concat(), [pad(&r), pad(&s)].concat()] {
+            let decoded = decode_signature(&encoded).unwrap();
+            assert_eq!((decoded.r(), decoded.s()), (signature.r(), signature.s()));
+        }
+        assert!(decode_signature(&[]).is_none());
+        assert!(decode_signature(&[1, 2, 3]).is_none());
+    }
+
+    fn signature_bits(bytes: &[u8]) -> u16 {
+        (bytes.len() * 8 - bytes[0].leading_zeros() as usize) as u16
+    }
+}
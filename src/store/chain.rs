@@ -0,0 +1,515 @@
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::{
+    sqlite::{SqliteConnection, SqlitePool},
+    Row,
+};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use syslog_parser::SyslogMessage;
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::info;
+
+/// The previous hash of the first message in a chain
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// How the hash chain is signed and how often it is checkpointed
+#[derive(Debug, Clone)]
+pub struct ChainConfig {
+    /// Hex-encoded Ed25519 secret key, created on first use
+    pub key_path: PathBuf,
+    /// Messages between signed checkpoints
+    pub checkpoint_interval: u64,
+}
+
+/// The newest link in the chain
+#[derive(Debug, Clone)]
+pub(super) struct ChainHead {
+    pub seq: i64,
+    pub hash: String,
+    since_checkpoint: u64,
+}
+
+/// Where a message sits in the chain
+pub(super) struct ChainLink {
+    pub seq: i64,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+/// Messages removed from the chain, recorded so that the gap they leave can
+/// still be verified
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct DeletedRange {
+    pub first_seq: i64,
+    pub last_seq: i64,
+    /// `prev_hash` of the first deleted message
+    pub prev_hash: String,
+    /// `hash` of the last deleted message, which the next message links to
+    pub last_hash: String,
+}
+
+/// Links every stored message to the one before it, and signs checkpoints and
+/// deletions with an Ed25519 key
+#[derive(Debug)]
+pub(super) struct HashChain {
+    key: SigningKey,
+    checkpoint_interval: u64,
+    head: Mutex<ChainHead>,
+}
+
+impl HashChain {
+    /// Loads the signing key and finds the head of the chain
+    pub async fn open(pool: &SqlitePool, config: &ChainConfig) -> Result<HashChain> {
+        let key = load_or_create_key(&config.key_path)?;
+
+        // The newest message may have been deleted, in which case the deletion
+        // event that removed it records the head
+        let newest_message: Option<(i64, String)> = sqlx::query_as(
+            "SELECT chain_seq, hash FROM syslog_messages WHERE chain_seq IS NOT NULL ORDER BY chain_seq DESC LIMIT 1",
+        )
+        .fetch_optional(pool)
+        .await?;
+        let newest_event: Option<(i64, String)> =
+            sqlx::query_as("SELECT head_seq, head_hash FROM chain_events ORDER BY head_seq DESC, id DESC LIMIT 1")
+                .fetch_optional(pool)
+                .await?;
+
+        let (seq, hash) = newest_message
+            .into_iter()
+            .chain(newest_event)
+            .max_by_key(|(seq, _)| *seq)
+            .unwrap_or_else(|| (0, GENESIS_HASH.to_string()));
+        info!("Hash chain head is message {}, signed by {}", seq, hex::encode(key.verifying_key().as_bytes()));
+
+        Ok(HashChain {
+            key,
+            checkpoint_interval: config.checkpoint_interval.max(1),
+            head: Mutex::new(ChainHead { seq, hash, since_checkpoint: 0 }),
+        })
+    }
+
+    /// Held while messages are linked or deleted, so the head never moves underneath
+    pub async fn lock(&self) -> MutexGuard<'_, ChainHead> {
+        self.head.lock().await
+    }
+
+    /// Appends a message to a copy of the head, which the caller stores once
+    /// the message is committed
+    pub fn link(head: &mut ChainHead, message: &SyslogMessage) -> Result<ChainLink> {
+        let seq = head.seq + 1;
+        let prev_hash = std::mem::take(&mut head.hash);
+        let hash = message_hash(seq, &prev_hash, message)?;
+
+        head.seq = seq;
+        head.hash = hash.clone();
+        head.since_checkpoint += 1;
+        Ok(ChainLink { seq, prev_hash, hash })
+    }
+
+    /// Signs a checkpoint of the head once enough messages have been linked since the last
+    pub async fn checkpoint_if_due(&self, conn: &mut SqliteConnection, head: &mut ChainHead) -> Result<()> {
+        if head.since_checkpoint < self.checkpoint_interval {
+            return Ok(());
+        }
+        self.record(conn, "checkpoint", head, json!({})).await?;
+        head.since_checkpoint = 0;
+        Ok(())
+    }
+
+    /// Signs a record of deleted messages, in the same transaction as the delete
+    pub async fn record_deletion(
+        &self,
+        conn: &mut SqliteConnection,
+        head: &ChainHead,
+        description: &str,
+        deleted: &[(i64, String, String)],
+    ) -> Result<()> {
+        let mut ranges: Vec<DeletedRange> = Vec::new();
+        for (seq, prev_hash, hash) in deleted {
+            match ranges.last_mut() {
+                Some(range) if range.last_seq + 1 == *seq => {
+                    range.last_seq = *seq;
+                    range.last_hash = hash.clone();
+                }
+                _ => ranges.push(DeletedRange {
+                    first_seq: *seq,
+                    last_seq: *seq,
+                    prev_hash: prev_hash.clone(),
+                    last_hash: hash.clone(),
+                }),
+            }
+        }
+
+        let detail = json!({ "filter": description, "deleted": deleted.len(), "ranges": ranges });
+        self.record(conn, "deletion", head, detail).await
+    }
+
+    async fn record(&self, conn: &mut SqliteConnection, kind: &str, head: &ChainHead, detail: Value) -> Result<()> {
+        let created_at = Utc::now().to_rfc3339();
+        let detail = detail.to_string();
+        let signature = self
+            .key
+            .sign(event_payload(kind, &created_at, head.seq, &head.hash, &detail).as_bytes());
+
+        sqlx::query(
+            "INSERT INTO chain_events (kind, created_at, head_seq, head_hash, detail, signature) VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(kind)
+        .bind(&created_at)
+        .bind(head.seq)
+        .bind(&head.hash)
+        .bind(&detail)
+        .bind(hex::encode(signature.to_bytes()))
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        self.key.verifying_key()
+    }
+}
+
+/// SHA-256 over the message's position, its predecessor's hash and its JSON form
+pub(super) fn message_hash(seq: i64, prev_hash: &str, message: &SyslogMessage) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}\n{}\n", seq, prev_hash));
+    hasher.update(serde_json::to_vec(message)?);
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn event_payload(kind: &str, created_at: &str, head_seq: i64, head_hash: &str, detail: &str) -> String {
+    format!("{}\n{}\n{}\n{}\n{}", kind, created_at, head_seq, head_hash, detail)
+}
+
+fn load_or_create_key(path: &Path) -> Result<SigningKey> {
+    if path.exists() {
+        let encoded = fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let bytes: [u8; 32] = hex::decode(encoded.trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .with_context(|| format!("{} does not hold a hex-encoded 32-byte Ed25519 key", path.display()))?;
+        return Ok(SigningKey::from_bytes(&bytes));
+    }
+
+    let key = SigningKey::generate(&mut rand::rngs::OsRng);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path).with_context(|| format!("failed to create {}", path.display()))?;
+    writeln!(file, "{}", hex::encode(key.to_bytes()))?;
+
+    info!("Created hash chain key {}", path.display());
+    Ok(key)
+}
+
+/// The outcome of walking the chain
+#[derive(Debug, Clone, Serialize)]
+pub struct ChainReport {
+    /// Hex-encoded Ed25519 key the checkpoints and deletions were checked against
+    pub public_key: String,
+    pub verified_messages: u64,
+    /// Messages stored before the chain was enabled
+    pub unchained_messages: u64,
+    pub checkpoints: u64,
+    pub deletion_events: u64,
+    pub deleted_messages: u64,
+    /// The first link that failed to verify; verification stops there
+    pub first_break: Option<ChainBreak>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChainBreak {
+    pub seq: i64,
+    pub id: Option<String>,
+    pub problem: String,
+}
+
+/// Checks messages in chain order against their hashes, the signed checkpoints
+/// and the recorded deletions
+pub(super) struct ChainVerifier {
+    report: ChainReport,
+    deleted: BTreeMap<i64, DeletedRange>,
+    checkpoints: BTreeMap<i64, String>,
+    /// Highest position any signed event vouches for
+    recorded_head: i64,
+    last_seq: i64,
+    last_hash: String,
+}
+
+impl ChainVerifier {
+    /// Checks the signature of every event before any message is walked
+    pub async fn new(conn: &mut SqliteConnection, key: VerifyingKey) -> Result<ChainVerifier> {
+        let unchained: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM syslog_messages WHERE chain_seq IS NULL")
+            .fetch_one(&mut *conn)
+            .await?;
+
+        let mut verifier = ChainVerifier {
+            report: ChainReport {
+                public_key: hex::encode(key.as_bytes()),
+                verified_messages: 0,
+                unchained_messages: unchained as u64,
+                checkpoints: 0,
+                deletion_events: 0,
+                deleted_messages: 0,
+                first_break: None,
+            },
+            deleted: BTreeMap::new(),
+            checkpoints: BTreeMap::new(),
+            recorded_head: 0,
+            last_seq: 0,
+            last_hash: GENESIS_HASH.to_string(),
+        };
+
+        let events = sqlx::query("SELECT id, kind, created_at, head_seq, head_hash, detail, signature FROM chain_events ORDER BY id")
+            .fetch_all(&mut *conn)
+            .await?;
+        for event in events {
+            let id: i64 = event.get("id");
+            let kind: String = event.get("kind");
+            let head_seq: i64 = event.get("head_seq");
+            let head_hash: String = event.get("head_hash");
+            let detail: String = event.get("detail");
+            let payload = event_payload(&kind, event.get("created_at"), head_seq, &head_hash, &detail);
+
+            let signature = hex::decode(event.get::<String, _>("signature"))
+                .ok()
+                .and_then(|bytes| Signature::from_slice(&bytes).ok());
+            if signature.is_none_or(|signature| key.verify(payload.as_bytes(), &signature).is_err()) {
+                verifier.fail(head_seq, None, format!("chain event {} has an invalid signature", id));
+                return Ok(verifier);
+            }
+
+            verifier.recorded_head = verifier.recorded_head.max(head_seq);
+            match kind.as_str() {
+                "checkpoint" => {
+                    verifier.report.checkpoints += 1;
+                    verifier.checkpoints.insert(head_seq, head_hash);
+                }
+                "deletion" => {
+                    verifier.report.deletion_events += 1;
+                    let detail: Value = serde_json::from_str(&detail)?;
+                    let ranges: Vec<DeletedRange> = serde_json::from_value(detail["ranges"].clone())?;
+                    for range in ranges {
+                        verifier.report.deleted_messages += (range.last_seq - range.first_seq + 1) as u64;
+                        verifier.deleted.insert(range.first_seq, range);
+                    }
+                }
+                other => bail!("unknown chain event kind {:?}", other),
+            }
+        }
+
+        Ok(verifier)
+    }
+
+    pub fn broken(&self) -> bool {
+        self.report.first_break.is_some()
+    }
+
+    /// Checks the next message in chain order; returns false once the chain is broken
+    pub fn check(&mut self, seq: i64, prev_hash: &str, hash: &str, message: &SyslogMessage) -> Result<bool> {
+        if !self.skip_deleted(seq) {
+            return Ok(false);
+        }
+
+        if prev_hash != self.last_hash {
+            self.fail(seq, Some(&message.id), "does not link to the previous message".to_string());
+        } else if message_hash(seq, prev_hash, message)? != hash {
+            self.fail(seq, Some(&message.id), "content does not match its hash".to_string());
+        } else if self.checkpoints.get(&seq).is_some_and(|signed| signed != hash) {
+            self.fail(seq, Some(&message.id), "hash differs from the signed checkpoint".to_string());
+        }
+        if self.broken() {
+            return Ok(false);
+        }
+
+        self.report.verified_messages += 1;
+        self.last_seq = seq;
+        self.last_hash = hash.to_string();
+        Ok(true)
+    }
+
+    pub fn finish(mut self) -> ChainReport {
+        if !self.broken() && self.skip_deleted(i64::MAX) && self.last_seq < self.recorded_head {
+            self.fail(
+                self.last_seq + 1,
+                None,
+                format!(
+                    "messages {} to {} are covered by a signed event but missing",
+                    self.last_seq + 1,
+                    self.recorded_head
+                ),
+            );
+        }
+        self.report
+    }
+
+    /// Follows recorded deletions from the last verified message up to `seq`
+    fn skip_deleted(&mut self, seq: i64) -> bool {
+        while self.last_seq + 1 < seq {
+            let Some(range) = self.deleted.get(&(self.last_seq + 1)) else {
+                if seq == i64::MAX {
+                    return true;
+                }
+                self.fail(
+                    self.last_seq + 1,
+                    None,
+                    format!("messages {} to {} are missing without a deletion record", self.last_seq + 1, seq - 1),
+                );
+                return false;
+            };
+
+            if range.prev_hash != self.last_hash || range.last_seq >= seq {
+                let first_seq = range.first_seq;
+                self.fail(first_seq, None, "deletion record does not match the chain".to_string());
+                return false;
+            }
+            self.last_seq = range.last_seq;
+            self.last_hash = range.last_hash.clone();
+        }
+        true
+    }
+
+    fn fail(&mut self, seq: i64, id: Option<&str>, problem: String) {
+        self.report.first_break.get_or_insert(ChainBreak {
+            seq,
+            id: id.map(str::to_string),
+            problem,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{
+        testing::{day, messages, TempDir},
+        LogFilter, LogStore, SqliteStore,
+    };
+
+    /// A chained store holding ten messages, and a second connection to tamper through
+    async fn chained(dir: &TempDir) -> (SqliteStore, SqlitePool, Vec<SyslogMessage>) {
+        let mut config = dir.config();
+        config.chain = Some(ChainConfig { key_path: dir.path().join("chain.key"), checkpoint_interval: 4 });
+        let store = SqliteStore::open(&config).await.unwrap();
+        let messages = messages(day(0), 10);
+        store.insert_batch(&messages).await.unwrap();
+        let pool = SqlitePool::connect_with(config.connect_options()).await.unwrap();
+        (store, pool, messages)
+    }
+
+    #[tokio::test]
+    async fn recorded_deletion_verifies() {
+        let dir = TempDir::new();
+        let (store, _, messages) = chained(&dir).await;
+        let ids = messages[3..6].iter().map(|message| message.id.clone()).collect();
+        assert_eq!(store.delete(&LogFilter::Id(ids), None).await.unwrap(), 3);
+
+        let report = store.verify_chain().await.unwrap();
+        assert!(report.first_break.is_none(), "{:?}", report.first_break);
+        assert_eq!(report.verified_messages, 7);
+        assert_eq!(report.deletion_events, 1);
+        assert_eq!(report.deleted_messages, 3);
+        assert_eq!(report.checkpoints, 1);
+    }
+
+    #[tokio::test]
+    async fn edited_message_breaks_the_chain() {
+        let dir = TempDir::new();
+        let (store, pool, messages) = chained(&dir).await;
+        sqlx::query("UPDATE syslog_messages SET message = 'nothing happened' WHERE id = ?")
+            .bind(&messages[4].id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let report = store.verify_chain().await.unwrap();
+        let broken = report.first_break.unwrap();
+        assert_eq!(broken.seq, 5);
+        assert_eq!(broken.id.as_deref(), Some(messages[4].id.as_str()));
+        assert_eq!(broken.problem, "content does not match its hash");
+        assert_eq!(report.verified_messages, 4);
+    }
+
+    #[tokio::test]
+    async fn unrecorded_deletion_breaks_the_chain() {
+        let dir = TempDir::new();
+        let (store, pool, messages) = chained(&dir).await;
+        sqlx::query("DELETE FROM syslog_messages WHERE id = ?")
+            .bind(&messages[4].id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let broken = store.verify_chain().await.unwrap().first_break.unwrap();
+        assert_eq!(broken.seq, 5);
+        assert_eq!(broken.problem, "messages 5 to 5 are missing without a deletion record");
+    }
+
+    #[tokio::test]
+    async fn deleting_the_newest_messages_unrecorded_breaks_the_chain() {
+        let dir = TempDir::new();
+        let (store, pool, _) = chained(&dir).await;
+        // The checkpoint signed after the batch still vouches for what was there
+        sqlx::query("DELETE FROM syslog_messages WHERE chain_seq > 7")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let broken = store.verify_chain().await.unwrap().first_break.unwrap();
+        assert_eq!(broken.problem, "messages 8 to 10 are covered by a signed event but missing");
+    }
+
+    #[tokio::test]
+    async fn forged_deletion_record_is_rejected() {
+        let dir = TempDir::new();
+        let (store, pool, messages) = chained(&dir).await;
+        let (prev_hash, hash): (String, String) = sqlx::query_as("SELECT prev_hash, hash FROM syslog_messages WHERE id = ?")
+            .bind(&messages[4].id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM syslog_messages WHERE id = ?")
+            .bind(&messages[4].id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // A well-formed record of the gap, signed with a key that is not the chain's
+        let forger = SigningKey::generate(&mut rand::rngs::OsRng);
+        let range = DeletedRange { first_seq: 5, last_seq: 5, prev_hash, last_hash: hash };
+        let detail = json!({ "filter": "forged", "deleted": 1, "ranges": [range] }).to_string();
+        let created_at = Utc::now().to_rfc3339();
+        let (head_seq, head_hash): (i64, String) =
+            sqlx::query_as("SELECT chain_seq, hash FROM syslog_messages ORDER BY chain_seq DESC LIMIT 1")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        let signature = forger.sign(event_payload("deletion", &created_at, head_seq, &head_hash, &detail).as_bytes());
+        let id = sqlx::query(
+            "INSERT INTO chain_events (kind, created_at, head_seq, head_hash, detail, signature) VALUES ('deletion', ?, ?, ?, ?, ?)",
+        )
+        .bind(&created_at)
+        .bind(head_seq)
+        .bind(&head_hash)
+        .bind(&detail)
+        .bind(hex::encode(signature.to_bytes()))
+        .execute(&pool)
+        .await
+        .unwrap()
+        .last_insert_rowid();
+
+        let broken = store.verify_chain().await.unwrap().first_break.unwrap();
+        assert_eq!(broken.problem, format!("chain event {} has an invalid signature", id));
+    }
+}
@@ -0,0 +1,315 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use sqlx::{sqlite::SqlitePool, Row};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+use tracing::info;
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Recent raw messages a dictionary is trained on, and the rows a database
+/// needs before training starts
+const DICTIONARY_SAMPLES: i64 = 5000;
+
+const DICTIONARY_SIZE: usize = 16 * 1024;
+
+/// Inserts between checks for whether a dictionary can be trained yet
+const TRAINING_CHECK_INTERVAL: u64 = 1000;
+
+/// How `raw_message` is stored in SQLite
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RawCompression {
+    /// Plain text
+    Off,
+    /// zstd, one frame per message
+    Zstd,
+    /// zstd with a dictionary trained on the database's first messages, which
+    /// compresses short messages far better
+    Dictionary,
+}
+
+/// A compressed raw message, with the dictionary needed to read it back
+pub(super) struct Compressed {
+    pub data: Vec<u8>,
+    pub dictionary: Option<i64>,
+}
+
+/// Compresses and decompresses `raw_message` for one SQLite database
+pub(super) struct RawCodec {
+    mode: RawCompression,
+    encoder: RwLock<Option<(i64, Arc<EncoderDictionary<'static>>)>>,
+    decoders: RwLock<HashMap<i64, Arc<DecoderDictionary<'static>>>>,
+    inserted: AtomicU64,
+    training: AtomicBool,
+}
+
+impl fmt::Debug for RawCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dictionary = self.encoder.read().unwrap().as_ref().map(|(id, _)| *id);
+        f.debug_struct("RawCodec")
+            .field("mode", &self.mode)
+            .field("dictionary", &dictionary)
+            .finish()
+    }
+}
+
+impl RawCodec {
+    /// Loads the dictionaries stored in the database; the newest one is used
+    /// for new rows
+    pub async fn load(pool: &SqlitePool, mode: RawCompression) -> Result<RawCodec> {
+        let rows: Vec<(i64, Vec<u8>)> = sqlx::query_as("SELECT id, dictionary FROM raw_dictionaries ORDER BY id")
+            .fetch_all(pool)
+            .await?;
+
+        let mut encoder = None;
+        let mut decoders = HashMap::new();
+        for (id, dictionary) in rows {
+            decoders.insert(id, Arc::new(DecoderDictionary::copy(&dictionary)));
+            if mode == RawCompression::Dictionary {
+                encoder = Some((id, Arc::new(EncoderDictionary::copy(&dictionary, COMPRESSION_LEVEL))));
+            }
+        }
+
+        Ok(RawCodec {
+            mode,
+            encoder: RwLock::new(encoder),
+            decoders: RwLock::new(decoders),
+            inserted: AtomicU64::new(0),
+            training: AtomicBool::new(false),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.mode != RawCompression::Off
+    }
+
+    /// Compresses each message, leaving out those compression would not shrink
+    pub fn compress_all<'a>(&self, raw_messages: impl Iterator<Item = &'a str>) -> Result<Vec<Option<Compressed>>> {
+        if self.mode == RawCompression::Off {
+            return Ok(raw_messages.map(|_| None).collect());
+        }
+
+        let encoder = self.encoder.read().unwrap().clone();
+        let (mut compressor, dictionary) = match &encoder {
+            Some((id, prepared)) => (zstd::bulk::Compressor::with_prepared_dictionary(prepared)?, Some(*id)),
+            None => (zstd::bulk::Compressor::new(COMPRESSION_LEVEL)?, None),
+        };
+
+        raw_messages
+            .map(|raw| {
+                let data = compressor.compress(raw.as_bytes())?;
+                Ok((data.len() < raw.len()).then_some(Compressed { data, dictionary }))
+            })
+            .collect()
+    }
+
+    pub fn decompress(&self, data: &[u8], length: usize, dictionary: Option<i64>) -> Result<String> {
+        let bytes = match dictionary {
+            Some(id) => {
+                let prepared = self
+                    .decoders
+                    .read()
+                    .unwrap()
+                    .get(&id)
+                    .cloned()
+                    .with_context(|| format!("raw message dictionary {} is missing", id))?;
+                let mut decompressor = zstd::bulk::Decompressor::with_prepared_dictionary(&prepared)?;
+                decompressor.decompress(data, length)?
+            }
+            None => zstd::bulk::decompress(data, length)?,
+        };
+        String::from_utf8(bytes).context("decompressed raw message is not UTF-8")
+    }
+
+    /// Trains a dictionary once the database holds enough messages, if this
+    /// codec uses one and has none yet
+    pub async fn train_if_ready(&self, pool: &SqlitePool, inserted: usize) -> Result<()> {
+        if self.mode != RawCompression::Dictionary || self.encoder.read().unwrap().is_some() {
+            return Ok(());
+        }
+        let before = self.inserted.fetch_add(inserted as u64, Ordering::Relaxed);
+        if before > 0 && before / TRAINING_CHECK_INTERVAL == (before + inserted as u64) / TRAINING_CHECK_INTERVAL {
+            return Ok(());
+        }
+        if self.training.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        let result = self.train(pool).await;
+        self.training.store(false, Ordering::Release);
+        result
+    }
+
+    async fn train(&self, pool: &SqlitePool) -> Result<()> {
+        let rows = sqlx::query(
+            "SELECT raw_message, raw_compressed, raw_length, raw_dictionary FROM syslog_messages ORDER BY timestamp DESC LIMIT ?",
+        )
+        .bind(DICTIONARY_SAMPLES)
+        .fetch_all(pool)
+        .await?;
+        if (rows.len() as i64) < DICTIONARY_SAMPLES {
+            return Ok(());
+        }
+
+        let samples = rows
+            .iter()
+            .map(|row| match row.get::<Option<Vec<u8>>, _>("raw_compressed") {
+                Some(data) => self.decompress(
+                    &data,
+                    row.get::<Option<i64>, _>("raw_length").unwrap_or_default() as usize,
+                    row.get("raw_dictionary"),
+                ),
+                None => Ok(row.get("raw_message")),
+            })
+            .collect::<Result<Vec<String>>>()?;
+
+        let dictionary = tokio::task::spawn_blocking(move || zstd::dict::from_samples(&samples, DICTIONARY_SIZE))
+            .await?
+            .context("failed to train a raw message dictionary")?;
+
+        let id: i64 = sqlx::query_scalar(
+            "INSERT INTO raw_dictionaries (dictionary, samples, created_at) VALUES (?, ?, ?) RETURNING id",
+        )
+        .bind(&dictionary)
+        .bind(DICTIONARY_SAMPLES)
+        .bind(Utc::now().to_rfc3339())
+        .fetch_one(pool)
+        .await?;
+
+        self.decoders.write().unwrap().insert(id, Arc::new(DecoderDictionary::copy(&dictionary)));
+        *self.encoder.write().unwrap() = Some((id, Arc::new(EncoderDictionary::copy(&dictionary, COMPRESSION_LEVEL))));
+        info!("Trained raw message dictionary {} ({} bytes) on {} messages", id, dictionary.len(), DICTIONARY_SAMPLES);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{
+        testing::{day, message, TempDir},
+        DatabaseConfig, LogFilter, LogStore, Page, SqliteStore,
+    };
+    use chrono::Duration;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use syslog_parser::SyslogMessage;
+
+    /// Messages long and alike enough that zstd shrinks them
+    fn verbose(start: usize, count: usize) -> Vec<SyslogMessage> {
+        (start..start + count)
+            .map(|i| {
+                let mut message = message(day(0) + Duration::seconds(i as i64), Some("host"), "connection closed");
+                message.raw_message = format!(
+                    "<14>1 2024-06-01T00:00:00Z host sshd {} - - connection from 192.0.2.{} port {} closed by remote peer [preauth] {}",
+                    i,
+                    i % 250,
+                    40000 + i,
+                    "x".repeat(i % 7)
+                );
+                message
+            })
+            .collect()
+    }
+
+    async fn open(config: &DatabaseConfig, raw_compression: RawCompression) -> SqliteStore {
+        let config = DatabaseConfig { raw_compression, ..config.clone() };
+        SqliteStore::open(&config).await.unwrap()
+    }
+
+    /// Raw messages as read back through the store, oldest first
+    async fn raw_messages(store: &SqliteStore) -> Vec<String> {
+        let page = Page { limit: 100_000, offset: 0, cursor: None };
+        let mut entries = store.query(&LogFilter::All, page).await.unwrap();
+        entries.reverse();
+        entries.into_iter().map(|entry| entry.message.raw_message).collect()
+    }
+
+    /// How many rows are stored plain, compressed without and with a dictionary
+    async fn storage(config: &DatabaseConfig) -> (i64, i64, i64) {
+        let pool = SqlitePoolOptions::new().connect_with(config.connect_options()).await.unwrap();
+        let counts = sqlx::query_as(
+            "SELECT COUNT(*) FILTER (WHERE raw_compressed IS NULL),
+                    COUNT(*) FILTER (WHERE raw_compressed IS NOT NULL AND raw_dictionary IS NULL),
+                    COUNT(*) FILTER (WHERE raw_dictionary IS NOT NULL)
+             FROM syslog_messages",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        pool.close().await;
+        counts
+    }
+
+    fn raw(messages: &[SyslogMessage]) -> Vec<String> {
+        messages.iter().map(|message| message.raw_message.clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn trains_a_dictionary_and_reads_its_rows_back() {
+        let dir = TempDir::new();
+        let config = dir.config();
+        let samples = verbose(0, DICTIONARY_SAMPLES as usize);
+        let later = verbose(samples.len(), 100);
+
+        let store = open(&config, RawCompression::Dictionary).await;
+        for batch in samples.chunks(1000) {
+            store.insert_batch(batch).await.unwrap();
+        }
+        store.insert_batch(&later).await.unwrap();
+        store.close().await;
+
+        // Rows from before training fall back to plain zstd
+        let (plain, zstd, dictionary) = storage(&config).await;
+        assert_eq!((plain + zstd, dictionary), (samples.len() as i64, later.len() as i64));
+
+        // The dictionary is stored with the rows, so a reopened store reads them all
+        let store = open(&config, RawCompression::Dictionary).await;
+        assert_eq!(raw_messages(&store).await, [raw(&samples), raw(&later)].concat());
+        let first = &later[0];
+        assert_eq!(store.get(&first.id).await.unwrap().unwrap().raw_message, first.raw_message);
+        store.close().await;
+
+        // Even with the dictionary no longer used for new rows
+        let store = open(&config, RawCompression::Off).await;
+        assert_eq!(raw_messages(&store).await, [raw(&samples), raw(&later)].concat());
+    }
+
+    #[tokio::test]
+    async fn reads_rows_stored_before_compression_was_enabled() {
+        let dir = TempDir::new();
+        let config = dir.config();
+        let before = verbose(0, 20);
+        let after = verbose(20, 20);
+
+        let store = open(&config, RawCompression::Off).await;
+        store.insert_batch(&before).await.unwrap();
+        store.close().await;
+        assert_eq!(storage(&config).await, (20, 0, 0));
+
+        let store = open(&config, RawCompression::Zstd).await;
+        store.insert_batch(&after).await.unwrap();
+        assert_eq!(raw_messages(&store).await, [raw(&before), raw(&after)].concat());
+        store.close().await;
+        assert_eq!(storage(&config).await, (20, 20, 0));
+    }
+
+    #[tokio::test]
+    async fn stores_incompressible_messages_plain() {
+        let dir = TempDir::new();
+        let store = open(&dir.config(), RawCompression::Zstd).await;
+        let short = message(day(0), Some("host"), "up");
+        store.insert_batch(std::slice::from_ref(&short)).await.unwrap();
+        store.close().await;
+
+        assert_eq!(storage(&dir.config()).await, (1, 0, 0));
+        let store = open(&dir.config(), RawCompression::Zstd).await;
+        assert_eq!(raw_messages(&store).await, [short.raw_message]);
+    }
+}
@@ -0,0 +1,263 @@
+use crate::{search::SearchQuery, signing::SignatureStatus};
+use syslog_parser::SyslogMessage;
+use chrono::{DateTime, Utc};
+
+/// Selects stored messages, independent of the backend that stores them
+#[derive(Debug, Clone)]
+pub enum LogFilter {
+    All,
+    Facility(Vec<u8>),
+    Severity(Vec<u8>),
+    /// Hostname matching any of these patterns, where `*` matches anything
+    Hostname(Vec<String>),
+    /// App name matching any of these patterns, where `*` matches anything
+    AppName(Vec<String>),
+    /// Messages with any of these ids
+    Id(Vec<String>),
+    /// Messages received from any of these addresses
+    SourceIp(Vec<String>),
+    /// Messages timestamped at or after this instant
+    Since(DateTime<Utc>),
+    /// Messages timestamped strictly before this instant
+    Before(DateTime<Utc>),
+    /// Messages that were (or were not) flagged as malformed
+    Flagged(bool),
+    /// Messages with this RFC 5848 signature status
+    Signature(SignatureStatus),
+    /// Messages that are (or are not) in the trash
+    Trashed(bool),
+    /// Messages moved to the trash strictly before this instant
+    TrashedBefore(DateTime<Utc>),
+    Search(SearchQuery),
+    And(Vec<LogFilter>),
+    Not(Box<LogFilter>),
+}
+
+impl LogFilter {
+    pub fn and(self, other: LogFilter) -> LogFilter {
+        match (self, other) {
+            (LogFilter::All, other) | (other, LogFilter::All) => other,
+            (LogFilter::And(mut filters), LogFilter::And(others)) => {
+                filters.extend(others);
+                LogFilter::And(filters)
+            }
+            (LogFilter::And(mut filters), other) => {
+                filters.push(other);
+                LogFilter::And(filters)
+            }
+            (filter, other) => LogFilter::And(vec![filter, other]),
+        }
+    }
+
+    pub fn negate(self) -> LogFilter {
+        LogFilter::Not(Box::new(self))
+    }
+
+    /// The `[since, before)` time range every matching message falls in, as far
+    /// as the filter restricts it
+    pub fn time_range(&self) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+        match self {
+            LogFilter::Since(instant) => (Some(*instant), None),
+            LogFilter::Before(instant) => (None, Some(*instant)),
+            LogFilter::And(filters) => filters.iter().map(LogFilter::time_range).fold(
+                (None, None),
+                |(since, before), (other_since, other_before)| {
+                    (since.max(other_since), before.into_iter().chain(other_before).min())
+                },
+            ),
+            _ => (None, None),
+        }
+    }
+
+    /// Whether the filter only restricts timestamps, so that every message in
+    /// its time range matches
+    pub fn is_time_range(&self) -> bool {
+        match self {
+            LogFilter::All | LogFilter::Since(_) | LogFilter::Before(_) => true,
+            LogFilter::And(filters) => filters.iter().all(LogFilter::is_time_range),
+            _ => false,
+        }
+    }
+
+    /// Evaluates the filter against a message held in memory, with the same
+    /// results as the SQL it renders to. The signature status and the time the
+    /// message was trashed, if it was, are kept beside the message.
+    pub fn matches(&self, message: &SyslogMessage, signature: SignatureStatus, deleted_at: Option<DateTime<Utc>>) -> bool {
+        match self {
+            LogFilter::All => true,
+            LogFilter::Facility(codes) => codes.contains(&message.facility),
+            LogFilter::Severity(codes) => codes.contains(&message.severity),
+            LogFilter::Hostname(patterns) => matches_any(patterns, message.hostname.as_deref()),
+            LogFilter::AppName(patterns) => matches_any(patterns, message.app_name.as_deref()),
+            LogFilter::Id(ids) => ids.contains(&message.id),
+            LogFilter::SourceIp(sources) => sources.contains(&message.source_ip),
+            LogFilter::Since(instant) => message.timestamp >= *instant,
+            LogFilter::Before(instant) => message.timestamp < *instant,
+            LogFilter::Flagged(flagged) => message.parse_error.is_some() == *flagged,
+            LogFilter::Signature(status) => signature == *status,
+            LogFilter::Trashed(trashed) => deleted_at.is_some() == *trashed,
+            LogFilter::TrashedBefore(instant) => deleted_at.is_some_and(|deleted_at| deleted_at < *instant),
+            LogFilter::Search(query) => query.matches(&[
+                &message.message,
+                message.hostname.as_deref().unwrap_or_default(),
+                message.app_name.as_deref().unwrap_or_default(),
+            ]),
+            LogFilter::And(filters) => filters.iter().all(|filter| filter.matches(message, signature, deleted_at)),
+            LogFilter::Not(filter) => !filter.matches(message, signature, deleted_at),
+        }
+    }
+
+    /// The full-text search this filter requires, used to build snippets
+    pub fn search(&self) -> Option<&SearchQuery> {
+        match self {
+            LogFilter::Search(query) => Some(query),
+            LogFilter::And(filters) => filters.iter().find_map(LogFilter::search),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Dialect {
+    Sqlite,
+    Postgres,
+}
+
+/// A value bound to a `?` placeholder in rendered SQL
+#[derive(Debug, Clone)]
+pub(crate) enum SqlValue {
+    Int(i64),
+    Text(String),
+    Time(DateTime<Utc>),
+}
+
+/// Renders a filter as a SQL condition over `syslog_messages`. Every value is
+/// appended to `binds` and written as a `?` placeholder.
+pub(crate) fn render(filter: &LogFilter, dialect: Dialect, binds: &mut Vec<SqlValue>) -> String {
+    match filter {
+        LogFilter::All => "1=1".to_string(),
+        LogFilter::Facility(codes) => render_in("facility", codes, binds),
+        LogFilter::Severity(codes) => render_in("severity", codes, binds),
+        LogFilter::Hostname(patterns) => render_patterns("hostname", patterns, dialect, binds),
+        LogFilter::AppName(patterns) => render_patterns("app_name", patterns, dialect, binds),
+        LogFilter::Id(ids) if ids.is_empty() => "1=0".to_string(),
+        LogFilter::Id(ids) => {
+            binds.extend(ids.iter().map(|id| SqlValue::Text(id.clone())));
+            format!("id IN ({})", vec!["?"; ids.len()].join(", "))
+        }
+        LogFilter::SourceIp(sources) if sources.is_empty() => "1=0".to_string(),
+        LogFilter::SourceIp(sources) => {
+            binds.extend(sources.iter().map(|source| SqlValue::Text(source.clone())));
+            format!("source_ip IN ({})", vec!["?"; sources.len()].join(", "))
+        }
+        LogFilter::Since(instant) => {
+            binds.push(SqlValue::Time(*instant));
+            "timestamp >= ?".to_string()
+        }
+        LogFilter::Before(instant) => {
+            binds.push(SqlValue::Time(*instant));
+            "timestamp < ?".to_string()
+        }
+        LogFilter::Flagged(true) => "parse_error IS NOT NULL".to_string(),
+        LogFilter::Flagged(false) => "parse_error IS NULL".to_string(),
+        LogFilter::Signature(status) => {
+            binds.push(SqlValue::Text(status.as_str().to_string()));
+            "signature_status = ?".to_string()
+        }
+        LogFilter::Trashed(true) => "deleted_at IS NOT NULL".to_string(),
+        LogFilter::Trashed(false) => "deleted_at IS NULL".to_string(),
+        LogFilter::TrashedBefore(instant) => {
+            binds.push(SqlValue::Time(*instant));
+            "deleted_at < ?".to_string()
+        }
+        LogFilter::Search(query) => match dialect {
+            Dialect::Sqlite => {
+                binds.push(SqlValue::Text(query.to_fts5()));
+                "seq IN (SELECT rowid FROM syslog_fts WHERE syslog_fts MATCH ?)".to_string()
+            }
+            Dialect::Postgres => {
+                binds.push(SqlValue::Text(query.to_tsquery()));
+                "search_vector @@ to_tsquery('simple', ?)".to_string()
+            }
+        },
+        LogFilter::And(filters) if filters.is_empty() => "1=1".to_string(),
+        LogFilter::And(filters) => {
+            let conditions: Vec<String> = filters.iter().map(|filter| render(filter, dialect, binds)).collect();
+            format!("({})", conditions.join(" AND "))
+        }
+        LogFilter::Not(filter) => format!("NOT {}", render(filter, dialect, binds)),
+    }
+}
+
+fn render_in(column: &str, codes: &[u8], binds: &mut Vec<SqlValue>) -> String {
+    if codes.is_empty() {
+        return "1=0".to_string();
+    }
+    binds.extend(codes.iter().map(|&code| SqlValue::Int(i64::from(code))));
+    format!("{} IN ({})", column, vec!["?"; codes.len()].join(", "))
+}
+
+/// Case-insensitive pattern match. A missing value matches as the empty string,
+/// so that negating the condition never silently drops NULL rows.
+fn render_patterns(column: &str, patterns: &[String], dialect: Dialect, binds: &mut Vec<SqlValue>) -> String {
+    if patterns.is_empty() {
+        return "1=0".to_string();
+    }
+
+    let like = match dialect {
+        Dialect::Sqlite => "LIKE",
+        Dialect::Postgres => "ILIKE",
+    };
+    let alternatives: Vec<String> = patterns
+        .iter()
+        .map(|pattern| {
+            binds.push(SqlValue::Text(like_pattern(pattern)));
+            format!("COALESCE({}, '') {} ? ESCAPE '\\'", column, like)
+        })
+        .collect();
+
+    format!("({})", alternatives.join(" OR "))
+}
+
+/// `render_patterns` for a value in memory: ASCII case-insensitive, like SQLite's LIKE
+fn matches_any(patterns: &[String], value: Option<&str>) -> bool {
+    let value = value.unwrap_or_default().to_ascii_lowercase();
+    patterns
+        .iter()
+        .any(|pattern| wildcard_match(&pattern.to_ascii_lowercase(), &value))
+}
+
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// Converts a `*` wildcard pattern to a LIKE pattern, escaping LIKE's own wildcards
+fn like_pattern(pattern: &str) -> String {
+    let mut like = String::with_capacity(pattern.len());
+    for c in pattern.chars() {
+        match c {
+            '*' => like.push('%'),
+            '%' | '_' | '\\' => {
+                like.push('\\');
+                like.push(c);
+            }
+            c => like.push(c),
+        }
+    }
+    like
+}
@@ -0,0 +1,216 @@
+use super::{LogFilter, LogStore};
+use crate::search::SearchQuery;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Messages a legal hold protects. Values within a field are alternatives,
+/// fields must all match, and a hold with no criteria protects everything.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HoldCriteria {
+    /// Hostname patterns, where `*` matches anything
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hostname: Vec<String>,
+    /// App name patterns, where `*` matches anything
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub app_name: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub source_ip: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub facility: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub severity: Vec<u8>,
+    /// Full-text search, in the syntax `/api/logs?search=` takes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl HoldCriteria {
+    /// Why the criteria can never be used, if they cannot
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(search) = &self.search {
+            if SearchQuery::parse(search).is_none() {
+                return Err(format!("invalid search '{}'", search));
+            }
+        }
+        if let (Some(since), Some(until)) = (self.since, self.until) {
+            if since >= until {
+                return Err("since must be before until".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// The messages held
+    pub fn filter(&self) -> LogFilter {
+        let mut filter = LogFilter::All;
+        if !self.hostname.is_empty() {
+            filter = filter.and(LogFilter::Hostname(self.hostname.clone()));
+        }
+        if !self.app_name.is_empty() {
+            filter = filter.and(LogFilter::AppName(self.app_name.clone()));
+        }
+        if !self.source_ip.is_empty() {
+            filter = filter.and(LogFilter::SourceIp(self.source_ip.clone()));
+        }
+        if !self.facility.is_empty() {
+            filter = filter.and(LogFilter::Facility(self.facility.clone()));
+        }
+        if !self.severity.is_empty() {
+            filter = filter.and(LogFilter::Severity(self.severity.clone()));
+        }
+        if let Some(search) = self.search.as_deref().and_then(SearchQuery::parse) {
+            filter = filter.and(LogFilter::Search(search));
+        }
+        if let Some(since) = self.since {
+            filter = filter.and(LogFilter::Since(since));
+        }
+        if let Some(until) = self.until {
+            filter = filter.and(LogFilter::Before(until));
+        }
+        filter
+    }
+}
+
+/// Keeps the messages its criteria match from being deleted, by retention or
+/// through the API, until it is released
+#[derive(Debug, Clone, Serialize)]
+pub struct LegalHold {
+    pub id: i64,
+    pub reason: String,
+    pub owner: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub released_at: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    pub criteria: HoldCriteria,
+}
+
+impl LegalHold {
+    pub fn is_active(&self) -> bool {
+        self.released_at.is_none()
+    }
+}
+
+/// Narrows `filter` to the messages no active hold protects
+pub fn exempt(filter: LogFilter, holds: &[LegalHold]) -> LogFilter {
+    holds
+        .iter()
+        .filter(|hold| hold.is_active())
+        .fold(filter, |filter, hold| filter.and(hold.criteria.filter().negate()))
+}
+
+/// How many of the messages `filter` matches an active hold protects
+pub async fn count_held(store: &dyn LogStore, filter: &LogFilter, holds: &[LegalHold]) -> Result<u64> {
+    if !holds.iter().any(LegalHold::is_active) {
+        return Ok(0);
+    }
+    store.count(&filter.clone().and(exempt(LogFilter::All, holds).negate())).await
+}
+
+/// `exempt` with the store's current holds, for deletes that should honour holds placed since they were asked for
+pub async fn without_held(store: &dyn LogStore, filter: LogFilter) -> Result<LogFilter> {
+    Ok(exempt(filter, &store.legal_holds().await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{
+        testing::{day, for_each_backend, message, TempDir},
+        Page, SqliteStore,
+    };
+    use chrono::Duration;
+    use syslog_parser::SyslogMessage;
+
+    /// Two web and three database messages, the last database one from `auth`
+    fn fixture() -> Vec<SyslogMessage> {
+        ["web-1", "web-2", "db-1", "db-2", "db-3"]
+            .iter()
+            .enumerate()
+            .map(|(i, host)| {
+                let mut message = message(day(0) + Duration::minutes(i as i64), Some(host), "query finished");
+                if i == 4 {
+                    message.facility = 4;
+                }
+                message
+            })
+            .collect()
+    }
+
+    fn hostnames(patterns: &[&str]) -> HoldCriteria {
+        HoldCriteria {
+            hostname: patterns.iter().map(|pattern| pattern.to_string()).collect(),
+            ..HoldCriteria::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn held_rows_survive_deletes() {
+        for_each_backend(async |store, _| {
+            store.insert_batch(&fixture()).await.unwrap();
+            let databases = store.add_legal_hold("audit", "legal", &hostnames(&["db-*"])).await.unwrap();
+            let auth = HoldCriteria {
+                facility: vec![4],
+                ..HoldCriteria::default()
+            };
+            let auth = store.add_legal_hold("breach", "security", &auth).await.unwrap();
+            let released = store.add_legal_hold("old case", "legal", &hostnames(&["web-1"])).await.unwrap();
+            assert!(store.release_legal_hold(released.id).await.unwrap());
+            assert!(!store.release_legal_hold(released.id).await.unwrap());
+
+            let holds = store.legal_holds().await.unwrap();
+            let ids: Vec<i64> = holds.iter().map(|hold| hold.id).collect();
+            assert_eq!(ids, vec![databases.id, auth.id, released.id]);
+            assert!(!holds[2].is_active());
+
+            // Each hold counts what it protects, overlaps included
+            let mut protected = Vec::new();
+            for hold in &holds {
+                protected.push(store.count(&hold.criteria.filter()).await.unwrap());
+            }
+            assert_eq!(protected, vec![3, 1, 1]);
+            // Across holds a message is only counted once, and released holds protect nothing
+            assert_eq!(count_held(store, &LogFilter::All, &holds).await.unwrap(), 3);
+            let web = LogFilter::Hostname(vec!["web-*".to_string()]);
+            assert_eq!(count_held(store, &web, &holds).await.unwrap(), 0);
+
+            assert_eq!(store.delete(&exempt(LogFilter::All, &holds), None).await.unwrap(), 2);
+            assert_eq!(store.delete(&without_held(store, LogFilter::All).await.unwrap(), None).await.unwrap(), 0);
+            let page = Page { limit: 10, offset: 0, cursor: None };
+            let mut left: Vec<String> = store
+                .query(&LogFilter::All, page)
+                .await
+                .unwrap()
+                .into_iter()
+                .filter_map(|entry| entry.message.hostname)
+                .collect();
+            left.sort();
+            assert_eq!(left, vec!["db-1", "db-2", "db-3"]);
+
+            // Once released, the messages can go
+            store.release_legal_hold(databases.id).await.unwrap();
+            let holds = store.legal_holds().await.unwrap();
+            assert_eq!(count_held(store, &LogFilter::All, &holds).await.unwrap(), 1);
+            assert_eq!(store.delete(&exempt(LogFilter::All, &holds), None).await.unwrap(), 2);
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn holds_survive_reopening() {
+        let dir = TempDir::new();
+        let store = SqliteStore::open(&dir.config()).await.unwrap();
+        let hold = store.add_legal_hold("audit", "legal", &hostnames(&["db-*"])).await.unwrap();
+
+        let reopened = SqliteStore::open(&dir.config()).await.unwrap();
+        let holds = reopened.legal_holds().await.unwrap();
+        assert_eq!(holds.len(), 1);
+        assert_eq!(holds[0].id, hold.id);
+        assert_eq!(holds[0].criteria, hold.criteria);
+    }
+}
@@ -0,0 +1,492 @@
+use super::{
+    hold,
+    rollup::{self, GroupBy, TimeSeriesPoint, TimeSeriesQuery},
+    Cursor, HoldCriteria, LegalHold, LogEntry, LogFilter, LogStore, MessageCounts, MessageTotals, Page, StoreStats,
+};
+use crate::{
+    search::highlight,
+    signing::{SignatureStatus, SignatureUpdate},
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+use syslog_parser::SyslogMessage;
+
+/// Messages held only in memory, in a ring buffer that forgets the oldest
+/// received message once `capacity` is reached. Nothing touches the disk, and
+/// everything is lost on exit.
+///
+/// Messages under a legal hold are never forgotten, so the buffer outgrows
+/// `capacity` rather than evict them.
+#[derive(Debug)]
+pub struct MemoryStore {
+    capacity: usize,
+    ring: RwLock<Ring>,
+    holds: RwLock<Vec<LegalHold>>,
+}
+
+#[derive(Debug, Default)]
+struct Ring {
+    /// In the order received
+    entries: VecDeque<Entry>,
+    counts: MessageTotals,
+    last_seq: i64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    seq: i64,
+    message: SyslogMessage,
+    signature_status: SignatureStatus,
+    signature_detail: Option<String>,
+    deleted_at: Option<DateTime<Utc>>,
+}
+
+impl Entry {
+    fn matches(&self, filter: &LogFilter) -> bool {
+        filter.matches(&self.message, self.signature_status, self.deleted_at)
+    }
+
+    fn to_log_entry(&self, snippet: Option<String>) -> LogEntry {
+        LogEntry {
+            seq: self.seq,
+            message: self.message.clone(),
+            snippet,
+            signature_status: Some(self.signature_status),
+            signature_detail: self.signature_detail.clone(),
+            deleted_at: self.deleted_at,
+        }
+    }
+}
+
+impl MemoryStore {
+    pub fn new(capacity: usize) -> MemoryStore {
+        MemoryStore {
+            capacity: capacity.max(1),
+            ring: RwLock::new(Ring::default()),
+            holds: RwLock::new(Vec::new()),
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Ring> {
+        self.ring.read().unwrap()
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Ring> {
+        self.ring.write().unwrap()
+    }
+}
+
+impl Ring {
+    fn matching<'a>(&'a self, filter: &'a LogFilter) -> impl DoubleEndedIterator<Item = &'a Entry> + 'a {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.matches(filter))
+    }
+
+    /// Removes the entries at these positions, which must be ascending
+    fn remove(&mut self, positions: &[usize]) -> u64 {
+        let Ring { entries, counts, .. } = self;
+        let mut position = 0;
+        entries.retain(|entry| {
+            let keep = positions.binary_search(&position).is_err();
+            if !keep && entry.deleted_at.is_none() {
+                uncount(&mut counts.stored, &entry.message);
+            }
+            position += 1;
+            keep
+        });
+        positions.len() as u64
+    }
+}
+
+fn count(counts: &mut MessageCounts, message: &SyslogMessage) {
+    counts.total_messages += 1;
+    *counts.messages_per_facility.entry(message.facility).or_default() += 1;
+    *counts.messages_per_severity.entry(message.severity).or_default() += 1;
+    *counts.messages_per_source.entry(message.source_ip.clone()).or_default() += 1;
+    if message.parse_error.is_some() {
+        counts.flagged_messages += 1;
+    }
+}
+
+/// Takes a message back out of `count`, forgetting values with nothing left
+fn uncount(counts: &mut MessageCounts, message: &SyslogMessage) {
+    fn decrement<K: Hash + Eq>(map: &mut HashMap<K, u64>, key: K) {
+        if let Some(count) = map.get_mut(&key) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                map.remove(&key);
+            }
+        }
+    }
+
+    counts.total_messages = counts.total_messages.saturating_sub(1);
+    decrement(&mut counts.messages_per_facility, message.facility);
+    decrement(&mut counts.messages_per_severity, message.severity);
+    decrement(&mut counts.messages_per_source, message.source_ip.clone());
+    if message.parse_error.is_some() {
+        counts.flagged_messages = counts.flagged_messages.saturating_sub(1);
+    }
+}
+
+fn group_value(group_by: GroupBy, message: &SyslogMessage) -> String {
+    match group_by {
+        GroupBy::SourceIp => message.source_ip.clone(),
+        GroupBy::Hostname => message.hostname.clone().unwrap_or_default(),
+        GroupBy::AppName => message.app_name.clone().unwrap_or_default(),
+        GroupBy::Facility => message.facility.to_string(),
+        GroupBy::Severity => message.severity.to_string(),
+    }
+}
+
+#[async_trait]
+impl LogStore for MemoryStore {
+    async fn insert_batch(&self, messages: &[SyslogMessage]) -> Result<()> {
+        let holds = self.holds.read().unwrap();
+        let evictable = holds.iter().any(LegalHold::is_active).then(|| hold::exempt(LogFilter::All, &holds));
+        let mut ring = self.write();
+        for message in messages {
+            if ring.entries.len() >= self.capacity {
+                let oldest = match &evictable {
+                    Some(filter) => ring.entries.iter().position(|entry| entry.matches(filter)),
+                    None => Some(0),
+                };
+                if let Some(evicted) = oldest.and_then(|position| ring.entries.remove(position)) {
+                    if evicted.deleted_at.is_none() {
+                        uncount(&mut ring.counts.stored, &evicted.message);
+                    }
+                }
+            }
+            count(&mut ring.counts.all_time, message);
+            count(&mut ring.counts.stored, message);
+            ring.last_seq += 1;
+            let seq = ring.last_seq;
+            ring.entries.push_back(Entry {
+                seq,
+                message: message.clone(),
+                signature_status: SignatureStatus::Unsigned,
+                signature_detail: None,
+                deleted_at: None,
+            });
+        }
+        Ok(())
+    }
+
+    async fn query(&self, filter: &LogFilter, page: Page) -> Result<Vec<LogEntry>> {
+        let ring = self.read();
+        // Entries are already in seq order, so paging is a walk from one end
+        let matches = ring.matching(filter);
+        let matches: Box<dyn Iterator<Item = &Entry>> = match page.cursor {
+            Some(Cursor::After(seq)) => Box::new(matches.filter(move |entry| entry.seq > seq)),
+            Some(Cursor::Before(seq)) => Box::new(matches.rev().filter(move |entry| entry.seq < seq)),
+            None => Box::new(matches.rev()),
+        };
+
+        let search = filter.search();
+        Ok(matches
+            .skip(page.offset)
+            .take(page.limit)
+            .map(|entry| entry.to_log_entry(search.map(|search| highlight(&search.snippet(&entry.message.message)))))
+            .collect())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<SyslogMessage>> {
+        let ring = self.read();
+        Ok(ring
+            .entries
+            .iter()
+            .find(|entry| entry.message.id == id && entry.deleted_at.is_none())
+            .map(|entry| entry.message.clone()))
+    }
+
+    async fn oldest(&self, filter: &LogFilter, limit: usize) -> Result<Vec<LogEntry>> {
+        let ring = self.read();
+        let mut matches: Vec<&Entry> = ring.matching(filter).collect();
+        matches.sort_by_key(|entry| entry.message.timestamp);
+        Ok(matches.into_iter().take(limit).map(|entry| entry.to_log_entry(None)).collect())
+    }
+
+    async fn delete(&self, filter: &LogFilter, limit: Option<u64>) -> Result<u64> {
+        let mut ring = self.write();
+        let mut positions: Vec<usize> = ring
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.matches(filter))
+            .map(|(position, _)| position)
+            .collect();
+        if let Some(limit) = limit {
+            positions.sort_by_key(|&position| ring.entries[position].message.timestamp);
+            positions.truncate(limit as usize);
+            positions.sort_unstable();
+        }
+        Ok(ring.remove(&positions))
+    }
+
+    async fn count(&self, filter: &LogFilter) -> Result<u64> {
+        Ok(self.read().matching(filter).count() as u64)
+    }
+
+    async fn trash(&self, filter: &LogFilter, limit: Option<u64>) -> Result<u64> {
+        let mut ring = self.write();
+        let Ring { entries, counts, .. } = &mut *ring;
+        let mut matches: Vec<&mut Entry> = entries
+            .iter_mut()
+            .filter(|entry| entry.deleted_at.is_none() && entry.matches(filter))
+            .collect();
+        if let Some(limit) = limit {
+            matches.sort_by_key(|entry| entry.message.timestamp);
+            matches.truncate(limit as usize);
+        }
+
+        let now = Utc::now();
+        for entry in &mut matches {
+            entry.deleted_at = Some(now);
+            uncount(&mut counts.stored, &entry.message);
+        }
+        Ok(matches.len() as u64)
+    }
+
+    async fn restore(&self, filter: &LogFilter) -> Result<u64> {
+        let mut ring = self.write();
+        let Ring { entries, counts, .. } = &mut *ring;
+        let mut restored = 0;
+        for entry in entries.iter_mut().filter(|entry| entry.deleted_at.is_some() && entry.matches(filter)) {
+            entry.deleted_at = None;
+            count(&mut counts.stored, &entry.message);
+            restored += 1;
+        }
+        Ok(restored)
+    }
+
+    async fn legal_holds(&self) -> Result<Vec<LegalHold>> {
+        Ok(self.holds.read().unwrap().clone())
+    }
+
+    async fn add_legal_hold(&self, reason: &str, owner: &str, criteria: &HoldCriteria) -> Result<LegalHold> {
+        let mut holds = self.holds.write().unwrap();
+        let hold = LegalHold {
+            id: holds.last().map_or(1, |hold| hold.id + 1),
+            reason: reason.to_string(),
+            owner: owner.to_string(),
+            created_at: Utc::now(),
+            released_at: None,
+            criteria: criteria.clone(),
+        };
+        holds.push(hold.clone());
+        Ok(hold)
+    }
+
+    async fn release_legal_hold(&self, id: i64) -> Result<bool> {
+        let mut holds = self.holds.write().unwrap();
+        match holds.iter_mut().find(|hold| hold.id == id && hold.is_active()) {
+            Some(hold) => {
+                hold.released_at = Some(Utc::now());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn set_signatures(&self, updates: &[SignatureUpdate]) -> Result<()> {
+        let mut ring = self.write();
+        for update in updates {
+            // Recent messages are the likely ones, so search from the back
+            if let Some(entry) = ring.entries.iter_mut().rev().find(|entry| entry.message.id == update.id) {
+                entry.signature_status = update.status;
+                entry.signature_detail = update.detail.clone();
+            }
+        }
+        Ok(())
+    }
+
+    async fn stats(&self) -> Result<StoreStats> {
+        let ring = self.read();
+        let trashed_messages = ring.entries.iter().filter(|entry| entry.deleted_at.is_some()).count() as u64;
+        Ok(StoreStats {
+            backend: "memory",
+            stored_messages: ring.entries.len() as u64 - trashed_messages,
+            trashed_messages,
+            used_bytes: None,
+            partitions: None,
+            raw_bytes_saved: None,
+        })
+    }
+
+    async fn counts(&self) -> Result<MessageTotals> {
+        Ok(self.read().counts.clone())
+    }
+
+    /// Counted from the buffer, so the series only reaches back as far as the oldest message held
+    async fn timeseries(&self, query: &TimeSeriesQuery) -> Result<Vec<TimeSeriesPoint>> {
+        let since = query.resolution.floor(query.since);
+        let ring = self.read();
+        let points = ring
+            .matching(&query.filter)
+            .filter(|entry| entry.message.timestamp >= since && entry.message.timestamp < query.until)
+            .map(|entry| TimeSeriesPoint {
+                bucket: query.resolution.floor(entry.message.timestamp),
+                group: query.group_by.map(|group_by| group_value(group_by, &entry.message)),
+                count: 1,
+            });
+        Ok(rollup::merge_points(points))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        search::SearchQuery,
+        store::testing::{day, for_each_backend, message, Backend},
+    };
+    use chrono::Duration;
+    use std::cell::RefCell;
+
+    /// Hostname, app name, facility, severity, source address and text
+    type Row = (Option<&'static str>, Option<&'static str>, u8, u8, &'static str, &'static str);
+
+    /// Messages that exercise the corners of each filter: missing and mixed-case
+    /// hostnames, LIKE metacharacters, and text for words, phrases and prefixes
+    fn fixture() -> Vec<SyslogMessage> {
+        let rows: [Row; 12] = [
+            (Some("web-01"), Some("nginx"), 1, 6, "10.0.0.5", "GET /index.html 200"),
+            (Some("WEB-02"), Some("nginx"), 1, 4, "10.0.0.6", "upstream timeout while reading"),
+            (None, Some("kernel"), 0, 2, "10.0.0.7", "Out of memory: killed process 4242"),
+            (Some("db_1"), Some("postgres"), 16, 3, "10.0.0.8", "connection refused by 10.0.0.5"),
+            (Some("dbx1"), Some("postgres"), 16, 6, "10.0.0.8", "checkpoint complete"),
+            (Some("mail%relay"), Some("postfix"), 2, 5, "10.0.0.9", "Connection timed out"),
+            (Some("gateway"), Some("sshd"), 4, 5, "10.0.0.10", "Accepted publickey for admin"),
+            (Some("gateway"), Some("sshd"), 10, 4, "10.0.0.10", "Failed password: connection refused"),
+            (None, None, 1, 7, "10.0.0.11", "debug tick"),
+            (Some(""), Some("cron"), 9, 6, "10.0.0.12", "(root) CMD /var/log/rotate"),
+            (Some("web-03"), Some("nginx_err"), 1, 3, "10.0.0.5", "connecting to upstream refused"),
+            (Some("Gateway"), Some("sshd"), 4, 6, "10.0.0.10", "session opened for web"),
+        ];
+        rows.iter()
+            .enumerate()
+            .map(|(i, &(hostname, app_name, facility, severity, source_ip, text))| {
+                let mut message = message(day(0) + Duration::minutes(i as i64), hostname, text);
+                message.app_name = app_name.map(str::to_string);
+                message.facility = facility;
+                message.severity = severity;
+                message.source_ip = source_ip.to_string();
+                if hostname.is_none() {
+                    message.parse_error = Some("missing hostname".to_string());
+                }
+                message
+            })
+            .collect()
+    }
+
+    fn search(input: &str) -> LogFilter {
+        LogFilter::Search(SearchQuery::parse(input).unwrap())
+    }
+
+    fn names(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    fn filters(messages: &[SyslogMessage]) -> Vec<(&'static str, LogFilter)> {
+        vec![
+            ("all", LogFilter::All),
+            ("hostname wildcard", LogFilter::Hostname(names(&["web-*"]))),
+            ("hostname case", LogFilter::Hostname(names(&["gateway"]))),
+            ("hostname underscore", LogFilter::Hostname(names(&["db_1"]))),
+            ("hostname percent", LogFilter::Hostname(names(&["mail%relay"]))),
+            ("hostname star", LogFilter::Hostname(names(&["*"]))),
+            ("hostname empty", LogFilter::Hostname(names(&[""]))),
+            ("not hostname", LogFilter::Hostname(names(&["web-*", "gate*"])).negate()),
+            ("not hostname star", LogFilter::Hostname(names(&["*"])).negate()),
+            ("app name", LogFilter::AppName(names(&["nginx*"]))),
+            ("not app name", LogFilter::AppName(names(&["sshd"])).negate()),
+            ("facility", LogFilter::Facility(vec![1, 16])),
+            ("severity", LogFilter::Severity(vec![0, 1, 2, 3])),
+            ("not severity", LogFilter::Severity(vec![6]).negate()),
+            ("source ip", LogFilter::SourceIp(names(&["10.0.0.5", "10.0.0.10"]))),
+            ("id", LogFilter::Id(vec![messages[3].id.clone(), messages[8].id.clone()])),
+            ("since", LogFilter::Since(messages[4].timestamp)),
+            ("before", LogFilter::Before(messages[4].timestamp)),
+            ("flagged", LogFilter::Flagged(true)),
+            ("not flagged", LogFilter::Flagged(false)),
+            ("verified", LogFilter::Signature(SignatureStatus::Verified)),
+            ("unsigned", LogFilter::Signature(SignatureStatus::Unsigned)),
+            ("trashed", LogFilter::Trashed(true)),
+            ("not trashed", LogFilter::Trashed(false)),
+            ("word", search("refused")),
+            ("word case", search("CONNECTION")),
+            ("phrase", search("\"connection refused\"")),
+            ("prefix", search("conn*")),
+            ("or", search("timeout OR memory")),
+            ("not term", search("connection NOT refused")),
+            ("address", search("10.0.0.5")),
+            ("path", search("/var/log")),
+            ("hostname term", search("web")),
+            ("app name term", search("postgres")),
+            ("not search", search("refused").negate()),
+            ("hostname and search", LogFilter::Hostname(names(&["gateway"])).and(search("connection"))),
+            (
+                "not hostname and search",
+                LogFilter::Hostname(names(&["web-*"])).negate().and(search("refused OR tick")),
+            ),
+            (
+                "not both",
+                LogFilter::And(vec![LogFilter::AppName(names(&["nginx"])), LogFilter::Severity(vec![6])]).negate(),
+            ),
+        ]
+    }
+
+    async fn ids(store: &dyn LogStore, filter: &LogFilter) -> Vec<String> {
+        let page = Page { limit: 1000, offset: 0, cursor: None };
+        let mut ids: Vec<String> =
+            store.query(filter, page).await.unwrap().into_iter().map(|entry| entry.message.id).collect();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn filters_match_sqlite() {
+        let messages = fixture();
+        let filters = filters(&messages);
+        // What each backend found, by filter
+        let found = RefCell::new(Vec::new());
+
+        for_each_backend(async |store, backend| {
+            store.insert_batch(&messages).await.unwrap();
+            let updates: Vec<SignatureUpdate> = messages[..3]
+                .iter()
+                .map(|message| SignatureUpdate {
+                    id: message.id.clone(),
+                    timestamp: message.timestamp,
+                    status: SignatureStatus::Verified,
+                    detail: None,
+                })
+                .collect();
+            store.set_signatures(&updates).await.unwrap();
+            store.trash(&LogFilter::Id(vec![messages[7].id.clone()]), None).await.unwrap();
+
+            let mut matches = Vec::new();
+            for (name, filter) in &filters {
+                let matched = ids(store, filter).await;
+                assert_eq!(store.count(filter).await.unwrap(), matched.len() as u64, "{name}");
+                matches.push(matched);
+            }
+            found.borrow_mut().push((backend, matches));
+        })
+        .await;
+
+        let found = found.into_inner();
+        let (_, expected) = found.iter().find(|(backend, _)| *backend == Backend::Sqlite).unwrap();
+        for (backend, matches) in &found {
+            for ((name, _), (matched, expected)) in filters.iter().zip(matches.iter().zip(expected)) {
+                assert_eq!(matched, expected, "{name} on the {backend:?} backend");
+            }
+        }
+    }
+}
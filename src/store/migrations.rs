@@ -0,0 +1,1073 @@
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use sqlx::{postgres::PgPool, sqlite::SqlitePool, PgConnection, SqliteConnection};
+use tracing::info;
+
+/// One step of a migration. Steps are written so they can be replayed against
+/// databases that predate version tracking, which may already have some of them.
+enum Step {
+    Sql(&'static str),
+    /// `ALTER TABLE ... ADD COLUMN`, skipped when the column already exists
+    AddColumn {
+        table: &'static str,
+        column: &'static str,
+        definition: &'static str,
+    },
+}
+
+struct Migration {
+    version: i64,
+    description: &'static str,
+    steps: &'static [Step],
+}
+
+/// Every SQLite schema change, in order. Append new migrations here; never edit one that has shipped.
+const SQLITE_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create syslog_messages",
+        steps: &[
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS syslog_messages (
+                    id TEXT PRIMARY KEY,
+                    timestamp TEXT NOT NULL,
+                    facility INTEGER NOT NULL,
+                    severity INTEGER NOT NULL,
+                    hostname TEXT,
+                    app_name TEXT,
+                    proc_id TEXT,
+                    msg_id TEXT,
+                    message TEXT NOT NULL,
+                    raw_message TEXT NOT NULL,
+                    source_ip TEXT NOT NULL,
+                    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+                )
+                "#,
+            ),
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_timestamp ON syslog_messages(timestamp)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_facility ON syslog_messages(facility)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_severity ON syslog_messages(severity)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_source_ip ON syslog_messages(source_ip)"),
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "record parse errors and original bytes",
+        steps: &[
+            Step::AddColumn { table: "syslog_messages", column: "parse_error", definition: "TEXT" },
+            Step::AddColumn { table: "syslog_messages", column: "original_bytes", definition: "BLOB" },
+        ],
+    },
+    Migration {
+        version: 3,
+        description: "store RFC 5424 structured data",
+        steps: &[Step::AddColumn { table: "syslog_messages", column: "structured_data", definition: "TEXT" }],
+    },
+    Migration {
+        version: 4,
+        description: "full-text index on message, hostname and app_name",
+        steps: &[
+            // Keyed by the implicit rowid until version 13 moves it to seq
+            Step::Sql(
+                r#"
+                CREATE VIRTUAL TABLE IF NOT EXISTS syslog_fts USING fts5(
+                    message, hostname, app_name,
+                    content = 'syslog_messages',
+                    content_rowid = 'rowid',
+                    prefix = '2 3'
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TRIGGER IF NOT EXISTS syslog_fts_insert AFTER INSERT ON syslog_messages BEGIN
+                    INSERT INTO syslog_fts(rowid, message, hostname, app_name)
+                    VALUES (new.rowid, new.message, new.hostname, new.app_name);
+                END
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TRIGGER IF NOT EXISTS syslog_fts_delete AFTER DELETE ON syslog_messages BEGIN
+                    INSERT INTO syslog_fts(syslog_fts, rowid, message, hostname, app_name)
+                    VALUES ('delete', old.rowid, old.message, old.hostname, old.app_name);
+                END
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TRIGGER IF NOT EXISTS syslog_fts_update AFTER UPDATE OF message, hostname, app_name ON syslog_messages BEGIN
+                    INSERT INTO syslog_fts(syslog_fts, rowid, message, hostname, app_name)
+                    VALUES ('delete', old.rowid, old.message, old.hostname, old.app_name);
+                    INSERT INTO syslog_fts(rowid, message, hostname, app_name)
+                    VALUES (new.rowid, new.message, new.hostname, new.app_name);
+                END
+                "#,
+            ),
+            // Index the rows stored before the triggers existed
+            Step::Sql("INSERT INTO syslog_fts(syslog_fts) VALUES('rebuild')"),
+        ],
+    },
+    Migration {
+        version: 5,
+        description: "zstd-compressed raw_message",
+        steps: &[
+            // A compressed row keeps an empty raw_message, which is NOT NULL
+            Step::AddColumn { table: "syslog_messages", column: "raw_compressed", definition: "BLOB" },
+            Step::AddColumn { table: "syslog_messages", column: "raw_length", definition: "INTEGER" },
+            Step::AddColumn { table: "syslog_messages", column: "raw_dictionary", definition: "INTEGER" },
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS raw_dictionaries (
+                    id INTEGER PRIMARY KEY,
+                    dictionary BLOB NOT NULL,
+                    samples INTEGER NOT NULL,
+                    created_at TEXT NOT NULL
+                )
+                "#,
+            ),
+        ],
+    },
+    Migration {
+        version: 6,
+        description: "hash chain and signed chain events",
+        steps: &[
+            // Rows stored before the chain was enabled keep NULLs
+            Step::AddColumn { table: "syslog_messages", column: "chain_seq", definition: "INTEGER" },
+            Step::AddColumn { table: "syslog_messages", column: "prev_hash", definition: "TEXT" },
+            Step::AddColumn { table: "syslog_messages", column: "hash", definition: "TEXT" },
+            Step::Sql("CREATE UNIQUE INDEX IF NOT EXISTS idx_chain_seq ON syslog_messages(chain_seq)"),
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS chain_events (
+                    id INTEGER PRIMARY KEY,
+                    kind TEXT NOT NULL,
+                    created_at TEXT NOT NULL,
+                    head_seq INTEGER NOT NULL,
+                    head_hash TEXT NOT NULL,
+                    detail TEXT NOT NULL,
+                    signature TEXT NOT NULL
+                )
+                "#,
+            ),
+        ],
+    },
+    Migration {
+        version: 7,
+        description: "RFC 5848 signature status",
+        steps: &[
+            Step::AddColumn {
+                table: "syslog_messages",
+                column: "signature_status",
+                definition: "TEXT NOT NULL DEFAULT 'unsigned'",
+            },
+            Step::AddColumn { table: "syslog_messages", column: "signature_detail", definition: "TEXT" },
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_signature_status ON syslog_messages(signature_status)"),
+        ],
+    },
+    Migration {
+        version: 8,
+        description: "message counts kept up to date by triggers",
+        steps: &[
+            // `all_time` only ever grows; `stored` follows deletes
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS message_counts (
+                    dimension TEXT NOT NULL,
+                    value TEXT NOT NULL,
+                    all_time INTEGER NOT NULL DEFAULT 0,
+                    stored INTEGER NOT NULL DEFAULT 0,
+                    PRIMARY KEY (dimension, value)
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TRIGGER IF NOT EXISTS message_counts_insert AFTER INSERT ON syslog_messages BEGIN
+                    INSERT INTO message_counts (dimension, value, all_time, stored)
+                    VALUES ('total', '', 1, 1), ('facility', CAST(new.facility AS TEXT), 1, 1),
+                           ('severity', CAST(new.severity AS TEXT), 1, 1), ('source', new.source_ip, 1, 1)
+                    ON CONFLICT (dimension, value) DO UPDATE SET all_time = all_time + 1, stored = stored + 1;
+                    INSERT INTO message_counts (dimension, value, all_time, stored)
+                    SELECT 'flagged', '', 1, 1 WHERE new.parse_error IS NOT NULL
+                    ON CONFLICT (dimension, value) DO UPDATE SET all_time = all_time + 1, stored = stored + 1;
+                END
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TRIGGER IF NOT EXISTS message_counts_delete AFTER DELETE ON syslog_messages BEGIN
+                    UPDATE message_counts SET stored = stored - 1
+                    WHERE (dimension = 'total' AND value = '')
+                       OR (dimension = 'facility' AND value = CAST(old.facility AS TEXT))
+                       OR (dimension = 'severity' AND value = CAST(old.severity AS TEXT))
+                       OR (dimension = 'source' AND value = old.source_ip)
+                       OR (dimension = 'flagged' AND value = '' AND old.parse_error IS NOT NULL);
+                END
+                "#,
+            ),
+            // Messages stored before the counts existed; earlier deletes are lost
+            Step::Sql("DELETE FROM message_counts"),
+            Step::Sql(
+                r#"
+                INSERT INTO message_counts (dimension, value, all_time, stored)
+                SELECT 'total', '', COUNT(*), COUNT(*) FROM syslog_messages
+                UNION ALL SELECT 'facility', CAST(facility AS TEXT), COUNT(*), COUNT(*) FROM syslog_messages GROUP BY facility
+                UNION ALL SELECT 'severity', CAST(severity AS TEXT), COUNT(*), COUNT(*) FROM syslog_messages GROUP BY severity
+                UNION ALL SELECT 'source', source_ip, COUNT(*), COUNT(*) FROM syslog_messages GROUP BY source_ip
+                UNION ALL SELECT 'flagged', '', COUNT(*), COUNT(*) FROM syslog_messages WHERE parse_error IS NOT NULL
+                "#,
+            ),
+        ],
+    },
+    Migration {
+        version: 9,
+        description: "per-minute, hourly and daily rollups",
+        steps: &[
+            // Timestamps are stored as RFC 3339 in UTC, so a bucket is a prefix of
+            // one, kept in the same format for comparisons against bound instants
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS rollup_minute (
+                    bucket TEXT NOT NULL,
+                    source_ip TEXT NOT NULL,
+                    hostname TEXT NOT NULL,
+                    app_name TEXT NOT NULL,
+                    facility INTEGER NOT NULL,
+                    severity INTEGER NOT NULL,
+                    count INTEGER NOT NULL,
+                    PRIMARY KEY (bucket, source_ip, hostname, app_name, facility, severity)
+                ) WITHOUT ROWID
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS rollup_hour (
+                    bucket TEXT NOT NULL,
+                    source_ip TEXT NOT NULL,
+                    hostname TEXT NOT NULL,
+                    app_name TEXT NOT NULL,
+                    facility INTEGER NOT NULL,
+                    severity INTEGER NOT NULL,
+                    count INTEGER NOT NULL,
+                    PRIMARY KEY (bucket, source_ip, hostname, app_name, facility, severity)
+                ) WITHOUT ROWID
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS rollup_day (
+                    bucket TEXT NOT NULL,
+                    source_ip TEXT NOT NULL,
+                    hostname TEXT NOT NULL,
+                    app_name TEXT NOT NULL,
+                    facility INTEGER NOT NULL,
+                    severity INTEGER NOT NULL,
+                    count INTEGER NOT NULL,
+                    PRIMARY KEY (bucket, source_ip, hostname, app_name, facility, severity)
+                ) WITHOUT ROWID
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TRIGGER IF NOT EXISTS rollups_insert AFTER INSERT ON syslog_messages BEGIN
+                    INSERT INTO rollup_minute (bucket, source_ip, hostname, app_name, facility, severity, count)
+                    VALUES (substr(new.timestamp, 1, 16) || ':00+00:00', new.source_ip, COALESCE(new.hostname, ''), COALESCE(new.app_name, ''), new.facility, new.severity, 1)
+                    ON CONFLICT (bucket, source_ip, hostname, app_name, facility, severity) DO UPDATE SET count = count + 1;
+                    INSERT INTO rollup_hour (bucket, source_ip, hostname, app_name, facility, severity, count)
+                    VALUES (substr(new.timestamp, 1, 13) || ':00:00+00:00', new.source_ip, COALESCE(new.hostname, ''), COALESCE(new.app_name, ''), new.facility, new.severity, 1)
+                    ON CONFLICT (bucket, source_ip, hostname, app_name, facility, severity) DO UPDATE SET count = count + 1;
+                    INSERT INTO rollup_day (bucket, source_ip, hostname, app_name, facility, severity, count)
+                    VALUES (substr(new.timestamp, 1, 10) || 'T00:00:00+00:00', new.source_ip, COALESCE(new.hostname, ''), COALESCE(new.app_name, ''), new.facility, new.severity, 1)
+                    ON CONFLICT (bucket, source_ip, hostname, app_name, facility, severity) DO UPDATE SET count = count + 1;
+                END
+                "#,
+            ),
+            // Older history was never rolled up, and retention may already have taken it
+            Step::Sql("DELETE FROM rollup_minute"),
+            Step::Sql("DELETE FROM rollup_hour"),
+            Step::Sql("DELETE FROM rollup_day"),
+            Step::Sql(
+                r#"
+                INSERT INTO rollup_minute (bucket, source_ip, hostname, app_name, facility, severity, count)
+                SELECT substr(timestamp, 1, 16) || ':00+00:00', source_ip, COALESCE(hostname, ''), COALESCE(app_name, ''), facility, severity, COUNT(*)
+                FROM syslog_messages GROUP BY 1, 2, 3, 4, 5, 6
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                INSERT INTO rollup_hour (bucket, source_ip, hostname, app_name, facility, severity, count)
+                SELECT substr(timestamp, 1, 13) || ':00:00+00:00', source_ip, COALESCE(hostname, ''), COALESCE(app_name, ''), facility, severity, COUNT(*)
+                FROM syslog_messages GROUP BY 1, 2, 3, 4, 5, 6
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                INSERT INTO rollup_day (bucket, source_ip, hostname, app_name, facility, severity, count)
+                SELECT substr(timestamp, 1, 10) || 'T00:00:00+00:00', source_ip, COALESCE(hostname, ''), COALESCE(app_name, ''), facility, severity, COUNT(*)
+                FROM syslog_messages GROUP BY 1, 2, 3, 4, 5, 6
+                "#,
+            ),
+        ],
+    },
+    Migration {
+        version: 10,
+        description: "monotonic message sequence numbers",
+        steps: &[
+            Step::AddColumn { table: "syslog_messages", column: "seq", definition: "INTEGER" },
+            // The next seq is kept apart from the rows, so that deleting the newest never frees its number
+            Step::Sql("CREATE TABLE IF NOT EXISTS sequences (name TEXT PRIMARY KEY, value INTEGER NOT NULL)"),
+            Step::Sql("UPDATE syslog_messages SET seq = rowid WHERE seq IS NULL"),
+            Step::Sql(
+                r#"
+                INSERT INTO sequences (name, value) SELECT 'messages', COALESCE(MAX(seq), 0) FROM syslog_messages WHERE true
+                ON CONFLICT (name) DO UPDATE SET value = MAX(value, excluded.value)
+                "#,
+            ),
+            Step::Sql("CREATE UNIQUE INDEX IF NOT EXISTS idx_seq ON syslog_messages(seq)"),
+        ],
+    },
+    Migration {
+        version: 11,
+        description: "trash for soft-deleted messages",
+        steps: &[
+            Step::AddColumn { table: "syslog_messages", column: "deleted_at", definition: "TEXT" },
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_deleted_at ON syslog_messages(deleted_at) WHERE deleted_at IS NOT NULL"),
+            // Trashed messages already left the stored counts, so purging them must not count them again
+            Step::Sql("DROP TRIGGER IF EXISTS message_counts_delete"),
+            Step::Sql(
+                r#"
+                CREATE TRIGGER message_counts_delete AFTER DELETE ON syslog_messages WHEN old.deleted_at IS NULL BEGIN
+                    UPDATE message_counts SET stored = stored - 1
+                    WHERE (dimension = 'total' AND value = '')
+                       OR (dimension = 'facility' AND value = CAST(old.facility AS TEXT))
+                       OR (dimension = 'severity' AND value = CAST(old.severity AS TEXT))
+                       OR (dimension = 'source' AND value = old.source_ip)
+                       OR (dimension = 'flagged' AND value = '' AND old.parse_error IS NOT NULL);
+                END
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TRIGGER IF NOT EXISTS message_counts_trash AFTER UPDATE OF deleted_at ON syslog_messages
+                WHEN (old.deleted_at IS NULL) <> (new.deleted_at IS NULL) BEGIN
+                    UPDATE message_counts SET stored = stored + CASE WHEN new.deleted_at IS NULL THEN 1 ELSE -1 END
+                    WHERE (dimension = 'total' AND value = '')
+                       OR (dimension = 'facility' AND value = CAST(new.facility AS TEXT))
+                       OR (dimension = 'severity' AND value = CAST(new.severity AS TEXT))
+                       OR (dimension = 'source' AND value = new.source_ip)
+                       OR (dimension = 'flagged' AND value = '' AND new.parse_error IS NOT NULL);
+                END
+                "#,
+            ),
+        ],
+    },
+    Migration {
+        version: 12,
+        description: "legal holds",
+        steps: &[Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS legal_holds (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                reason TEXT NOT NULL,
+                owner TEXT NOT NULL,
+                criteria TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                released_at TEXT
+            )
+            "#,
+        )],
+    },
+    Migration {
+        version: 13,
+        description: "full-text index keyed on seq",
+        steps: &[
+            // The implicit rowid of a table with a TEXT primary key may be renumbered
+            // by VACUUM, which would point the index at the wrong rows
+            Step::Sql("DROP TRIGGER IF EXISTS syslog_fts_insert"),
+            Step::Sql("DROP TRIGGER IF EXISTS syslog_fts_delete"),
+            Step::Sql("DROP TRIGGER IF EXISTS syslog_fts_update"),
+            Step::Sql("DROP TABLE IF EXISTS syslog_fts"),
+            Step::Sql(
+                r#"
+                CREATE VIRTUAL TABLE syslog_fts USING fts5(
+                    message, hostname, app_name,
+                    content = 'syslog_messages',
+                    content_rowid = 'seq',
+                    prefix = '2 3'
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TRIGGER syslog_fts_insert AFTER INSERT ON syslog_messages BEGIN
+                    INSERT INTO syslog_fts(rowid, message, hostname, app_name)
+                    VALUES (new.seq, new.message, new.hostname, new.app_name);
+                END
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TRIGGER syslog_fts_delete AFTER DELETE ON syslog_messages BEGIN
+                    INSERT INTO syslog_fts(syslog_fts, rowid, message, hostname, app_name)
+                    VALUES ('delete', old.seq, old.message, old.hostname, old.app_name);
+                END
+                "#,
+            ),
+            // Renumbering partitions changes seq, which moves the entry too
+            Step::Sql(
+                r#"
+                CREATE TRIGGER syslog_fts_update AFTER UPDATE OF message, hostname, app_name, seq ON syslog_messages BEGIN
+                    INSERT INTO syslog_fts(syslog_fts, rowid, message, hostname, app_name)
+                    VALUES ('delete', old.seq, old.message, old.hostname, old.app_name);
+                    INSERT INTO syslog_fts(rowid, message, hostname, app_name)
+                    VALUES (new.seq, new.message, new.hostname, new.app_name);
+                END
+                "#,
+            ),
+            Step::Sql("INSERT INTO syslog_fts(syslog_fts) VALUES('rebuild')"),
+        ],
+    },
+];
+
+/// The PostgreSQL schema, which started out with everything SQLite gained over time
+const POSTGRES_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create syslog_messages with a full-text search vector",
+        steps: &[
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS syslog_messages (
+                    id TEXT PRIMARY KEY,
+                    timestamp TIMESTAMPTZ NOT NULL,
+                    facility SMALLINT NOT NULL,
+                    severity SMALLINT NOT NULL,
+                    hostname TEXT,
+                    app_name TEXT,
+                    proc_id TEXT,
+                    msg_id TEXT,
+                    structured_data JSONB,
+                    message TEXT NOT NULL,
+                    raw_message TEXT NOT NULL,
+                    source_ip TEXT NOT NULL,
+                    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                    parse_error TEXT,
+                    original_bytes BYTEA,
+                    search_vector TSVECTOR GENERATED ALWAYS AS (
+                        to_tsvector('simple', coalesce(message, '') || ' ' || coalesce(hostname, '') || ' ' || coalesce(app_name, ''))
+                    ) STORED
+                )
+                "#,
+            ),
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_timestamp ON syslog_messages(timestamp)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_facility ON syslog_messages(facility)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_severity ON syslog_messages(severity)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_source_ip ON syslog_messages(source_ip)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_search_vector ON syslog_messages USING GIN (search_vector)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_structured_data ON syslog_messages USING GIN (structured_data)"),
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "RFC 5848 signature status",
+        steps: &[
+            Step::AddColumn {
+                table: "syslog_messages",
+                column: "signature_status",
+                definition: "TEXT NOT NULL DEFAULT 'unsigned'",
+            },
+            Step::AddColumn { table: "syslog_messages", column: "signature_detail", definition: "TEXT" },
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_signature_status ON syslog_messages(signature_status)"),
+        ],
+    },
+    Migration {
+        version: 3,
+        description: "message counts kept up to date by triggers",
+        steps: &[
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS message_counts (
+                    dimension TEXT NOT NULL,
+                    value TEXT NOT NULL,
+                    all_time BIGINT NOT NULL DEFAULT 0,
+                    stored BIGINT NOT NULL DEFAULT 0,
+                    PRIMARY KEY (dimension, value)
+                )
+                "#,
+            ),
+            // Statement-level, so a large delete updates each count once rather than per row
+            Step::Sql(
+                r#"
+                CREATE OR REPLACE FUNCTION message_counts_insert() RETURNS trigger LANGUAGE plpgsql AS $$
+                BEGIN
+                    INSERT INTO message_counts (dimension, value, all_time, stored)
+                    SELECT dimension, value, COUNT(*), COUNT(*) FROM (
+                        SELECT 'total' AS dimension, '' AS value FROM inserted
+                        UNION ALL SELECT 'facility', facility::TEXT FROM inserted
+                        UNION ALL SELECT 'severity', severity::TEXT FROM inserted
+                        UNION ALL SELECT 'source', source_ip FROM inserted
+                        UNION ALL SELECT 'flagged', '' FROM inserted WHERE parse_error IS NOT NULL
+                    ) counted
+                    GROUP BY dimension, value
+                    ON CONFLICT (dimension, value) DO UPDATE
+                    SET all_time = message_counts.all_time + EXCLUDED.all_time, stored = message_counts.stored + EXCLUDED.stored;
+                    RETURN NULL;
+                END
+                $$
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE OR REPLACE FUNCTION message_counts_delete() RETURNS trigger LANGUAGE plpgsql AS $$
+                BEGIN
+                    UPDATE message_counts SET stored = message_counts.stored - counted.removed
+                    FROM (
+                        SELECT dimension, value, COUNT(*) AS removed FROM (
+                            SELECT 'total' AS dimension, '' AS value FROM deleted
+                            UNION ALL SELECT 'facility', facility::TEXT FROM deleted
+                            UNION ALL SELECT 'severity', severity::TEXT FROM deleted
+                            UNION ALL SELECT 'source', source_ip FROM deleted
+                            UNION ALL SELECT 'flagged', '' FROM deleted WHERE parse_error IS NOT NULL
+                        ) removed_rows
+                        GROUP BY dimension, value
+                    ) counted
+                    WHERE message_counts.dimension = counted.dimension AND message_counts.value = counted.value;
+                    RETURN NULL;
+                END
+                $$
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE OR REPLACE TRIGGER message_counts_insert AFTER INSERT ON syslog_messages
+                REFERENCING NEW TABLE AS inserted FOR EACH STATEMENT EXECUTE FUNCTION message_counts_insert()
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE OR REPLACE TRIGGER message_counts_delete AFTER DELETE ON syslog_messages
+                REFERENCING OLD TABLE AS deleted FOR EACH STATEMENT EXECUTE FUNCTION message_counts_delete()
+                "#,
+            ),
+            Step::Sql("DELETE FROM message_counts"),
+            Step::Sql(
+                r#"
+                INSERT INTO message_counts (dimension, value, all_time, stored)
+                SELECT 'total', '', COUNT(*), COUNT(*) FROM syslog_messages
+                UNION ALL SELECT 'facility', facility::TEXT, COUNT(*), COUNT(*) FROM syslog_messages GROUP BY facility
+                UNION ALL SELECT 'severity', severity::TEXT, COUNT(*), COUNT(*) FROM syslog_messages GROUP BY severity
+                UNION ALL SELECT 'source', source_ip, COUNT(*), COUNT(*) FROM syslog_messages GROUP BY source_ip
+                UNION ALL SELECT 'flagged', '', COUNT(*), COUNT(*) FROM syslog_messages WHERE parse_error IS NOT NULL
+                "#,
+            ),
+        ],
+    },
+    Migration {
+        version: 4,
+        description: "per-minute, hourly and daily rollups",
+        steps: &[
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS rollup_minute (
+                    bucket TIMESTAMPTZ NOT NULL,
+                    source_ip TEXT NOT NULL,
+                    hostname TEXT NOT NULL,
+                    app_name TEXT NOT NULL,
+                    facility SMALLINT NOT NULL,
+                    severity SMALLINT NOT NULL,
+                    count BIGINT NOT NULL,
+                    PRIMARY KEY (bucket, source_ip, hostname, app_name, facility, severity)
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS rollup_hour (
+                    bucket TIMESTAMPTZ NOT NULL,
+                    source_ip TEXT NOT NULL,
+                    hostname TEXT NOT NULL,
+                    app_name TEXT NOT NULL,
+                    facility SMALLINT NOT NULL,
+                    severity SMALLINT NOT NULL,
+                    count BIGINT NOT NULL,
+                    PRIMARY KEY (bucket, source_ip, hostname, app_name, facility, severity)
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS rollup_day (
+                    bucket TIMESTAMPTZ NOT NULL,
+                    source_ip TEXT NOT NULL,
+                    hostname TEXT NOT NULL,
+                    app_name TEXT NOT NULL,
+                    facility SMALLINT NOT NULL,
+                    severity SMALLINT NOT NULL,
+                    count BIGINT NOT NULL,
+                    PRIMARY KEY (bucket, source_ip, hostname, app_name, facility, severity)
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE OR REPLACE FUNCTION rollups_insert() RETURNS trigger LANGUAGE plpgsql AS $$
+                BEGIN
+                    INSERT INTO rollup_minute (bucket, source_ip, hostname, app_name, facility, severity, count)
+                    SELECT date_trunc('minute', timestamp, 'UTC'), source_ip, COALESCE(hostname, ''), COALESCE(app_name, ''), facility, severity, COUNT(*)
+                    FROM inserted GROUP BY 1, 2, 3, 4, 5, 6
+                    ON CONFLICT (bucket, source_ip, hostname, app_name, facility, severity) DO UPDATE SET count = rollup_minute.count + EXCLUDED.count;
+                    INSERT INTO rollup_hour (bucket, source_ip, hostname, app_name, facility, severity, count)
+                    SELECT date_trunc('hour', timestamp, 'UTC'), source_ip, COALESCE(hostname, ''), COALESCE(app_name, ''), facility, severity, COUNT(*)
+                    FROM inserted GROUP BY 1, 2, 3, 4, 5, 6
+                    ON CONFLICT (bucket, source_ip, hostname, app_name, facility, severity) DO UPDATE SET count = rollup_hour.count + EXCLUDED.count;
+                    INSERT INTO rollup_day (bucket, source_ip, hostname, app_name, facility, severity, count)
+                    SELECT date_trunc('day', timestamp, 'UTC'), source_ip, COALESCE(hostname, ''), COALESCE(app_name, ''), facility, severity, COUNT(*)
+                    FROM inserted GROUP BY 1, 2, 3, 4, 5, 6
+                    ON CONFLICT (bucket, source_ip, hostname, app_name, facility, severity) DO UPDATE SET count = rollup_day.count + EXCLUDED.count;
+                    RETURN NULL;
+                END
+                $$
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE OR REPLACE TRIGGER rollups_insert AFTER INSERT ON syslog_messages
+                REFERENCING NEW TABLE AS inserted FOR EACH STATEMENT EXECUTE FUNCTION rollups_insert()
+                "#,
+            ),
+            Step::Sql("DELETE FROM rollup_minute"),
+            Step::Sql("DELETE FROM rollup_hour"),
+            Step::Sql("DELETE FROM rollup_day"),
+            Step::Sql(
+                r#"
+                INSERT INTO rollup_minute (bucket, source_ip, hostname, app_name, facility, severity, count)
+                SELECT date_trunc('minute', timestamp, 'UTC'), source_ip, COALESCE(hostname, ''), COALESCE(app_name, ''), facility, severity, COUNT(*)
+                FROM syslog_messages GROUP BY 1, 2, 3, 4, 5, 6
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                INSERT INTO rollup_hour (bucket, source_ip, hostname, app_name, facility, severity, count)
+                SELECT date_trunc('hour', timestamp, 'UTC'), source_ip, COALESCE(hostname, ''), COALESCE(app_name, ''), facility, severity, COUNT(*)
+                FROM syslog_messages GROUP BY 1, 2, 3, 4, 5, 6
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                INSERT INTO rollup_day (bucket, source_ip, hostname, app_name, facility, severity, count)
+                SELECT date_trunc('day', timestamp, 'UTC'), source_ip, COALESCE(hostname, ''), COALESCE(app_name, ''), facility, severity, COUNT(*)
+                FROM syslog_messages GROUP BY 1, 2, 3, 4, 5, 6
+                "#,
+            ),
+        ],
+    },
+    Migration {
+        version: 5,
+        description: "monotonic message sequence numbers",
+        steps: &[
+            // Existing rows are numbered as the column is added
+            Step::AddColumn {
+                table: "syslog_messages",
+                column: "seq",
+                definition: "BIGINT GENERATED BY DEFAULT AS IDENTITY",
+            },
+            Step::Sql("CREATE UNIQUE INDEX IF NOT EXISTS idx_seq ON syslog_messages(seq)"),
+        ],
+    },
+    Migration {
+        version: 6,
+        description: "trash for soft-deleted messages",
+        steps: &[
+            Step::AddColumn { table: "syslog_messages", column: "deleted_at", definition: "TIMESTAMPTZ" },
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_deleted_at ON syslog_messages(deleted_at) WHERE deleted_at IS NOT NULL"),
+            // Trashed messages already left the stored counts, so purging them must not count them again
+            Step::Sql(
+                r#"
+                CREATE OR REPLACE FUNCTION message_counts_delete() RETURNS trigger LANGUAGE plpgsql AS $$
+                BEGIN
+                    UPDATE message_counts SET stored = message_counts.stored - counted.removed
+                    FROM (
+                        SELECT dimension, value, COUNT(*) AS removed FROM (
+                            SELECT 'total' AS dimension, '' AS value FROM deleted WHERE deleted_at IS NULL
+                            UNION ALL SELECT 'facility', facility::TEXT FROM deleted WHERE deleted_at IS NULL
+                            UNION ALL SELECT 'severity', severity::TEXT FROM deleted WHERE deleted_at IS NULL
+                            UNION ALL SELECT 'source', source_ip FROM deleted WHERE deleted_at IS NULL
+                            UNION ALL SELECT 'flagged', '' FROM deleted WHERE deleted_at IS NULL AND parse_error IS NOT NULL
+                        ) removed_rows
+                        GROUP BY dimension, value
+                    ) counted
+                    WHERE message_counts.dimension = counted.dimension AND message_counts.value = counted.value;
+                    RETURN NULL;
+                END
+                $$
+                "#,
+            ),
+            // Transition tables rule out `UPDATE OF deleted_at`, so every update
+            // compares the rows outside the trash before and after
+            Step::Sql(
+                r#"
+                CREATE OR REPLACE FUNCTION message_counts_update() RETURNS trigger LANGUAGE plpgsql AS $$
+                BEGIN
+                    WITH changed AS (
+                        SELECT facility, severity, source_ip, parse_error, 1 AS change FROM after_update WHERE deleted_at IS NULL
+                        UNION ALL
+                        SELECT facility, severity, source_ip, parse_error, -1 FROM before_update WHERE deleted_at IS NULL
+                    )
+                    UPDATE message_counts SET stored = message_counts.stored + counted.change
+                    FROM (
+                        SELECT dimension, value, SUM(change) AS change FROM (
+                            SELECT 'total' AS dimension, '' AS value, change FROM changed
+                            UNION ALL SELECT 'facility', facility::TEXT, change FROM changed
+                            UNION ALL SELECT 'severity', severity::TEXT, change FROM changed
+                            UNION ALL SELECT 'source', source_ip, change FROM changed
+                            UNION ALL SELECT 'flagged', '', change FROM changed WHERE parse_error IS NOT NULL
+                        ) changed_rows
+                        GROUP BY dimension, value
+                        HAVING SUM(change) <> 0
+                    ) counted
+                    WHERE message_counts.dimension = counted.dimension AND message_counts.value = counted.value;
+                    RETURN NULL;
+                END
+                $$
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE OR REPLACE TRIGGER message_counts_update AFTER UPDATE ON syslog_messages
+                REFERENCING OLD TABLE AS before_update NEW TABLE AS after_update
+                FOR EACH STATEMENT EXECUTE FUNCTION message_counts_update()
+                "#,
+            ),
+        ],
+    },
+    Migration {
+        version: 7,
+        description: "legal holds",
+        steps: &[Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS legal_holds (
+                id BIGSERIAL PRIMARY KEY,
+                reason TEXT NOT NULL,
+                owner TEXT NOT NULL,
+                criteria JSONB NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                released_at TIMESTAMPTZ
+            )
+            "#,
+        )],
+    },
+];
+
+
+fn latest_version(migrations: &[Migration]) -> i64 {
+    migrations.last().map_or(0, |migration| migration.version)
+}
+
+fn pending(migrations: &'static [Migration], current: i64) -> impl Iterator<Item = &'static Migration> {
+    migrations.iter().filter(move |migration| migration.version > current)
+}
+
+/// Refuses to run against a database written by a newer build
+fn check_version(current: i64, migrations: &[Migration]) -> Result<()> {
+    let latest = latest_version(migrations);
+    if current > latest {
+        bail!(
+            "database schema version {} is newer than this build supports ({}); upgrade SyslogParser or restore a backup",
+            current,
+            latest
+        );
+    }
+    Ok(())
+}
+
+fn print_versions(current: i64, migrations: &[Migration]) -> bool {
+    println!("Current schema version: {}", current);
+    println!("Latest schema version:  {}", latest_version(migrations));
+
+    let up_to_date = current >= latest_version(migrations);
+    if up_to_date {
+        println!("Nothing to migrate.");
+    }
+    !up_to_date
+}
+
+fn print_migration(migration: &Migration) {
+    println!();
+    println!("-- Migration {}: {}", migration.version, migration.description);
+}
+
+fn log_applying(migration: &Migration) {
+    info!("Applying migration {}: {}", migration.version, migration.description);
+}
+
+fn failed(migration: &Migration) -> String {
+    format!("migration {} ({}) failed", migration.version, migration.description)
+}
+
+// SQLite
+
+/// Highest migration applied to the database, 0 for a new database or one
+/// created before migrations were tracked
+async fn sqlite_version(conn: &mut SqliteConnection) -> Result<i64> {
+    let tracked: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'schema_version'")
+        .fetch_one(&mut *conn)
+        .await?;
+    if tracked == 0 {
+        return Ok(0);
+    }
+
+    let version: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM schema_version")
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok(version.unwrap_or(0))
+}
+
+/// Brings the schema up to date, applying each pending migration in its own transaction
+pub async fn migrate_sqlite(pool: &SqlitePool) -> Result<()> {
+    let mut conn = pool.acquire().await?;
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER PRIMARY KEY, description TEXT NOT NULL, applied_at TEXT NOT NULL)"
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let current = sqlite_version(&mut conn).await?;
+    check_version(current, SQLITE_MIGRATIONS)?;
+    if current == latest_version(SQLITE_MIGRATIONS) {
+        info!("Database schema is up to date (version {})", current);
+        return Ok(());
+    }
+
+    for migration in pending(SQLITE_MIGRATIONS, current) {
+        log_applying(migration);
+        apply_sqlite(pool, migration).await.with_context(|| failed(migration))?;
+    }
+
+    info!("Database schema migrated from version {} to {}", current, latest_version(SQLITE_MIGRATIONS));
+    Ok(())
+}
+
+async fn apply_sqlite(pool: &SqlitePool, migration: &Migration) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    for step in migration.steps {
+        if let Some(sql) = plan_sqlite(step, &mut tx).await? {
+            sqlx::query(&sql).execute(&mut *tx).await?;
+        }
+    }
+
+    sqlx::query("INSERT INTO schema_version (version, description, applied_at) VALUES (?, ?, ?)")
+        .bind(migration.version)
+        .bind(migration.description)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Prints the migrations that would run, with their SQL, without changing anything
+pub async fn dry_run_sqlite(conn: &mut SqliteConnection) -> Result<()> {
+    let current = sqlite_version(conn).await?;
+    check_version(current, SQLITE_MIGRATIONS)?;
+    if !print_versions(current, SQLITE_MIGRATIONS) {
+        return Ok(());
+    }
+
+    // Columns added by earlier pending migrations are not there yet, so
+    // AddColumn steps are judged against the schema as it is today
+    for migration in pending(SQLITE_MIGRATIONS, current) {
+        print_migration(migration);
+        for step in migration.steps {
+            match plan_sqlite(step, conn).await? {
+                Some(sql) => println!("{};", unindent(&sql)),
+                None => {
+                    if let Step::AddColumn { table, column, .. } = step {
+                        println!("-- skip: {}.{} already exists", table, column);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The SQL a step will run, or None when there is nothing to do
+async fn plan_sqlite(step: &Step, conn: &mut SqliteConnection) -> Result<Option<String>> {
+    match step {
+        Step::Sql(sql) => Ok(Some(sql.to_string())),
+        Step::AddColumn { table, column, definition } => {
+            let exists: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?")
+                .bind(table)
+                .bind(column)
+                .fetch_one(&mut *conn)
+                .await?;
+
+            Ok((exists == 0).then(|| format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition)))
+        }
+    }
+}
+
+// PostgreSQL
+
+async fn postgres_version(conn: &mut PgConnection) -> Result<i64> {
+    let tracked: bool = sqlx::query_scalar("SELECT to_regclass('schema_version') IS NOT NULL")
+        .fetch_one(&mut *conn)
+        .await?;
+    if !tracked {
+        return Ok(0);
+    }
+
+    let version: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM schema_version")
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok(version.unwrap_or(0))
+}
+
+pub async fn migrate_postgres(pool: &PgPool) -> Result<()> {
+    let mut conn = pool.acquire().await?;
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS schema_version (version BIGINT PRIMARY KEY, description TEXT NOT NULL, applied_at TIMESTAMPTZ NOT NULL)"
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let current = postgres_version(&mut conn).await?;
+    check_version(current, POSTGRES_MIGRATIONS)?;
+    if current == latest_version(POSTGRES_MIGRATIONS) {
+        info!("Database schema is up to date (version {})", current);
+        return Ok(());
+    }
+
+    for migration in pending(POSTGRES_MIGRATIONS, current) {
+        log_applying(migration);
+        apply_postgres(pool, migration).await.with_context(|| failed(migration))?;
+    }
+
+    info!("Database schema migrated from version {} to {}", current, latest_version(POSTGRES_MIGRATIONS));
+    Ok(())
+}
+
+async fn apply_postgres(pool: &PgPool, migration: &Migration) -> Result<()> {
+    // PostgreSQL DDL is transactional, so a failed migration leaves nothing behind
+    let mut tx = pool.begin().await?;
+
+    for step in migration.steps {
+        sqlx::query(&plan_postgres(step)).execute(&mut *tx).await?;
+    }
+
+    sqlx::query("INSERT INTO schema_version (version, description, applied_at) VALUES ($1, $2, $3)")
+        .bind(migration.version)
+        .bind(migration.description)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn dry_run_postgres(conn: &mut PgConnection) -> Result<()> {
+    let current = postgres_version(conn).await?;
+    check_version(current, POSTGRES_MIGRATIONS)?;
+    if !print_versions(current, POSTGRES_MIGRATIONS) {
+        return Ok(());
+    }
+
+    for migration in pending(POSTGRES_MIGRATIONS, current) {
+        print_migration(migration);
+        for step in migration.steps {
+            println!("{};", unindent(&plan_postgres(step)));
+        }
+    }
+
+    Ok(())
+}
+
+fn plan_postgres(step: &Step) -> String {
+    match step {
+        Step::Sql(sql) => sql.to_string(),
+        Step::AddColumn { table, column, definition } => {
+            format!("ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {}", table, column, definition)
+        }
+    }
+}
+
+fn unindent(sql: &str) -> String {
+    let lines: Vec<&str> = sql.lines().filter(|line| !line.trim().is_empty()).collect();
+    let indent = lines
+        .iter()
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+
+    lines
+        .iter()
+        .map(|line| &line[indent..])
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::SearchQuery;
+    use crate::store::{
+        testing::{message, TempDir},
+        LogFilter, LogStore, Page, SqliteStore,
+    };
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    #[tokio::test]
+    async fn search_finds_rows_indexed_before_the_seq_rekey() {
+        let dir = TempDir::new();
+        let config = dir.config();
+
+        // A database as version 12 left it, whose seqs no longer match the rowids
+        // after partitions were renumbered
+        let pool = SqlitePoolOptions::new().connect_with(config.connect_options()).await.unwrap();
+        sqlx::query("CREATE TABLE schema_version (version INTEGER PRIMARY KEY, description TEXT NOT NULL, applied_at TEXT NOT NULL)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        for migration in SQLITE_MIGRATIONS.iter().filter(|migration| migration.version <= 12) {
+            apply_sqlite(&pool, migration).await.unwrap();
+        }
+        for (seq, text) in [(7, "disk full on sda"), (3, "link down on eth0"), (12, "disk quota exceeded")] {
+            sqlx::query(
+                "INSERT INTO syslog_messages (id, timestamp, facility, severity, hostname, app_name, message, raw_message, source_ip, seq)
+                 VALUES (?, '2024-06-01T00:00:00+00:00', 1, 6, 'host', 'app', ?, ?, '192.0.2.1', ?)",
+            )
+            .bind(format!("id-{}", seq))
+            .bind(text)
+            .bind(text)
+            .bind(seq)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        pool.close().await;
+
+        let store = SqliteStore::open(&config).await.unwrap();
+        let search = |input: &str| LogFilter::Search(SearchQuery::parse(input).unwrap());
+        let page = Page { limit: 10, offset: 0, cursor: None };
+
+        let found = store.query(&search("disk"), page).await.unwrap();
+        let found: Vec<(i64, &str)> = found.iter().map(|entry| (entry.seq, entry.message.message.as_str())).collect();
+        assert_eq!(found, [(12, "disk quota exceeded"), (7, "disk full on sda")]);
+
+        let found = store.query(&search("eth0"), page).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].seq, found[0].message.id.as_str()), (3, "id-3"));
+        assert!(found[0].snippet.as_deref().is_some_and(|snippet| snippet.contains("eth0")));
+
+        // New rows are indexed under their seq too
+        store.insert_batch(&[message(Utc::now(), Some("host"), "disk replaced")]).await.unwrap();
+        assert_eq!(store.count(&search("disk")).await.unwrap(), 3);
+        assert_eq!(store.count(&search("replaced")).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,295 @@
+use crate::signing::{SignatureStatus, SignatureUpdate};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{collections::HashMap, fmt, sync::Arc};
+use syslog_parser::SyslogMessage;
+
+mod chain;
+mod compression;
+mod filter;
+mod hold;
+mod memory;
+mod migrations;
+mod partitioned;
+mod postgres;
+mod rollup;
+mod sqlite;
+#[cfg(test)]
+pub(crate) mod testing;
+
+pub use chain::{ChainConfig, ChainReport};
+pub use compression::RawCompression;
+pub use filter::LogFilter;
+pub use hold::{count_held, exempt, without_held, HoldCriteria, LegalHold};
+pub use memory::MemoryStore;
+pub use partitioned::PartitionedStore;
+pub use postgres::PostgresStore;
+pub use rollup::{GroupBy, Resolution, TimeSeriesPoint, TimeSeriesQuery};
+pub use sqlite::{DatabaseConfig, IntegrityCheck, SqliteStore};
+
+/// Columns read back into a `SyslogMessage`, named the same in every backend
+const MESSAGE_COLUMNS: &str = "id, timestamp, facility, severity, hostname, app_name, proc_id, msg_id, structured_data, message, raw_message, source_ip, parse_error, original_bytes";
+
+/// Columns read into a `LogEntry` alongside the message itself
+const ENTRY_COLUMNS: &str = "seq, signature_status, signature_detail, deleted_at";
+
+/// Where received messages are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StorageKind {
+    /// A local SQLite database file (see --db-path)
+    Sqlite,
+    /// A PostgreSQL server (see --postgres-url)
+    Postgres,
+    /// A ring buffer in memory, lost on exit (see --memory-capacity)
+    Memory,
+}
+
+/// How SQLite splits messages across database files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Partitioning {
+    /// Everything in the single --db-path file
+    None,
+    /// One file per UTC day, listed in a catalog next to --db-path
+    Daily,
+}
+
+/// A stored message, plus the highlighted excerpt when it came from a search
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    /// Position in the order messages were stored, never reused
+    pub seq: i64,
+    #[serde(flatten)]
+    pub message: SyslogMessage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+    /// RFC 5848 verification outcome; kept outside the message so the hash chain covers only what was received
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature_status: Option<SignatureStatus>,
+    /// Gaps in the signed sequence, or why a signature block failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature_detail: Option<String>,
+    /// When the message was moved to the trash
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// Which page of matching messages `LogStore::query` returns
+#[derive(Debug, Clone, Copy)]
+pub struct Page {
+    pub limit: usize,
+    /// Matches skipped before the page starts, after applying the cursor
+    pub offset: usize,
+    pub cursor: Option<Cursor>,
+}
+
+/// A `seq` to page from. Pages run newest first, except after an `After` cursor,
+/// where they run oldest first so that new messages can be followed without gaps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cursor {
+    Before(i64),
+    After(i64),
+}
+
+impl Page {
+    fn descending(&self) -> bool {
+        !matches!(self.cursor, Some(Cursor::After(_)))
+    }
+
+    /// The `seq` condition and sort order, with a `?` for the cursor bound to `binds`
+    fn render(&self, binds: &mut Vec<filter::SqlValue>) -> (&'static str, &'static str) {
+        match self.cursor {
+            Some(Cursor::Before(seq)) => {
+                binds.push(filter::SqlValue::Int(seq));
+                ("seq < ?", "seq DESC")
+            }
+            Some(Cursor::After(seq)) => {
+                binds.push(filter::SqlValue::Int(seq));
+                ("seq > ?", "seq ASC")
+            }
+            None => ("1=1", "seq DESC"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StoreStats {
+    pub backend: &'static str,
+    /// Messages outside the trash
+    pub stored_messages: u64,
+    /// Messages in the trash, which take up space until they are purged
+    pub trashed_messages: u64,
+    /// Space taken by stored messages, when the backend can measure it cheaply
+    pub used_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partitions: Option<usize>,
+    /// Bytes compression saved on `raw_message`, when it is compressed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_bytes_saved: Option<u64>,
+}
+
+/// Messages counted by the dimensions `/api/stats` breaks them down by
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MessageCounts {
+    pub total_messages: u64,
+    pub flagged_messages: u64,
+    pub messages_per_facility: HashMap<u8, u64>,
+    pub messages_per_severity: HashMap<u8, u64>,
+    pub messages_per_source: HashMap<String, u64>,
+}
+
+impl MessageCounts {
+    fn add(&mut self, dimension: &str, value: &str, count: u64) {
+        // Values whose messages are all deleted keep a row with nothing stored
+        if count == 0 {
+            return;
+        }
+        match dimension {
+            "total" => self.total_messages += count,
+            "flagged" => self.flagged_messages += count,
+            "facility" => *self.messages_per_facility.entry(value.parse().unwrap_or_default()).or_default() += count,
+            "severity" => *self.messages_per_severity.entry(value.parse().unwrap_or_default()).or_default() += count,
+            "source" => *self.messages_per_source.entry(value.to_string()).or_default() += count,
+            _ => {}
+        }
+    }
+
+    fn merge(&mut self, other: MessageCounts) {
+        self.total_messages += other.total_messages;
+        self.flagged_messages += other.flagged_messages;
+        for (facility, count) in other.messages_per_facility {
+            *self.messages_per_facility.entry(facility).or_default() += count;
+        }
+        for (severity, count) in other.messages_per_severity {
+            *self.messages_per_severity.entry(severity).or_default() += count;
+        }
+        for (source, count) in other.messages_per_source {
+            *self.messages_per_source.entry(source).or_default() += count;
+        }
+    }
+}
+
+/// Counts of every message ever stored, and of those still stored
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MessageTotals {
+    pub all_time: MessageCounts,
+    pub stored: MessageCounts,
+}
+
+/// The `message_counts` table, which the backends keep up to date with triggers
+const COUNTS_QUERY: &str = "SELECT dimension, value, all_time, stored FROM message_counts";
+
+impl MessageTotals {
+    /// Adds `(dimension, value, all_time, stored)` rows read with `COUNTS_QUERY`
+    fn add_rows(&mut self, rows: &[(String, String, i64, i64)]) {
+        for (dimension, value, all_time, stored) in rows {
+            self.all_time.add(dimension, value, (*all_time).max(0) as u64);
+            self.stored.add(dimension, value, (*stored).max(0) as u64);
+        }
+    }
+
+    fn merge(&mut self, other: MessageTotals) {
+        self.all_time.merge(other.all_time);
+        self.stored.merge(other.stored);
+    }
+}
+
+/// Persistent storage for parsed messages
+#[async_trait]
+pub trait LogStore: fmt::Debug + Send + Sync {
+    /// Stores all messages in one transaction
+    async fn insert_batch(&self, messages: &[SyslogMessage]) -> Result<()>;
+
+    /// A page of matching messages in `seq` order. Results of a full-text search carry a snippet.
+    async fn query(&self, filter: &LogFilter, page: Page) -> Result<Vec<LogEntry>>;
+
+    async fn get(&self, id: &str) -> Result<Option<SyslogMessage>>;
+
+    /// The oldest matching messages, oldest first
+    async fn oldest(&self, filter: &LogFilter, limit: usize) -> Result<Vec<LogEntry>>;
+
+    /// Deletes matching messages, oldest first, stopping after `limit` when given
+    async fn delete(&self, filter: &LogFilter, limit: Option<u64>) -> Result<u64>;
+
+    async fn count(&self, filter: &LogFilter) -> Result<u64>;
+
+    /// Moves matching messages to the trash, oldest first and stopping after
+    /// `limit` when given. Trashed messages stay stored until deleted, but only
+    /// filters that ask for `LogFilter::Trashed` see them.
+    async fn trash(&self, filter: &LogFilter, limit: Option<u64>) -> Result<u64>;
+
+    /// Takes matching messages back out of the trash
+    async fn restore(&self, filter: &LogFilter) -> Result<u64>;
+
+    /// Every legal hold, released ones included, oldest first
+    async fn legal_holds(&self) -> Result<Vec<LegalHold>>;
+
+    async fn add_legal_hold(&self, reason: &str, owner: &str, criteria: &HoldCriteria) -> Result<LegalHold>;
+
+    /// Releases an active hold, returning whether there was one with this id
+    async fn release_legal_hold(&self, id: i64) -> Result<bool>;
+
+    /// Records RFC 5848 verification results for stored messages
+    async fn set_signatures(&self, updates: &[SignatureUpdate]) -> Result<()>;
+
+    /// Stores archived messages under their original `seq`, with the signature
+    /// status and trash state they were archived with
+    async fn insert_archived(&self, _entries: &[LogEntry]) -> Result<()> {
+        bail!("this storage backend cannot hold rehydrated archives")
+    }
+
+    /// Drops whole partitions in which every message matches, oldest first and at
+    /// most `max_partitions` of them, returning the number of messages dropped.
+    /// Unpartitioned backends have nothing to drop.
+    async fn drop_partitions(&self, _filter: &LogFilter, _max_partitions: Option<usize>) -> Result<u64> {
+        Ok(0)
+    }
+
+    async fn stats(&self) -> Result<StoreStats>;
+
+    /// Message counts kept alongside the messages, so they survive restarts and
+    /// follow every delete
+    async fn counts(&self) -> Result<MessageTotals>;
+
+    /// Message counts over time from the rollup tables, which deletes leave alone
+    async fn timeseries(&self, query: &TimeSeriesQuery) -> Result<Vec<TimeSeriesPoint>>;
+
+    /// Walks the hash chain, checking every link and signed event
+    async fn verify_chain(&self) -> Result<ChainReport> {
+        bail!("this storage backend does not keep a hash chain")
+    }
+}
+
+/// Connects to the configured backend and brings its schema up to date
+pub async fn open(kind: StorageKind, config: &DatabaseConfig, postgres_url: Option<&str>) -> Result<Arc<dyn LogStore>> {
+    if config.chain.is_some() && (kind, config.partitioning) != (StorageKind::Sqlite, Partitioning::None) {
+        bail!("--chain-key requires --storage sqlite without partitioning");
+    }
+    Ok(match (kind, config.partitioning) {
+        (StorageKind::Sqlite, Partitioning::None) => Arc::new(SqliteStore::open(config).await?),
+        (StorageKind::Sqlite, Partitioning::Daily) => Arc::new(PartitionedStore::open(config).await?),
+        (StorageKind::Postgres, Partitioning::None) => {
+            Arc::new(PostgresStore::open(require_url(postgres_url)?, config.max_connections).await?)
+        }
+        (StorageKind::Memory, Partitioning::None) => Arc::new(MemoryStore::new(config.memory_capacity)),
+        (_, Partitioning::Daily) => bail!("--partition daily requires --storage sqlite"),
+    })
+}
+
+/// Handles `migrate [--dry-run]` for the configured backend
+pub async fn migrate(kind: StorageKind, config: &DatabaseConfig, postgres_url: Option<&str>, dry_run: bool) -> Result<()> {
+    match (kind, dry_run) {
+        (StorageKind::Sqlite, true) if config.partitioning == Partitioning::Daily => {
+            partitioned::dry_run_migrations(config).await
+        }
+        (StorageKind::Sqlite, true) => sqlite::dry_run_migrations(config).await,
+        (StorageKind::Postgres, true) => postgres::dry_run_migrations(require_url(postgres_url)?).await,
+        (StorageKind::Memory, _) => bail!("--storage memory has no schema to migrate"),
+        (_, false) => open(kind, config, postgres_url).await.map(|_| ()),
+    }
+}
+
+fn require_url(postgres_url: Option<&str>) -> Result<&str> {
+    postgres_url.context("--storage postgres requires --postgres-url")
+}
@@ -0,0 +1,730 @@
+use super::{
+    sqlite::{self, DatabaseConfig, SqliteStore},
+    rollup::{self, Resolution, TimeSeriesPoint, TimeSeriesQuery},
+    HoldCriteria, LegalHold, LogEntry, LogFilter, LogStore, MessageTotals, Page, StoreStats,
+};
+use crate::signing::SignatureUpdate;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteConnection, SqlitePool, SqlitePoolOptions},
+    ConnectOptions, Connection,
+};
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+use syslog_parser::SyslogMessage;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+const CATALOG_FILE: &str = "catalog.db";
+
+/// Messages split into one SQLite file per UTC day, so that retention can
+/// delete a day by removing its file instead of deleting rows.
+///
+/// The partitions live in a directory named after `--db-path` (`syslog.db`
+/// becomes `syslog.partitions/`), along with a catalog of the days it holds.
+#[derive(Debug)]
+pub struct PartitionedStore {
+    config: DatabaseConfig,
+    dir: PathBuf,
+    catalog: SqlitePool,
+    /// Held for reading while partitions are used, and for writing while any is
+    /// created or dropped, so a dropped partition is never written to
+    partitions: RwLock<BTreeMap<NaiveDate, SqliteStore>>,
+}
+
+impl PartitionedStore {
+    /// Opens the catalog and every partition it lists
+    pub async fn open(config: &DatabaseConfig) -> Result<PartitionedStore> {
+        let dir = partition_dir(&config.path);
+        std::fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+
+        let catalog_config = DatabaseConfig {
+            path: dir.join(CATALOG_FILE),
+            ..config.clone()
+        };
+        let catalog = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(catalog_config.connect_options())
+            .await
+            .with_context(|| format!("failed to open partition catalog {}", catalog_config.path.display()))?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS partitions (
+                day TEXT PRIMARY KEY,
+                file TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )
+            "#,
+        )
+        .execute(&catalog)
+        .await?;
+        // All-time counts of dropped partitions, which took their own counts with them
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS retired_counts (
+                dimension TEXT NOT NULL,
+                value TEXT NOT NULL,
+                all_time INTEGER NOT NULL,
+                PRIMARY KEY (dimension, value)
+            )
+            "#,
+        )
+        .execute(&catalog)
+        .await?;
+        // Rollups of dropped partitions, so charts reach back past retention
+        Self::create_rollup_tables(&catalog).await?;
+        // Numbers messages across every partition
+        sqlx::query("CREATE TABLE IF NOT EXISTS sequences (name TEXT PRIMARY KEY, value INTEGER NOT NULL)")
+            .execute(&catalog)
+            .await?;
+        // Holds cover every partition, so they live beside them
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS legal_holds (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                reason TEXT NOT NULL,
+                owner TEXT NOT NULL,
+                criteria TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                released_at TEXT
+            )
+            "#,
+        )
+        .execute(&catalog)
+        .await?;
+
+        let mut partitions = BTreeMap::new();
+        for (day, file) in list_partitions(&catalog).await? {
+            let path = dir.join(&file);
+            if !path.exists() {
+                warn!("Partition {} is missing {}; removing it from the catalog", day, path.display());
+                sqlx::query("DELETE FROM partitions WHERE day = ?")
+                    .bind(day.to_string())
+                    .execute(&catalog)
+                    .await?;
+                continue;
+            }
+            partitions.insert(day, SqliteStore::open(&partition_config(config, path)).await?);
+        }
+        info!("Opened {} daily partitions in {}", partitions.len(), dir.display());
+        number_partitions(&catalog, &partitions).await?;
+
+        Ok(PartitionedStore {
+            config: config.clone(),
+            dir,
+            catalog,
+            partitions: RwLock::new(partitions),
+        })
+    }
+
+    async fn create_partition(&self, day: NaiveDate) -> Result<SqliteStore> {
+        let file = format!("{}.db", day);
+        let partition = SqliteStore::open(&partition_config(&self.config, self.dir.join(&file))).await?;
+
+        sqlx::query("INSERT OR IGNORE INTO partitions (day, file, created_at) VALUES (?, ?, ?)")
+            .bind(day.to_string())
+            .bind(&file)
+            .bind(Utc::now().to_rfc3339())
+            .execute(&self.catalog)
+            .await?;
+
+        info!("Created partition {}", day);
+        Ok(partition)
+    }
+
+    /// Removes a partition from the catalog, keeping its all-time counts, then deletes its files
+    async fn drop_partition(&self, partitions: &mut BTreeMap<NaiveDate, SqliteStore>, day: NaiveDate) -> Result<()> {
+        let Some(partition) = partitions.remove(&day) else {
+            return Ok(());
+        };
+        partition.close().await;
+
+        let file: Option<String> = sqlx::query_scalar("SELECT file FROM partitions WHERE day = ?")
+            .bind(day.to_string())
+            .fetch_optional(&self.catalog)
+            .await?;
+        let path = self.dir.join(file.unwrap_or_else(|| format!("{}.db", day)));
+
+        // The catalog has a single connection, so the attachment is seen by every statement below
+        let mut conn = self.catalog.acquire().await?;
+        sqlx::query("ATTACH DATABASE ? AS retiring")
+            .bind(path.to_string_lossy())
+            .execute(&mut *conn)
+            .await?;
+        let retired = retire(&mut conn, day).await;
+        sqlx::query("DETACH DATABASE retiring").execute(&mut *conn).await?;
+        retired?;
+        drop(conn);
+        for suffix in ["", "-wal", "-shm"] {
+            let mut name = path.clone().into_os_string();
+            name.push(suffix);
+            match tokio::fs::remove_file(&name).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(e).with_context(|| format!("failed to remove {}", Path::new(&name).display()));
+                }
+                _ => {}
+            }
+        }
+
+        info!("Dropped partition {}", day);
+        Ok(())
+    }
+
+    async fn create_rollup_tables(catalog: &SqlitePool) -> Result<()> {
+        for resolution in Resolution::ALL {
+            sqlx::query(&format!(
+                r#"
+                CREATE TABLE IF NOT EXISTS {} (
+                    bucket TEXT NOT NULL,
+                    source_ip TEXT NOT NULL,
+                    hostname TEXT NOT NULL,
+                    app_name TEXT NOT NULL,
+                    facility INTEGER NOT NULL,
+                    severity INTEGER NOT NULL,
+                    count INTEGER NOT NULL,
+                    PRIMARY KEY (bucket, source_ip, hostname, app_name, facility, severity)
+                ) WITHOUT ROWID
+                "#,
+                resolution.table()
+            ))
+            .execute(catalog)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Messages in the partition if all of them match, or `None` if some do not
+    async fn fully_matching(&self, partition: &SqliteStore, day: NaiveDate, filter: &LogFilter) -> Result<Option<u64>> {
+        let total = partition.count(&LogFilter::All).await?;
+        if filter.is_time_range() && covers(day, filter) {
+            return Ok(Some(total));
+        }
+
+        let matching = partition.count(filter).await?;
+        Ok((matching == total).then_some(total))
+    }
+}
+
+/// Prints the migrations opening each partition would apply, without changing them
+pub async fn dry_run_migrations(config: &DatabaseConfig) -> Result<()> {
+    let dir = partition_dir(&config.path);
+    let catalog_path = dir.join(CATALOG_FILE);
+    if !catalog_path.exists() {
+        println!(
+            "{} does not exist yet; partitions will be created with the full schema as messages arrive.",
+            catalog_path.display()
+        );
+        return Ok(());
+    }
+
+    let mut catalog = SqliteConnectOptions::new()
+        .filename(&catalog_path)
+        .read_only(true)
+        .connect()
+        .await
+        .with_context(|| format!("failed to open partition catalog {}", catalog_path.display()))?;
+
+    let rows: Vec<(String, String)> = sqlx::query_as("SELECT day, file FROM partitions ORDER BY day")
+        .fetch_all(&mut catalog)
+        .await?;
+    if rows.is_empty() {
+        println!("{} lists no partitions.", catalog_path.display());
+    }
+
+    for (day, file) in rows {
+        println!("Partition {}:", day);
+        sqlite::dry_run_migrations(&partition_config(config, dir.join(file))).await?;
+    }
+    Ok(())
+}
+
+/// `syslog.db` keeps its partitions in `syslog.partitions/`
+fn partition_dir(db_path: &Path) -> PathBuf {
+    db_path.with_extension("partitions")
+}
+
+fn partition_config(config: &DatabaseConfig, path: PathBuf) -> DatabaseConfig {
+    DatabaseConfig { path, ..config.clone() }
+}
+
+async fn list_partitions(catalog: &SqlitePool) -> Result<Vec<(NaiveDate, String)>> {
+    let rows: Vec<(String, String)> = sqlx::query_as("SELECT day, file FROM partitions ORDER BY day")
+        .fetch_all(catalog)
+        .await?;
+
+    rows.into_iter()
+        .map(|(day, file)| {
+            let day = NaiveDate::parse_from_str(&day, "%Y-%m-%d")
+                .with_context(|| format!("invalid day {:?} in the partition catalog", day))?;
+            Ok((day, file))
+        })
+        .collect()
+}
+
+fn day_start(day: NaiveDate) -> DateTime<Utc> {
+    day.and_time(chrono::NaiveTime::MIN).and_utc()
+}
+
+fn next_day_start(day: NaiveDate) -> DateTime<Utc> {
+    day_start(day.checked_add_days(Days::new(1)).unwrap_or(NaiveDate::MAX))
+}
+
+/// Moves the all-time counts and rollups of the partition attached as `retiring`
+/// into the catalog, and removes it from the catalog, in one transaction
+async fn retire(conn: &mut SqliteConnection, day: NaiveDate) -> Result<()> {
+    let mut tx = conn.begin().await?;
+    sqlx::query(
+        "INSERT INTO retired_counts (dimension, value, all_time) SELECT dimension, value, all_time FROM retiring.message_counts WHERE all_time > 0 ON CONFLICT (dimension, value) DO UPDATE SET all_time = all_time + excluded.all_time",
+    )
+    .execute(&mut *tx)
+    .await?;
+    for resolution in Resolution::ALL {
+        // The WHERE clause keeps SQLite from reading ON CONFLICT as a join constraint
+        let sql = format!(
+            "INSERT INTO {table} SELECT * FROM retiring.{table} WHERE true ON CONFLICT (bucket, source_ip, hostname, app_name, facility, severity) DO UPDATE SET count = count + excluded.count",
+            table = resolution.table()
+        );
+        sqlx::query(&sql).execute(&mut *tx).await?;
+    }
+    sqlx::query("DELETE FROM partitions WHERE day = ?")
+        .bind(day.to_string())
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Whether the filter's time range includes any part of the day
+fn overlaps(day: NaiveDate, filter: &LogFilter) -> bool {
+    let (since, before) = filter.time_range();
+    since.is_none_or(|since| since < next_day_start(day)) && before.is_none_or(|before| before > day_start(day))
+}
+
+/// Whether the filter's time range includes the whole day
+fn covers(day: NaiveDate, filter: &LogFilter) -> bool {
+    let (since, before) = filter.time_range();
+    since.is_none_or(|since| since <= day_start(day)) && before.is_none_or(|before| before >= next_day_start(day))
+}
+
+/// Starts the catalog's sequence past every stored message. Partitions from
+/// before the catalog kept one each numbered their messages from 1, so they are
+/// shifted to follow one another in day order first.
+async fn number_partitions(catalog: &SqlitePool, partitions: &BTreeMap<NaiveDate, SqliteStore>) -> Result<()> {
+    let numbered: Option<i64> = sqlx::query_scalar("SELECT value FROM sequences WHERE name = 'messages'")
+        .fetch_optional(catalog)
+        .await?;
+    if numbered.is_some() {
+        return Ok(());
+    }
+
+    let mut last = 0;
+    for (day, partition) in partitions {
+        if let Some((first, end)) = partition.seq_range().await? {
+            if first <= last {
+                info!("Renumbering messages in partition {}", day);
+                partition.shift_seqs(last - first + 1).await?;
+                last += end - first + 1;
+            } else {
+                last = end;
+            }
+        }
+    }
+    sqlx::query("INSERT INTO sequences (name, value) VALUES ('messages', ?)")
+        .bind(last)
+        .execute(catalog)
+        .await?;
+    Ok(())
+}
+
+/// Splits a batch into runs of consecutive messages from the same day, which is
+/// usually the whole batch
+fn runs_by_day(messages: &[SyslogMessage]) -> Vec<(NaiveDate, &[SyslogMessage])> {
+    let mut runs = Vec::new();
+    let mut rest = messages;
+    while let Some(first) = rest.first() {
+        let day = first.timestamp.date_naive();
+        let len = rest
+            .iter()
+            .position(|message| message.timestamp.date_naive() != day)
+            .unwrap_or(rest.len());
+        let (run, remainder) = rest.split_at(len);
+        runs.push((day, run));
+        rest = remainder;
+    }
+    runs
+}
+
+#[async_trait]
+impl LogStore for PartitionedStore {
+    /// Each day's messages are committed in their own transaction
+    async fn insert_batch(&self, messages: &[SyslogMessage]) -> Result<()> {
+        let runs = runs_by_day(messages);
+
+        let partitions = {
+            let partitions = self.partitions.read().await;
+            if runs.iter().all(|(day, _)| partitions.contains_key(day)) {
+                partitions
+            } else {
+                drop(partitions);
+                let mut partitions = self.partitions.write().await;
+                for (day, _) in &runs {
+                    if !partitions.contains_key(day) {
+                        let partition = self.create_partition(*day).await?;
+                        partitions.insert(*day, partition);
+                    }
+                }
+                partitions.downgrade()
+            }
+        };
+
+        let mut seq = {
+            let mut conn = self.catalog.acquire().await?;
+            sqlite::reserve_seqs(&mut conn, messages.len()).await?
+        };
+        for (day, run) in runs {
+            partitions[&day].insert_numbered(run, Some(seq)).await?;
+            seq += run.len() as i64;
+        }
+        Ok(())
+    }
+
+    /// Late messages land in older partitions, so `seq` ranges can overlap. Each
+    /// partition is asked for a whole page and the results merged, visiting
+    /// partitions from the one whose range reaches furthest in the page's
+    /// direction and stopping once none left could make the page.
+    async fn query(&self, filter: &LogFilter, page: Page) -> Result<Vec<LogEntry>> {
+        let ascending = !page.descending();
+        // Order seqs so that the page takes the smallest
+        let key = |seq: i64| if ascending { seq } else { -seq };
+        let wanted = page.offset + page.limit;
+
+        let partitions = self.partitions.read().await;
+        let mut candidates = Vec::new();
+        for (_, partition) in partitions.iter().filter(|(day, _)| overlaps(**day, filter)) {
+            if let Some((first, last)) = partition.seq_range().await? {
+                candidates.push((key(if ascending { first } else { last }), partition));
+            }
+        }
+        candidates.sort_by_key(|(nearest, _)| *nearest);
+
+        let per_partition = Page {
+            limit: wanted,
+            offset: 0,
+            ..page
+        };
+        let mut entries: Vec<LogEntry> = Vec::new();
+        for (nearest, partition) in candidates {
+            if entries.len() >= wanted && entries.last().is_some_and(|entry| key(entry.seq) < nearest) {
+                break;
+            }
+            entries.extend(partition.query(filter, per_partition).await?);
+            entries.sort_by_key(|entry| key(entry.seq));
+            entries.truncate(wanted);
+        }
+
+        Ok(entries.into_iter().skip(page.offset).collect())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<SyslogMessage>> {
+        let partitions = self.partitions.read().await;
+        for partition in partitions.values().rev() {
+            if let Some(message) = partition.get(id).await? {
+                return Ok(Some(message));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn oldest(&self, filter: &LogFilter, limit: usize) -> Result<Vec<LogEntry>> {
+        let partitions = self.partitions.read().await;
+        let mut messages = Vec::new();
+        for (_, partition) in partitions.iter().filter(|(day, _)| overlaps(**day, filter)) {
+            if messages.len() >= limit {
+                break;
+            }
+            messages.extend(partition.oldest(filter, limit - messages.len()).await?);
+        }
+        Ok(messages)
+    }
+
+    /// Drops partitions the delete would empty, as long as they fit within `limit`,
+    /// and deletes rows from the rest
+    async fn delete(&self, filter: &LogFilter, limit: Option<u64>) -> Result<u64> {
+        let mut partitions = self.partitions.write().await;
+        let days: Vec<NaiveDate> = partitions.keys().copied().filter(|day| overlaps(*day, filter)).collect();
+        let mut deleted = 0;
+
+        for day in days {
+            let remaining = limit.map(|limit| limit - deleted);
+            if remaining == Some(0) {
+                break;
+            }
+
+            let partition = &partitions[&day];
+            if let Some(total) = self.fully_matching(partition, day, filter).await? {
+                if remaining.is_none_or(|remaining| total <= remaining) {
+                    self.drop_partition(&mut partitions, day).await?;
+                    deleted += total;
+                    continue;
+                }
+            }
+
+            deleted += partition.delete(filter, remaining).await?;
+            if partition.count(&LogFilter::All).await? == 0 {
+                self.drop_partition(&mut partitions, day).await?;
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    async fn count(&self, filter: &LogFilter) -> Result<u64> {
+        let partitions = self.partitions.read().await;
+        let mut count = 0;
+        for (_, partition) in partitions.iter().filter(|(day, _)| overlaps(**day, filter)) {
+            count += partition.count(filter).await?;
+        }
+        Ok(count)
+    }
+
+    /// Updates go to the partition for their message's day
+    async fn trash(&self, filter: &LogFilter, limit: Option<u64>) -> Result<u64> {
+        let partitions = self.partitions.read().await;
+        let mut trashed = 0;
+        for (_, partition) in partitions.iter().filter(|(day, _)| overlaps(**day, filter)) {
+            let remaining = limit.map(|limit| limit - trashed);
+            if remaining == Some(0) {
+                break;
+            }
+            trashed += partition.trash(filter, remaining).await?;
+        }
+        Ok(trashed)
+    }
+
+    async fn restore(&self, filter: &LogFilter) -> Result<u64> {
+        let partitions = self.partitions.read().await;
+        let mut restored = 0;
+        for (_, partition) in partitions.iter().filter(|(day, _)| overlaps(**day, filter)) {
+            restored += partition.restore(filter).await?;
+        }
+        Ok(restored)
+    }
+
+    async fn legal_holds(&self) -> Result<Vec<LegalHold>> {
+        sqlite::legal_holds(&self.catalog).await
+    }
+
+    async fn add_legal_hold(&self, reason: &str, owner: &str, criteria: &HoldCriteria) -> Result<LegalHold> {
+        sqlite::add_legal_hold(&self.catalog, reason, owner, criteria).await
+    }
+
+    async fn release_legal_hold(&self, id: i64) -> Result<bool> {
+        sqlite::release_legal_hold(&self.catalog, id).await
+    }
+
+    async fn set_signatures(&self, updates: &[SignatureUpdate]) -> Result<()> {
+        let partitions = self.partitions.read().await;
+        let mut by_day: BTreeMap<NaiveDate, Vec<SignatureUpdate>> = BTreeMap::new();
+        for update in updates {
+            by_day.entry(update.timestamp.date_naive()).or_default().push(update.clone());
+        }
+
+        for (day, updates) in by_day {
+            if let Some(partition) = partitions.get(&day) {
+                partition.set_signatures(&updates).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn drop_partitions(&self, filter: &LogFilter, max_partitions: Option<usize>) -> Result<u64> {
+        let mut partitions = self.partitions.write().await;
+        let days: Vec<NaiveDate> = partitions.keys().copied().filter(|day| overlaps(*day, filter)).collect();
+        let mut dropped_partitions = 0;
+        let mut dropped = 0;
+
+        for day in days {
+            if max_partitions.is_some_and(|max| dropped_partitions >= max) {
+                break;
+            }
+            if let Some(total) = self.fully_matching(&partitions[&day], day, filter).await? {
+                self.drop_partition(&mut partitions, day).await?;
+                dropped_partitions += 1;
+                dropped += total;
+            }
+        }
+
+        Ok(dropped)
+    }
+
+    async fn stats(&self) -> Result<StoreStats> {
+        let partitions = self.partitions.read().await;
+        let mut stored_messages = 0;
+        let mut trashed_messages = 0;
+        let mut used_bytes = 0;
+        let mut raw_bytes_saved = None;
+        for partition in partitions.values() {
+            let stats = partition.stats().await?;
+            stored_messages += stats.stored_messages;
+            trashed_messages += stats.trashed_messages;
+            used_bytes += stats.used_bytes.unwrap_or(0);
+            if let Some(saved) = stats.raw_bytes_saved {
+                *raw_bytes_saved.get_or_insert(0) += saved;
+            }
+        }
+
+        Ok(StoreStats {
+            backend: "sqlite",
+            stored_messages,
+            trashed_messages,
+            used_bytes: Some(used_bytes),
+            partitions: Some(partitions.len()),
+            raw_bytes_saved,
+        })
+    }
+
+    async fn timeseries(&self, query: &TimeSeriesQuery) -> Result<Vec<TimeSeriesPoint>> {
+        let range = LogFilter::Since(query.resolution.floor(query.since)).and(LogFilter::Before(query.until));
+        let partitions = self.partitions.read().await;
+        let mut points = sqlite::timeseries(&self.catalog, query).await?;
+        for (day, partition) in partitions.iter() {
+            if overlaps(*day, &range) {
+                points.extend(partition.timeseries(query).await?);
+            }
+        }
+        Ok(rollup::merge_points(points))
+    }
+
+    async fn counts(&self) -> Result<MessageTotals> {
+        let partitions = self.partitions.read().await;
+        let mut totals = MessageTotals::default();
+        for partition in partitions.values() {
+            totals.merge(partition.counts().await?);
+        }
+
+        let retired: Vec<(String, String, i64)> = sqlx::query_as("SELECT dimension, value, all_time FROM retired_counts")
+            .fetch_all(&self.catalog)
+            .await?;
+        for (dimension, value, all_time) in retired {
+            totals.all_time.add(&dimension, &value, all_time.max(0) as u64);
+        }
+        Ok(totals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{
+        testing::{day, message, messages, TempDir},
+        Cursor, Partitioning,
+    };
+    use chrono::Duration;
+
+    async fn partitioned(dir: &TempDir) -> PartitionedStore {
+        let config = DatabaseConfig {
+            partitioning: Partitioning::Daily,
+            ..dir.config()
+        };
+        PartitionedStore::open(&config).await.unwrap()
+    }
+
+    /// Every page of `filter`, `limit` at a time, following cursors in one direction
+    async fn page_through(store: &PartitionedStore, filter: &LogFilter, limit: usize, ascending: bool) -> Vec<i64> {
+        let mut seqs: Vec<i64> = Vec::new();
+        loop {
+            let cursor = match (seqs.last(), ascending) {
+                (Some(&seq), true) => Some(Cursor::After(seq)),
+                (Some(&seq), false) => Some(Cursor::Before(seq)),
+                (None, true) => Some(Cursor::After(0)),
+                (None, false) => None,
+            };
+            let page = store.query(filter, Page { limit, offset: 0, cursor }).await.unwrap();
+            seqs.extend(page.iter().map(|entry| entry.seq));
+            if page.len() < limit {
+                return seqs;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn pages_cross_partitions_without_gaps() {
+        let dir = TempDir::new();
+        let store = partitioned(&dir).await;
+        for offset in 0..3 {
+            store.insert_batch(&messages(day(offset), 10)).await.unwrap();
+        }
+        // Late messages land in the first partition, so its seqs overlap the others'
+        let late: Vec<SyslogMessage> = (0..3)
+            .map(|i| message(day(0) + Duration::hours(12 + i), Some("late"), "arrived late"))
+            .collect();
+        store.insert_batch(&late).await.unwrap();
+        // One batch split across two days
+        store.insert_batch(&messages(day(2) + Duration::hours(23), 120)).await.unwrap();
+        assert_eq!(store.partitions.read().await.len(), 4);
+
+        let total = 10 * 3 + 3 + 120;
+        for limit in [1, 7, 50, 1000] {
+            let descending = page_through(&store, &LogFilter::All, limit, false).await;
+            assert_eq!(descending, (1..=total).rev().collect::<Vec<i64>>(), "descending by {limit}");
+            let ascending = page_through(&store, &LogFilter::All, limit, true).await;
+            assert_eq!(ascending, (1..=total).collect::<Vec<i64>>(), "ascending by {limit}");
+        }
+
+        let late_only = LogFilter::Hostname(vec!["late".to_string()]);
+        assert_eq!(page_through(&store, &late_only, 2, false).await, vec![33, 32, 31]);
+        assert_eq!(page_through(&store, &late_only, 2, true).await, vec![31, 32, 33]);
+
+        let from_day_one = LogFilter::Since(day(1));
+        let expected: Vec<i64> = (11..=30).chain(34..=total).collect();
+        assert_eq!(page_through(&store, &from_day_one, 9, true).await, expected);
+    }
+
+    #[tokio::test]
+    async fn partitions_numbered_apart_are_renumbered_once() {
+        let dir = TempDir::new();
+        let inserted: Vec<SyslogMessage> = (0..3).flat_map(|offset| messages(day(offset), 5)).collect();
+        {
+            let store = partitioned(&dir).await;
+            store.insert_batch(&inserted).await.unwrap();
+            // Partitions from before the catalog numbered their messages from 1 each
+            let partitions = store.partitions.read().await;
+            for (i, partition) in partitions.values().enumerate() {
+                partition.shift_seqs(-5 * i as i64).await.unwrap();
+                assert_eq!(partition.seq_range().await.unwrap(), Some((1, 5)));
+            }
+            sqlx::query("DELETE FROM sequences").execute(&store.catalog).await.unwrap();
+            for partition in partitions.values() {
+                partition.close().await;
+            }
+        }
+
+        let store = partitioned(&dir).await;
+        let mut numbered = Vec::new();
+        for partition in store.partitions.read().await.values() {
+            numbered.push(partition.seq_range().await.unwrap());
+        }
+        assert_eq!(numbered, vec![Some((1, 5)), Some((6, 10)), Some((11, 15))]);
+
+        let page = Page { limit: 100, offset: 0, cursor: Some(Cursor::After(0)) };
+        let entries = store.query(&LogFilter::All, page).await.unwrap();
+        let ids: Vec<&str> = entries.iter().map(|entry| entry.message.id.as_str()).collect();
+        let expected: Vec<&str> = inserted.iter().map(|message| message.id.as_str()).collect();
+        assert_eq!(ids, expected);
+
+        store.insert_batch(&messages(day(3), 1)).await.unwrap();
+        let newest = store.query(&LogFilter::All, Page { limit: 1, offset: 0, cursor: None }).await.unwrap();
+        assert_eq!(newest[0].seq, 16);
+        drop(store);
+
+        // Once the catalog numbers messages, reopening leaves them alone
+        let store = partitioned(&dir).await;
+        assert_eq!(page_through(&store, &LogFilter::All, 4, false).await, (1..=16).rev().collect::<Vec<i64>>());
+    }
+}
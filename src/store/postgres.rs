@@ -0,0 +1,545 @@
+use super::{
+    filter::{render, Dialect, SqlValue},
+    migrations,
+    rollup::{self, TimeSeriesPoint, TimeSeriesQuery},
+    HoldCriteria, LegalHold, LogEntry, LogFilter, LogStore, MessageTotals, Page, StoreStats, COUNTS_QUERY, ENTRY_COLUMNS, MESSAGE_COLUMNS,
+};
+use crate::{
+    search::{highlight, SNIPPET_WORDS},
+    signing::{SignatureStatus, SignatureUpdate},
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+use sqlx::{
+    postgres::{PgConnection, PgPool, PgPoolOptions, PgRow},
+    Connection, Row,
+};
+use syslog_parser::SyslogMessage;
+use tracing::info;
+
+/// Messages in PostgreSQL, with native timestamps, structured data as JSONB and
+/// full-text search over a generated `tsvector` column
+#[derive(Debug)]
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    pub async fn open(url: &str, max_connections: u32) -> Result<PostgresStore> {
+        info!("Connecting to PostgreSQL ({} connections)", max_connections);
+
+        let pool = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .connect(url)
+            .await
+            .context("failed to connect to PostgreSQL")?;
+
+        migrations::migrate_postgres(&pool)
+            .await
+            .context("failed to migrate the database schema")?;
+
+        info!("Database initialized successfully");
+        Ok(PostgresStore { pool })
+    }
+}
+
+/// Prints the migrations `open` would apply, without changing the database
+pub async fn dry_run_migrations(url: &str) -> Result<()> {
+    let mut conn = PgConnection::connect(url)
+        .await
+        .context("failed to connect to PostgreSQL")?;
+
+    migrations::dry_run_postgres(&mut conn).await
+}
+
+/// Rewrites the `?` placeholders of rendered SQL as `$1`, `$2`, ...
+fn numbered(sql: &str) -> String {
+    let mut numbered = String::with_capacity(sql.len() + 16);
+    let mut index = 0;
+    for c in sql.chars() {
+        if c == '?' {
+            index += 1;
+            numbered.push('$');
+            numbered.push_str(&index.to_string());
+        } else {
+            numbered.push(c);
+        }
+    }
+    numbered
+}
+
+macro_rules! bind_values {
+    ($query:expr, $values:expr) => {{
+        let mut query = $query;
+        for value in $values {
+            query = match value {
+                SqlValue::Int(value) => query.bind(value),
+                SqlValue::Text(value) => query.bind(value),
+                SqlValue::Time(value) => query.bind(value),
+            };
+        }
+        query
+    }};
+}
+
+#[async_trait]
+impl LogStore for PostgresStore {
+    async fn insert_batch(&self, messages: &[SyslogMessage]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        for message in messages {
+            sqlx::query(
+                r#"
+                INSERT INTO syslog_messages
+                (id, timestamp, facility, severity, hostname, app_name, proc_id, msg_id, structured_data, message, raw_message, source_ip, parse_error, original_bytes)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+                "#
+            )
+            .bind(&message.id)
+            .bind(message.timestamp)
+            .bind(i16::from(message.facility))
+            .bind(i16::from(message.severity))
+            .bind(message.hostname.as_deref().map(without_nul))
+            .bind(message.app_name.as_deref().map(without_nul))
+            .bind(message.proc_id.as_deref().map(without_nul))
+            .bind(message.msg_id.as_deref().map(without_nul))
+            .bind(message.structured_data.as_deref().map(|sd| structured_data_to_json(&without_nul(sd))))
+            .bind(without_nul(&message.message))
+            .bind(without_nul(&message.raw_message))
+            .bind(without_nul(&message.source_ip))
+            .bind(message.parse_error.as_deref().map(without_nul))
+            .bind(&message.original_bytes)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn query(&self, filter: &LogFilter, page: Page) -> Result<Vec<LogEntry>> {
+        let mut binds = Vec::new();
+
+        // ts_headline is costly, so PostgreSQL only evaluates it for the rows on the page
+        let snippet = match filter.search() {
+            Some(search) => {
+                binds.push(SqlValue::Text(search.to_tsquery()));
+                format!(
+                    "ts_headline('simple', message, to_tsquery('simple', ?), 'StartSel=' || chr(2) || ', StopSel=' || chr(3) || ', MaxWords={}, MinWords={}')",
+                    SNIPPET_WORDS,
+                    SNIPPET_WORDS / 2
+                )
+            }
+            None => "NULL::TEXT".to_string(),
+        };
+
+        let (cursor, order) = page.render(&mut binds);
+        let sql = numbered(&format!(
+            "SELECT {}, {}, {} AS snippet FROM syslog_messages WHERE {} AND {} ORDER BY {} LIMIT ? OFFSET ?",
+            MESSAGE_COLUMNS,
+            ENTRY_COLUMNS,
+            snippet,
+            cursor,
+            render(filter, Dialect::Postgres, &mut binds),
+            order
+        ));
+        binds.push(SqlValue::Int(page.limit as i64));
+        binds.push(SqlValue::Int(page.offset as i64));
+
+        let rows = bind_values!(sqlx::query(&sql), binds).fetch_all(&self.pool).await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| entry_from_row(row, row.get::<Option<String>, _>("snippet").as_deref().map(highlight)))
+            .collect())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<SyslogMessage>> {
+        let row = sqlx::query(&format!("SELECT {} FROM syslog_messages WHERE id = $1 AND deleted_at IS NULL", MESSAGE_COLUMNS))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.as_ref().map(message_from_row))
+    }
+
+    async fn oldest(&self, filter: &LogFilter, limit: usize) -> Result<Vec<LogEntry>> {
+        let mut binds = Vec::new();
+        let sql = numbered(&format!(
+            "SELECT {}, {} FROM syslog_messages WHERE {} ORDER BY timestamp ASC LIMIT ?",
+            MESSAGE_COLUMNS,
+            ENTRY_COLUMNS,
+            render(filter, Dialect::Postgres, &mut binds)
+        ));
+        binds.push(SqlValue::Int(limit as i64));
+
+        let rows = bind_values!(sqlx::query(&sql), binds).fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(|row| entry_from_row(row, None)).collect())
+    }
+
+    async fn delete(&self, filter: &LogFilter, limit: Option<u64>) -> Result<u64> {
+        let mut binds = Vec::new();
+        let condition = render(filter, Dialect::Postgres, &mut binds);
+        let sql = numbered(&match limit {
+            Some(limit) => {
+                binds.push(SqlValue::Int(limit as i64));
+                format!(
+                    "DELETE FROM syslog_messages WHERE id IN (SELECT id FROM syslog_messages WHERE {} ORDER BY timestamp ASC LIMIT ?)",
+                    condition
+                )
+            }
+            None => format!("DELETE FROM syslog_messages WHERE {}", condition),
+        });
+
+        let result = bind_values!(sqlx::query(&sql), binds).execute(&self.pool).await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn trash(&self, filter: &LogFilter, limit: Option<u64>) -> Result<u64> {
+        let mut binds = Vec::new();
+        let condition = render(filter, Dialect::Postgres, &mut binds);
+        let sql = numbered(&match limit {
+            Some(limit) => {
+                binds.push(SqlValue::Int(limit as i64));
+                format!(
+                    "UPDATE syslog_messages SET deleted_at = now() WHERE id IN (SELECT id FROM syslog_messages WHERE deleted_at IS NULL AND {} ORDER BY timestamp ASC LIMIT ?)",
+                    condition
+                )
+            }
+            None => format!("UPDATE syslog_messages SET deleted_at = now() WHERE deleted_at IS NULL AND {}", condition),
+        });
+
+        let result = bind_values!(sqlx::query(&sql), binds).execute(&self.pool).await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn restore(&self, filter: &LogFilter) -> Result<u64> {
+        let mut binds = Vec::new();
+        let sql = numbered(&format!(
+            "UPDATE syslog_messages SET deleted_at = NULL WHERE deleted_at IS NOT NULL AND {}",
+            render(filter, Dialect::Postgres, &mut binds)
+        ));
+
+        let result = bind_values!(sqlx::query(&sql), binds).execute(&self.pool).await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn legal_holds(&self) -> Result<Vec<LegalHold>> {
+        let rows: Vec<(i64, String, String, String, DateTime<Utc>, Option<DateTime<Utc>>)> = sqlx::query_as(
+            "SELECT id, reason, owner, criteria::TEXT, created_at, released_at FROM legal_holds ORDER BY id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|(id, reason, owner, criteria, created_at, released_at)| {
+                Ok(LegalHold {
+                    id,
+                    reason,
+                    owner,
+                    created_at,
+                    released_at,
+                    criteria: serde_json::from_str(&criteria)?,
+                })
+            })
+            .collect()
+    }
+
+    async fn add_legal_hold(&self, reason: &str, owner: &str, criteria: &HoldCriteria) -> Result<LegalHold> {
+        let (id, created_at): (i64, DateTime<Utc>) = sqlx::query_as(
+            "INSERT INTO legal_holds (reason, owner, criteria) VALUES ($1, $2, $3::JSONB) RETURNING id, created_at",
+        )
+        .bind(reason)
+        .bind(owner)
+        .bind(serde_json::to_string(criteria)?)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(LegalHold {
+            id,
+            reason: reason.to_string(),
+            owner: owner.to_string(),
+            created_at,
+            released_at: None,
+            criteria: criteria.clone(),
+        })
+    }
+
+    async fn release_legal_hold(&self, id: i64) -> Result<bool> {
+        let result = sqlx::query("UPDATE legal_holds SET released_at = now() WHERE id = $1 AND released_at IS NULL")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_signatures(&self, updates: &[SignatureUpdate]) -> Result<()> {
+        if updates.is_empty() {
+            return Ok(());
+        }
+
+        let mut tx = self.pool.begin().await?;
+        for update in updates {
+            sqlx::query("UPDATE syslog_messages SET signature_status = $1, signature_detail = $2 WHERE id = $3")
+                .bind(update.status.as_str())
+                .bind(&update.detail)
+                .bind(&update.id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn count(&self, filter: &LogFilter) -> Result<u64> {
+        let mut binds = Vec::new();
+        let sql = numbered(&format!(
+            "SELECT COUNT(*) FROM syslog_messages WHERE {}",
+            render(filter, Dialect::Postgres, &mut binds)
+        ));
+
+        let count: i64 = bind_values!(sqlx::query_scalar(&sql), binds)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count as u64)
+    }
+
+    async fn stats(&self) -> Result<StoreStats> {
+        let (stored_messages, trashed_messages): (i64, i64) =
+            sqlx::query_as("SELECT COUNT(*) FILTER (WHERE deleted_at IS NULL), COUNT(deleted_at) FROM syslog_messages")
+                .fetch_one(&self.pool)
+                .await?;
+
+        // The table's size on disk includes dead rows until autovacuum reclaims
+        // them, so it is not reported as the space used by stored messages
+        Ok(StoreStats {
+            backend: "postgres",
+            stored_messages: stored_messages as u64,
+            trashed_messages: trashed_messages as u64,
+            used_bytes: None,
+            partitions: None,
+            raw_bytes_saved: None,
+        })
+    }
+
+    async fn timeseries(&self, query: &TimeSeriesQuery) -> Result<Vec<TimeSeriesPoint>> {
+        let mut binds = Vec::new();
+        let sql = numbered(&rollup::render_query(query, Dialect::Postgres, &mut binds));
+        let rows: Vec<(DateTime<Utc>, Option<String>, i64)> =
+            bind_values!(sqlx::query_as(&sql), binds).fetch_all(&self.pool).await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(bucket, group, count)| TimeSeriesPoint { bucket, group, count: count as u64 })
+            .collect())
+    }
+
+    async fn counts(&self) -> Result<MessageTotals> {
+        let rows = sqlx::query_as(COUNTS_QUERY).fetch_all(&self.pool).await?;
+        let mut totals = MessageTotals::default();
+        totals.add_rows(&rows);
+        Ok(totals)
+    }
+}
+
+/// Reads a row selected with `ENTRY_COLUMNS` as well
+fn entry_from_row(row: &PgRow, snippet: Option<String>) -> LogEntry {
+    LogEntry {
+        seq: row.get("seq"),
+        message: message_from_row(row),
+        snippet,
+        signature_status: SignatureStatus::parse(row.get("signature_status")),
+        signature_detail: row.get("signature_detail"),
+        deleted_at: row.get("deleted_at"),
+    }
+}
+
+fn message_from_row(row: &PgRow) -> SyslogMessage {
+    let timestamp: DateTime<Utc> = row.get("timestamp");
+
+    SyslogMessage {
+        id: row.get("id"),
+        timestamp,
+        facility: row.get::<i16, _>("facility") as u8,
+        severity: row.get::<i16, _>("severity") as u8,
+        hostname: row.get("hostname"),
+        app_name: row.get("app_name"),
+        proc_id: row.get("proc_id"),
+        msg_id: row.get("msg_id"),
+        structured_data: row
+            .get::<Option<Value>, _>("structured_data")
+            .map(structured_data_from_json),
+        message: row.get("message"),
+        raw_message: row.get("raw_message"),
+        source_ip: row.get("source_ip"),
+        parse_error: row.get("parse_error"),
+        original_bytes: row.get("original_bytes"),
+    }
+}
+
+/// PostgreSQL rejects NUL in text and JSONB, so it is replaced before binding
+fn without_nul(text: &str) -> String {
+    text.replace('\0', "\u{FFFD}")
+}
+
+/// Converts RFC 5424 structured data to JSON so it can be queried, e.g.
+/// `[origin ip="192.0.2.1"]` becomes `[{"id": "origin", "params": {"ip": "192.0.2.1"}}]`.
+/// A parameter given more than once becomes an array of values. Anything that
+/// does not parse is kept as a JSON string.
+fn structured_data_to_json(sd: &str) -> Value {
+    parse_sd_elements(sd)
+        .map(Value::Array)
+        .unwrap_or_else(|| Value::String(sd.to_string()))
+}
+
+fn parse_sd_elements(sd: &str) -> Option<Vec<Value>> {
+    let mut elements = Vec::new();
+    let mut chars = sd.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '[' {
+            return None;
+        }
+
+        let mut id = String::new();
+        while let Some(c) = chars.next_if(|&c| c != ' ' && c != ']') {
+            id.push(c);
+        }
+        if id.is_empty() {
+            return None;
+        }
+
+        let mut params = Map::new();
+        loop {
+            match chars.next()? {
+                ']' => break,
+                ' ' => {
+                    let name: String = chars.by_ref().take_while(|&c| c != '=').collect();
+                    if name.is_empty() || chars.next()? != '"' {
+                        return None;
+                    }
+
+                    let mut value = String::new();
+                    loop {
+                        match chars.next()? {
+                            '"' => break,
+                            '\\' => match chars.next()? {
+                                escaped @ ('"' | '\\' | ']') => value.push(escaped),
+                                other => {
+                                    value.push('\\');
+                                    value.push(other);
+                                }
+                            },
+                            c => value.push(c),
+                        }
+                    }
+
+                    match params.get_mut(&name) {
+                        Some(Value::Array(values)) => values.push(Value::String(value)),
+                        Some(existing) => {
+                            let first = existing.take();
+                            *existing = Value::Array(vec![first, Value::String(value)]);
+                        }
+                        None => {
+                            params.insert(name, Value::String(value));
+                        }
+                    }
+                }
+                _ => return None,
+            }
+        }
+
+        let mut element = Map::new();
+        element.insert("id".to_string(), Value::String(id));
+        element.insert("params".to_string(), Value::Object(params));
+        elements.push(Value::Object(element));
+    }
+
+    Some(elements)
+}
+
+/// Rebuilds the structured data string. Parameter order follows JSONB's key
+/// order, so it can differ from the original; `raw_message` keeps that.
+fn structured_data_from_json(value: Value) -> String {
+    let elements = match value {
+        Value::String(sd) => return sd,
+        Value::Array(elements) => elements,
+        other => return other.to_string(),
+    };
+
+    let mut sd = String::new();
+    for element in elements {
+        sd.push('[');
+        sd.push_str(element.get("id").and_then(Value::as_str).unwrap_or_default());
+
+        if let Some(params) = element.get("params").and_then(Value::as_object) {
+            for (name, values) in params {
+                let values = match values {
+                    Value::Array(values) => values.iter().collect(),
+                    value => vec![value],
+                };
+                for value in values {
+                    sd.push(' ');
+                    sd.push_str(name);
+                    sd.push_str("=\"");
+                    for c in value.as_str().unwrap_or_default().chars() {
+                        if matches!(c, '"' | '\\' | ']') {
+                            sd.push('\\');
+                        }
+                        sd.push(c);
+                    }
+                    sd.push('"');
+                }
+            }
+        }
+        sd.push(']');
+    }
+    sd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Parameters are written in sorted order, as JSONB returns them
+    #[test]
+    fn structured_data_round_trips() {
+        let sd = r#"[origin ip="192.0.2.1" ip="192.0.2.2"][meta note="a \"quoted\" \] and \\" sequenceId="7"]"#;
+        let json = structured_data_to_json(sd);
+        assert_eq!(
+            json,
+            json!([
+                {"id": "origin", "params": {"ip": ["192.0.2.1", "192.0.2.2"]}},
+                {"id": "meta", "params": {"sequenceId": "7", "note": "a \"quoted\" ] and \\"}},
+            ])
+        );
+        assert_eq!(structured_data_from_json(json.clone()), sd);
+        assert_eq!(parse_sd_elements(&structured_data_from_json(json.clone())).map(Value::Array), Some(json));
+    }
+
+    #[test]
+    fn unparsable_structured_data_is_kept_as_text() {
+        for sd in ["-", "[origin", "[origin ip=192.0.2.1]", "[]", "[a x=\"1\"] trailing"] {
+            assert_eq!(parse_sd_elements(sd), None, "{sd}");
+            let json = structured_data_to_json(sd);
+            assert_eq!(json, Value::String(sd.to_string()));
+            assert_eq!(structured_data_from_json(json), sd);
+        }
+    }
+
+    #[test]
+    fn nul_is_replaced_in_structured_data() {
+        let sd = "[origin ip=\"192.0.2.1\0\"]";
+        let json = structured_data_to_json(&without_nul(sd));
+        assert!(!json.to_string().contains("\\u0000"));
+        assert_eq!(json, json!([{"id": "origin", "params": {"ip": "192.0.2.1\u{FFFD}"}}]));
+        assert_eq!(structured_data_from_json(json), "[origin ip=\"192.0.2.1\u{FFFD}\"]");
+
+        // Unparsable structured data goes in as a JSON string, which needs the same care
+        let json = structured_data_to_json(&without_nul("bad\0sd"));
+        assert_eq!(json, Value::String("bad\u{FFFD}sd".to_string()));
+    }
+}
@@ -0,0 +1,135 @@
+use super::{
+    filter::{render, Dialect, SqlValue},
+    LogFilter,
+};
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Most buckets `Resolution::auto` picks for a range, one day of minutes
+const AUTO_BUCKETS: i64 = 1440;
+
+/// Size of the buckets message counts are rolled up into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Resolution {
+    pub(crate) const ALL: [Resolution; 3] = [Resolution::Minute, Resolution::Hour, Resolution::Day];
+
+    pub fn step(self) -> TimeDelta {
+        match self {
+            Resolution::Minute => TimeDelta::minutes(1),
+            Resolution::Hour => TimeDelta::hours(1),
+            Resolution::Day => TimeDelta::days(1),
+        }
+    }
+
+    /// The finest resolution that covers the range in at most `AUTO_BUCKETS` buckets
+    pub fn auto(since: DateTime<Utc>, until: DateTime<Utc>) -> Resolution {
+        let range = until - since;
+        [Resolution::Minute, Resolution::Hour]
+            .into_iter()
+            .find(|resolution| range <= resolution.step() * AUTO_BUCKETS as i32)
+            .unwrap_or(Resolution::Day)
+    }
+
+    /// Number of buckets between `since` and `until`
+    pub fn buckets(self, since: DateTime<Utc>, until: DateTime<Utc>) -> i64 {
+        let range = until - self.floor(since);
+        (range.num_seconds() + self.step().num_seconds() - 1) / self.step().num_seconds()
+    }
+
+    /// Start of the bucket `instant` falls in
+    pub fn floor(self, instant: DateTime<Utc>) -> DateTime<Utc> {
+        instant.duration_trunc(self.step()).unwrap_or(instant)
+    }
+
+    pub(crate) fn table(self) -> &'static str {
+        match self {
+            Resolution::Minute => "rollup_minute",
+            Resolution::Hour => "rollup_hour",
+            Resolution::Day => "rollup_day",
+        }
+    }
+}
+
+/// Dimension a time series is split by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupBy {
+    SourceIp,
+    Hostname,
+    AppName,
+    Facility,
+    Severity,
+}
+
+impl GroupBy {
+    fn column(self) -> &'static str {
+        match self {
+            GroupBy::SourceIp => "source_ip",
+            GroupBy::Hostname => "hostname",
+            GroupBy::AppName => "app_name",
+            GroupBy::Facility => "facility",
+            GroupBy::Severity => "severity",
+        }
+    }
+}
+
+/// Message counts per bucket over `[since, until)`, read from the rollup tables.
+/// `filter` may only use the dimensions the rollups keep: facility, severity,
+/// hostname, app name and source IP.
+#[derive(Debug, Clone)]
+pub struct TimeSeriesQuery {
+    pub resolution: Resolution,
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    pub group_by: Option<GroupBy>,
+    pub filter: LogFilter,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TimeSeriesPoint {
+    pub bucket: DateTime<Utc>,
+    /// Value of the `group_by` dimension; empty when messages had no hostname or app name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    pub count: u64,
+}
+
+/// Renders the query over the rollup table for its resolution. Rows are
+/// `(bucket, group, count)`, ordered by bucket.
+pub(crate) fn render_query(query: &TimeSeriesQuery, dialect: Dialect, binds: &mut Vec<SqlValue>) -> String {
+    let group = match query.group_by {
+        Some(group_by) => format!("CAST({} AS TEXT)", group_by.column()),
+        None => "CAST(NULL AS TEXT)".to_string(),
+    };
+    binds.push(SqlValue::Time(query.resolution.floor(query.since)));
+    binds.push(SqlValue::Time(query.until));
+    let condition = render(&query.filter, dialect, binds);
+
+    format!(
+        "SELECT bucket, {} AS grp, CAST(SUM(count) AS BIGINT) FROM {} WHERE bucket >= ? AND bucket < ? AND {} GROUP BY bucket, grp ORDER BY bucket, grp",
+        group,
+        query.resolution.table(),
+        condition
+    )
+}
+
+/// Adds up points from several sources that may share buckets
+pub(crate) fn merge_points(points: impl IntoIterator<Item = TimeSeriesPoint>) -> Vec<TimeSeriesPoint> {
+    let mut merged: BTreeMap<(DateTime<Utc>, Option<String>), u64> = BTreeMap::new();
+    for point in points {
+        *merged.entry((point.bucket, point.group)).or_default() += point.count;
+    }
+
+    merged
+        .into_iter()
+        .map(|((bucket, group), count)| TimeSeriesPoint { bucket, group, count })
+        .collect()
+}
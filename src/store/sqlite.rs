@@ -0,0 +1,716 @@
+use super::{
+    chain::{ChainConfig, ChainReport, ChainVerifier, HashChain},
+    compression::{RawCodec, RawCompression},
+    filter::{render, Dialect, SqlValue},
+    migrations,
+    rollup::{self, TimeSeriesPoint, TimeSeriesQuery},
+    HoldCriteria, LegalHold, LogEntry, LogFilter, LogStore, MessageTotals, Page, Partitioning, StoreStats, COUNTS_QUERY, ENTRY_COLUMNS, MESSAGE_COLUMNS,
+};
+use crate::{
+    search::{highlight, SNIPPET_WORDS},
+    signing::{SignatureStatus, SignatureUpdate},
+};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{
+    sqlite::{
+        SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePool, SqlitePoolOptions, SqliteRow,
+        SqliteSynchronous,
+    },
+    ConnectOptions, Connection, Row,
+};
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+use syslog_parser::SyslogMessage;
+use tracing::{error, info, warn};
+
+/// Columns holding a compressed `raw_message`, read along with `MESSAGE_COLUMNS`
+const COMPRESSION_COLUMNS: &str = "raw_compressed, raw_length, raw_dictionary";
+
+/// Messages read per query while verifying the hash chain
+const VERIFY_PAGE: i64 = 1000;
+
+/// Characters of a deletion's filter kept in its chain event
+const MAX_FILTER_DESCRIPTION: usize = 200;
+
+/// Problems reported by the integrity check before it gives up listing them
+const MAX_INTEGRITY_ERRORS: u32 = 20;
+
+/// How thoroughly the database is checked for corruption at startup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum IntegrityCheck {
+    /// Skip the check
+    Off,
+    /// `PRAGMA quick_check`: page and record structure, without verifying index contents
+    Quick,
+    /// `PRAGMA integrity_check`: also verifies every index, slower on large databases
+    Full,
+}
+
+/// Where the database lives and how connections to it are tuned
+#[derive(Debug, Clone)]
+pub struct DatabaseConfig {
+    pub path: PathBuf,
+    pub max_connections: u32,
+    pub busy_timeout: Duration,
+    pub mmap_size: u64,
+    pub integrity_check: IntegrityCheck,
+    pub partitioning: Partitioning,
+    pub raw_compression: RawCompression,
+    /// Links messages into a signed hash chain; single-file SQLite only
+    pub chain: Option<ChainConfig>,
+    /// Messages `--storage memory` holds before forgetting the oldest
+    pub memory_capacity: usize,
+}
+
+impl DatabaseConfig {
+    pub(super) fn connect_options(&self) -> SqliteConnectOptions {
+        SqliteConnectOptions::new()
+            .filename(&self.path)
+            .create_if_missing(true)
+            // WAL lets the API read while the writer task commits; NORMAL only
+            // risks the last transactions on power loss, never corruption
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(self.busy_timeout)
+            .pragma("mmap_size", self.mmap_size.to_string())
+    }
+}
+
+/// Messages in a local SQLite database, searched through the `syslog_fts` index
+#[derive(Debug)]
+pub struct SqliteStore {
+    pool: SqlitePool,
+    codec: RawCodec,
+    chain: Option<HashChain>,
+}
+
+impl SqliteStore {
+    /// Opens the database, checks it for corruption and brings its schema up to date
+    pub async fn open(config: &DatabaseConfig) -> Result<SqliteStore> {
+        let pool = open_pool(config).await?;
+        let codec = RawCodec::load(&pool, config.raw_compression).await?;
+        let chain = match &config.chain {
+            Some(chain) => Some(HashChain::open(&pool, chain).await?),
+            None => None,
+        };
+        Ok(SqliteStore { pool, codec, chain })
+    }
+
+    /// Waits for queries in flight, then closes every connection
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+async fn open_pool(config: &DatabaseConfig) -> Result<SqlitePool> {
+    info!("Opening database {} ({} connections)", config.path.display(), config.max_connections);
+
+    let pool = SqlitePoolOptions::new()
+        .max_connections(config.max_connections)
+        .connect_with(config.connect_options())
+        .await
+        .with_context(|| format!("failed to open database {}", config.path.display()))?;
+
+    check_integrity(&pool, config).await?;
+
+    migrations::migrate_sqlite(&pool)
+        .await
+        .context("failed to migrate the database schema")?;
+
+    info!("Database initialized successfully");
+    Ok(pool)
+}
+
+async fn check_integrity(pool: &SqlitePool, config: &DatabaseConfig) -> Result<()> {
+    let pragma = match config.integrity_check {
+        IntegrityCheck::Off => return Ok(()),
+        IntegrityCheck::Quick => "quick_check",
+        IntegrityCheck::Full => "integrity_check",
+    };
+
+    info!("Running {} on {}", pragma, config.path.display());
+    let problems: Vec<String> = sqlx::query_scalar(&format!("PRAGMA {}({})", pragma, MAX_INTEGRITY_ERRORS))
+        .fetch_all(pool)
+        .await
+        .with_context(|| format!("{} could not read {}; {}", pragma, config.path.display(), recovery_hint(config)))?;
+
+    if problems.len() == 1 && problems[0] == "ok" {
+        return Ok(());
+    }
+
+    for problem in &problems {
+        error!("Integrity check: {}", problem);
+    }
+    bail!(
+        "database {} is corrupt ({} problems reported by {}); {}",
+        config.path.display(),
+        problems.len(),
+        pragma,
+        recovery_hint(config)
+    )
+}
+
+fn recovery_hint(config: &DatabaseConfig) -> String {
+    format!(
+        "restore a backup or salvage it with `sqlite3 {} .recover`, or start with --integrity-check off to run anyway",
+        config.path.display()
+    )
+}
+
+/// Prints the migrations `open` would apply, without creating or changing the database
+pub async fn dry_run_migrations(config: &DatabaseConfig) -> Result<()> {
+    let mut conn = if config.path.exists() {
+        SqliteConnectOptions::new()
+            .filename(&config.path)
+            .read_only(true)
+            .connect()
+            .await
+            .with_context(|| format!("failed to open database {}", config.path.display()))?
+    } else {
+        println!("{} does not exist yet; it will be created with the full schema.", config.path.display());
+        SqliteConnection::connect("sqlite::memory:").await?
+    };
+
+    migrations::dry_run_sqlite(&mut conn).await
+}
+
+/// Binds rendered filter values in order; SQLite stores timestamps as RFC 3339 text
+macro_rules! bind_values {
+    ($query:expr, $values:expr) => {{
+        let mut query = $query;
+        for value in $values {
+            query = match value {
+                SqlValue::Int(value) => query.bind(value),
+                SqlValue::Text(value) => query.bind(value),
+                SqlValue::Time(value) => query.bind(value.to_rfc3339()),
+            };
+        }
+        query
+    }};
+}
+
+/// Reads rollups from any database with the rollup tables, which the partition
+/// catalog shares with the partitions
+pub(super) async fn timeseries(pool: &SqlitePool, query: &TimeSeriesQuery) -> Result<Vec<TimeSeriesPoint>> {
+    let mut binds = Vec::new();
+    let sql = rollup::render_query(query, Dialect::Sqlite, &mut binds);
+    let rows: Vec<(String, Option<String>, i64)> = bind_values!(sqlx::query_as(&sql), binds).fetch_all(pool).await?;
+
+    rows.into_iter()
+        .map(|(bucket, group, count)| {
+            Ok(TimeSeriesPoint {
+                bucket: DateTime::parse_from_rfc3339(&bucket)
+                    .with_context(|| format!("invalid rollup bucket {}", bucket))?
+                    .with_timezone(&Utc),
+                group,
+                count: count as u64,
+            })
+        })
+        .collect()
+}
+
+/// Legal holds are kept in the database they protect, or the partition catalog
+pub(super) async fn legal_holds(pool: &SqlitePool) -> Result<Vec<LegalHold>> {
+    let rows: Vec<(i64, String, String, String, String, Option<String>)> =
+        sqlx::query_as("SELECT id, reason, owner, criteria, created_at, released_at FROM legal_holds ORDER BY id")
+            .fetch_all(pool)
+            .await?;
+
+    rows.into_iter()
+        .map(|(id, reason, owner, criteria, created_at, released_at)| {
+            Ok(LegalHold {
+                id,
+                reason,
+                owner,
+                created_at: parse_time(&created_at).unwrap_or_default(),
+                released_at: released_at.as_deref().and_then(parse_time),
+                criteria: serde_json::from_str(&criteria)?,
+            })
+        })
+        .collect()
+}
+
+pub(super) async fn add_legal_hold(pool: &SqlitePool, reason: &str, owner: &str, criteria: &HoldCriteria) -> Result<LegalHold> {
+    let created_at = Utc::now();
+    let id: i64 = sqlx::query_scalar("INSERT INTO legal_holds (reason, owner, criteria, created_at) VALUES (?, ?, ?, ?) RETURNING id")
+        .bind(reason)
+        .bind(owner)
+        .bind(serde_json::to_string(criteria)?)
+        .bind(created_at.to_rfc3339())
+        .fetch_one(pool)
+        .await?;
+
+    Ok(LegalHold {
+        id,
+        reason: reason.to_string(),
+        owner: owner.to_string(),
+        created_at,
+        released_at: None,
+        criteria: criteria.clone(),
+    })
+}
+
+pub(super) async fn release_legal_hold(pool: &SqlitePool, id: i64) -> Result<bool> {
+    let result = sqlx::query("UPDATE legal_holds SET released_at = ? WHERE id = ? AND released_at IS NULL")
+        .bind(Utc::now().to_rfc3339())
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Reserves `count` numbers from the `sequences` table, returning the first
+pub(super) async fn reserve_seqs(conn: &mut SqliteConnection, count: usize) -> Result<i64> {
+    let last: i64 = sqlx::query_scalar("UPDATE sequences SET value = value + ? WHERE name = 'messages' RETURNING value")
+        .bind(count as i64)
+        .fetch_one(conn)
+        .await?;
+    Ok(last - count as i64 + 1)
+}
+
+impl SqliteStore {
+    /// Lowest and highest `seq` stored, if any
+    pub(super) async fn seq_range(&self) -> Result<Option<(i64, i64)>> {
+        let (min, max): (Option<i64>, Option<i64>) = sqlx::query_as("SELECT MIN(seq), MAX(seq) FROM syslog_messages")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(min.zip(max))
+    }
+
+    /// Shifts every `seq` up by `offset`, going through negative numbers so the
+    /// unique index never sees two rows with one number
+    pub(super) async fn shift_seqs(&self, offset: i64) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE syslog_messages SET seq = -seq").execute(&mut *tx).await?;
+        sqlx::query("UPDATE syslog_messages SET seq = ? - seq")
+            .bind(offset)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Stores messages numbered from `first_seq`, or from this database's own
+    /// sequence when the caller does not number them
+    pub(super) async fn insert_numbered(&self, messages: &[SyslogMessage], first_seq: Option<i64>) -> Result<()> {
+        let seqs = first_seq.map(|first| (first..).take(messages.len()).collect());
+        self.insert_rows(messages, seqs, &[]).await
+    }
+
+    /// Stores messages under `seqs`, or numbered from this database's own sequence,
+    /// then gives the rows of `archived` back their signature status and trash state
+    async fn insert_rows(&self, messages: &[SyslogMessage], seqs: Option<Vec<i64>>, archived: &[LogEntry]) -> Result<()> {
+        let compressed = self
+            .codec
+            .compress_all(messages.iter().map(|message| message.raw_message.as_str()))?;
+        // The head only moves once the batch is committed
+        let mut head = match &self.chain {
+            Some(chain) => Some(chain.lock().await),
+            None => None,
+        };
+        let mut next = head.as_deref().cloned();
+        let mut tx = self.pool.begin().await?;
+        let seqs = match seqs {
+            Some(seqs) => seqs,
+            None => {
+                let first = reserve_seqs(&mut tx, messages.len()).await?;
+                (first..).take(messages.len()).collect()
+            }
+        };
+
+        for ((message, compressed), seq) in messages.iter().zip(&compressed).zip(seqs) {
+            let raw_message = match compressed {
+                Some(_) => "",
+                None => message.raw_message.as_str(),
+            };
+            let link = next.as_mut().map(|head| HashChain::link(head, message)).transpose()?;
+            sqlx::query(
+                r#"
+                INSERT INTO syslog_messages
+                (id, timestamp, facility, severity, hostname, app_name, proc_id, msg_id, structured_data, message, raw_message, source_ip, parse_error, original_bytes, raw_compressed, raw_length, raw_dictionary, chain_seq, prev_hash, hash, seq)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#
+            )
+            .bind(&message.id)
+            .bind(message.timestamp.to_rfc3339())
+            .bind(message.facility as i32)
+            .bind(message.severity as i32)
+            .bind(&message.hostname)
+            .bind(&message.app_name)
+            .bind(&message.proc_id)
+            .bind(&message.msg_id)
+            .bind(&message.structured_data)
+            .bind(&message.message)
+            .bind(raw_message)
+            .bind(&message.source_ip)
+            .bind(&message.parse_error)
+            .bind(&message.original_bytes)
+            .bind(compressed.as_ref().map(|compressed| &compressed.data))
+            .bind(compressed.as_ref().map(|_| message.raw_message.len() as i64))
+            .bind(compressed.as_ref().and_then(|compressed| compressed.dictionary))
+            .bind(link.as_ref().map(|link| link.seq))
+            .bind(link.as_ref().map(|link| &link.prev_hash))
+            .bind(link.as_ref().map(|link| &link.hash))
+            .bind(seq)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        // Trashing after the insert keeps the stored counts right
+        for entry in archived {
+            sqlx::query("UPDATE syslog_messages SET signature_status = ?, signature_detail = ?, deleted_at = ? WHERE seq = ?")
+                .bind(entry.signature_status.unwrap_or(SignatureStatus::Unsigned).as_str())
+                .bind(&entry.signature_detail)
+                .bind(entry.deleted_at.map(|deleted_at| deleted_at.to_rfc3339()))
+                .bind(entry.seq)
+                .execute(&mut *tx)
+                .await?;
+        }
+        if let Some(last) = archived.iter().map(|entry| entry.seq).max() {
+            sqlx::query("UPDATE sequences SET value = MAX(value, ?) WHERE name = 'messages'")
+                .bind(last)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        if let (Some(chain), Some(next)) = (&self.chain, next.as_mut()) {
+            chain.checkpoint_if_due(&mut tx, next).await?;
+        }
+        tx.commit().await?;
+        if let (Some(head), Some(next)) = (head.as_mut(), next) {
+            **head = next;
+        }
+        drop(head);
+
+        // The batch is stored either way, so a failed training only costs compression
+        if let Err(e) = self.codec.train_if_ready(&self.pool, messages.len()).await {
+            warn!("Failed to train a raw message dictionary: {}", e);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LogStore for SqliteStore {
+    async fn insert_batch(&self, messages: &[SyslogMessage]) -> Result<()> {
+        self.insert_numbered(messages, None).await
+    }
+
+    async fn query(&self, filter: &LogFilter, page: Page) -> Result<Vec<LogEntry>> {
+        let mut binds = Vec::new();
+        let (cursor, order) = page.render(&mut binds);
+        let sql = format!(
+            "SELECT {}, {}, {} FROM syslog_messages WHERE {} AND {} ORDER BY {} LIMIT ? OFFSET ?",
+            MESSAGE_COLUMNS,
+            COMPRESSION_COLUMNS,
+            ENTRY_COLUMNS,
+            cursor,
+            render(filter, Dialect::Sqlite, &mut binds),
+            order
+        );
+        binds.push(SqlValue::Int(page.limit as i64));
+        binds.push(SqlValue::Int(page.offset as i64));
+
+        let rows = bind_values!(sqlx::query(&sql), binds).fetch_all(&self.pool).await?;
+
+        // Snippets are only built for the page being returned, not every match
+        let mut excerpts = match filter.search() {
+            Some(search) => {
+                let seqs: Vec<i64> = rows.iter().map(|row| row.get("seq")).collect();
+                self.snippets(&search.to_fts5(), &seqs).await?
+            }
+            None => HashMap::new(),
+        };
+
+        rows.iter()
+            .map(|row| self.entry_from_row(row, excerpts.remove(&row.get::<i64, _>("seq"))))
+            .collect()
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<SyslogMessage>> {
+        let row = sqlx::query(&format!(
+            "SELECT {}, {} FROM syslog_messages WHERE id = ? AND deleted_at IS NULL",
+            MESSAGE_COLUMNS, COMPRESSION_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref().map(|row| self.message_from_row(row)).transpose()
+    }
+
+    async fn oldest(&self, filter: &LogFilter, limit: usize) -> Result<Vec<LogEntry>> {
+        let mut binds = Vec::new();
+        let sql = format!(
+            "SELECT {}, {}, {} FROM syslog_messages WHERE {} ORDER BY timestamp ASC LIMIT ?",
+            MESSAGE_COLUMNS,
+            COMPRESSION_COLUMNS,
+            ENTRY_COLUMNS,
+            render(filter, Dialect::Sqlite, &mut binds)
+        );
+        binds.push(SqlValue::Int(limit as i64));
+
+        let rows = bind_values!(sqlx::query(&sql), binds).fetch_all(&self.pool).await?;
+        rows.iter().map(|row| self.entry_from_row(row, None)).collect()
+    }
+
+    async fn delete(&self, filter: &LogFilter, limit: Option<u64>) -> Result<u64> {
+        let mut binds = Vec::new();
+        let condition = render(filter, Dialect::Sqlite, &mut binds);
+        let sql = match limit {
+            Some(limit) => {
+                binds.push(SqlValue::Int(limit as i64));
+                format!(
+                    "DELETE FROM syslog_messages WHERE id IN (SELECT id FROM syslog_messages WHERE {} ORDER BY timestamp ASC LIMIT ?)",
+                    condition
+                )
+            }
+            None => format!("DELETE FROM syslog_messages WHERE {}", condition),
+        };
+
+        let Some(chain) = &self.chain else {
+            let result = bind_values!(sqlx::query(&sql), binds).execute(&self.pool).await?;
+            return Ok(result.rows_affected());
+        };
+
+        // The deleted links are recorded in a signed event committed with the
+        // delete, so verification can tell them from tampering
+        let head = chain.lock().await;
+        let mut tx = self.pool.begin().await?;
+        let sql = format!("{} RETURNING chain_seq, prev_hash, hash", sql);
+        let deleted = bind_values!(sqlx::query(&sql), binds)
+            .fetch_all(&mut *tx)
+            .await?;
+
+        let mut links: Vec<(i64, String, String)> = deleted
+            .iter()
+            .filter_map(|row| Some((row.get::<Option<i64>, _>(0)?, row.get(1), row.get(2))))
+            .collect();
+        if !links.is_empty() {
+            links.sort_unstable_by_key(|(seq, _, _)| *seq);
+            let description: String = format!("{:?}", filter).chars().take(MAX_FILTER_DESCRIPTION).collect();
+            chain.record_deletion(&mut tx, &head, &description, &links).await?;
+        }
+        tx.commit().await?;
+        Ok(deleted.len() as u64)
+    }
+
+    async fn count(&self, filter: &LogFilter) -> Result<u64> {
+        let mut binds = Vec::new();
+        let sql = format!(
+            "SELECT COUNT(*) FROM syslog_messages WHERE {}",
+            render(filter, Dialect::Sqlite, &mut binds)
+        );
+
+        let count: i64 = bind_values!(sqlx::query_scalar(&sql), binds).fetch_one(&self.pool).await?;
+        Ok(count as u64)
+    }
+
+    async fn trash(&self, filter: &LogFilter, limit: Option<u64>) -> Result<u64> {
+        let mut binds = vec![SqlValue::Time(Utc::now())];
+        let condition = render(filter, Dialect::Sqlite, &mut binds);
+        let sql = match limit {
+            Some(limit) => {
+                binds.push(SqlValue::Int(limit as i64));
+                format!(
+                    "UPDATE syslog_messages SET deleted_at = ? WHERE id IN (SELECT id FROM syslog_messages WHERE deleted_at IS NULL AND {} ORDER BY timestamp ASC LIMIT ?)",
+                    condition
+                )
+            }
+            None => format!("UPDATE syslog_messages SET deleted_at = ? WHERE deleted_at IS NULL AND {}", condition),
+        };
+
+        let result = bind_values!(sqlx::query(&sql), binds).execute(&self.pool).await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn restore(&self, filter: &LogFilter) -> Result<u64> {
+        let mut binds = Vec::new();
+        let sql = format!(
+            "UPDATE syslog_messages SET deleted_at = NULL WHERE deleted_at IS NOT NULL AND {}",
+            render(filter, Dialect::Sqlite, &mut binds)
+        );
+
+        let result = bind_values!(sqlx::query(&sql), binds).execute(&self.pool).await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn legal_holds(&self) -> Result<Vec<LegalHold>> {
+        legal_holds(&self.pool).await
+    }
+
+    async fn add_legal_hold(&self, reason: &str, owner: &str, criteria: &HoldCriteria) -> Result<LegalHold> {
+        add_legal_hold(&self.pool, reason, owner, criteria).await
+    }
+
+    async fn release_legal_hold(&self, id: i64) -> Result<bool> {
+        release_legal_hold(&self.pool, id).await
+    }
+
+    async fn set_signatures(&self, updates: &[SignatureUpdate]) -> Result<()> {
+        if updates.is_empty() {
+            return Ok(());
+        }
+
+        let mut tx = self.pool.begin().await?;
+        for update in updates {
+            sqlx::query("UPDATE syslog_messages SET signature_status = ?, signature_detail = ? WHERE id = ?")
+                .bind(update.status.as_str())
+                .bind(&update.detail)
+                .bind(&update.id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn insert_archived(&self, entries: &[LogEntry]) -> Result<()> {
+        if self.chain.is_some() {
+            bail!("archived messages cannot be added to a hash chain");
+        }
+        let messages: Vec<SyslogMessage> = entries.iter().map(|entry| entry.message.clone()).collect();
+        let seqs = entries.iter().map(|entry| entry.seq).collect();
+        self.insert_rows(&messages, Some(seqs), entries).await
+    }
+
+    async fn stats(&self) -> Result<StoreStats> {
+        // Deleted rows return their pages to the freelist, so used space shrinks
+        // immediately even though the file itself only shrinks on VACUUM
+        let (stored_messages, trashed_messages, page_count, freelist_count, page_size, raw_bytes_saved): (i64, i64, i64, i64, i64, i64) = sqlx::query_as(
+            "SELECT (SELECT COUNT(*) FROM syslog_messages WHERE deleted_at IS NULL), (SELECT COUNT(*) FROM syslog_messages WHERE deleted_at IS NOT NULL), (SELECT page_count FROM pragma_page_count), (SELECT freelist_count FROM pragma_freelist_count), (SELECT page_size FROM pragma_page_size), (SELECT COALESCE(SUM(raw_length - length(raw_compressed)), 0) FROM syslog_messages WHERE raw_compressed IS NOT NULL)"
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(StoreStats {
+            backend: "sqlite",
+            stored_messages: stored_messages as u64,
+            trashed_messages: trashed_messages as u64,
+            used_bytes: Some(((page_count - freelist_count) * page_size) as u64),
+            partitions: None,
+            raw_bytes_saved: (self.codec.enabled() || raw_bytes_saved > 0).then_some(raw_bytes_saved as u64),
+        })
+    }
+
+    async fn counts(&self) -> Result<MessageTotals> {
+        let rows = sqlx::query_as(COUNTS_QUERY).fetch_all(&self.pool).await?;
+        let mut totals = MessageTotals::default();
+        totals.add_rows(&rows);
+        Ok(totals)
+    }
+
+    async fn timeseries(&self, query: &TimeSeriesQuery) -> Result<Vec<TimeSeriesPoint>> {
+        timeseries(&self.pool, query).await
+    }
+
+    async fn verify_chain(&self) -> Result<ChainReport> {
+        let chain = self
+            .chain
+            .as_ref()
+            .context("the hash chain is not enabled; start with --chain-key")?;
+
+        // One read transaction, so the walk sees the rows and events of a single moment
+        let mut tx = self.pool.begin().await?;
+        let mut verifier = ChainVerifier::new(&mut tx, chain.verifying_key()).await?;
+        let sql = format!(
+            "SELECT chain_seq, prev_hash, hash, {}, {} FROM syslog_messages WHERE chain_seq > ? ORDER BY chain_seq LIMIT ?",
+            MESSAGE_COLUMNS, COMPRESSION_COLUMNS
+        );
+
+        let mut after = 0;
+        'pages: while !verifier.broken() {
+            let rows = sqlx::query(&sql).bind(after).bind(VERIFY_PAGE).fetch_all(&mut *tx).await?;
+            if rows.is_empty() {
+                break;
+            }
+            for row in &rows {
+                after = row.get("chain_seq");
+                if !verifier.check(after, row.get("prev_hash"), row.get("hash"), &self.message_from_row(row)?)? {
+                    break 'pages;
+                }
+            }
+        }
+
+        tx.rollback().await?;
+        Ok(verifier.finish())
+    }
+}
+
+impl SqliteStore {
+    /// Highlighted excerpts of the message column for the given rows, by seq
+    async fn snippets(&self, fts_query: &str, seqs: &[i64]) -> Result<HashMap<i64, String>> {
+        if seqs.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let sql = format!(
+            "SELECT rowid, snippet(syslog_fts, 0, char(2), char(3), '…', {}) AS snippet FROM syslog_fts WHERE syslog_fts MATCH ? AND rowid IN ({})",
+            SNIPPET_WORDS,
+            vec!["?"; seqs.len()].join(", ")
+        );
+
+        let mut snippet_query = sqlx::query(&sql).bind(fts_query);
+        for seq in seqs {
+            snippet_query = snippet_query.bind(seq);
+        }
+
+        let rows = snippet_query.fetch_all(&self.pool).await?;
+        Ok(rows
+            .iter()
+            .map(|row| (row.get("rowid"), highlight(row.get("snippet"))))
+            .collect())
+    }
+
+    /// Reads a row, decompressing its raw message if needed
+    fn message_from_row(&self, row: &SqliteRow) -> Result<SyslogMessage> {
+        let raw_message = match row.get::<Option<Vec<u8>>, _>("raw_compressed") {
+            Some(data) => self.codec.decompress(
+                &data,
+                row.get::<Option<i64>, _>("raw_length").unwrap_or_default() as usize,
+                row.get("raw_dictionary"),
+            )?,
+            None => row.get("raw_message"),
+        };
+        Ok(SyslogMessage { raw_message, ..message_from_row(row) })
+    }
+
+    /// Reads a row selected with `ENTRY_COLUMNS` as well
+    fn entry_from_row(&self, row: &SqliteRow, snippet: Option<String>) -> Result<LogEntry> {
+        Ok(LogEntry {
+            seq: row.get("seq"),
+            message: self.message_from_row(row)?,
+            snippet,
+            signature_status: SignatureStatus::parse(row.get("signature_status")),
+            signature_detail: row.get("signature_detail"),
+            deleted_at: row.get::<Option<String>, _>("deleted_at").as_deref().and_then(parse_time),
+        })
+    }
+}
+
+fn parse_time(text: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(text).ok().map(|time| time.with_timezone(&Utc))
+}
+
+fn message_from_row(row: &SqliteRow) -> SyslogMessage {
+    let timestamp_str: String = row.get("timestamp");
+    let timestamp = parse_time(&timestamp_str).unwrap_or_else(Utc::now);
+
+    SyslogMessage {
+        id: row.get("id"),
+        timestamp,
+        facility: row.get::<i32, _>("facility") as u8,
+        severity: row.get::<i32, _>("severity") as u8,
+        hostname: row.get("hostname"),
+        app_name: row.get("app_name"),
+        proc_id: row.get("proc_id"),
+        msg_id: row.get("msg_id"),
+        structured_data: row.get("structured_data"),
+        message: row.get("message"),
+        raw_message: row.get("raw_message"),
+        source_ip: row.get("source_ip"),
+        parse_error: row.get("parse_error"),
+        original_bytes: row.get("original_bytes"),
+    }
+}
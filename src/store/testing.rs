@@ -0,0 +1,109 @@
+//! Scratch databases and messages shared by the storage tests
+
+use super::{
+    DatabaseConfig, IntegrityCheck, LogStore, MemoryStore, PartitionedStore, Partitioning, RawCompression, SqliteStore,
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use syslog_parser::SyslogMessage;
+use uuid::Uuid;
+
+/// A directory under the system temp dir, removed with everything in it on drop
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        let path = std::env::temp_dir().join(format!("syslog-parser-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// A single-file database in this directory, without a hash chain
+    pub fn config(&self) -> DatabaseConfig {
+        DatabaseConfig {
+            path: self.0.join("syslog.db"),
+            max_connections: 2,
+            busy_timeout: std::time::Duration::from_secs(5),
+            mmap_size: 0,
+            integrity_check: IntegrityCheck::Off,
+            partitioning: Partitioning::None,
+            raw_compression: RawCompression::Zstd,
+            chain: None,
+            memory_capacity: 1000,
+        }
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Midnight UTC on a fixed day, so tests do not depend on the clock
+pub fn day(offset: i64) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap() + Duration::days(offset)
+}
+
+/// A message received at `timestamp`, with everything else left plain
+pub fn message(timestamp: DateTime<Utc>, hostname: Option<&str>, text: &str) -> SyslogMessage {
+    SyslogMessage {
+        id: Uuid::new_v4().to_string(),
+        timestamp,
+        facility: 1,
+        severity: 6,
+        hostname: hostname.map(str::to_string),
+        app_name: Some("app".to_string()),
+        proc_id: None,
+        msg_id: None,
+        structured_data: None,
+        message: text.to_string(),
+        raw_message: format!("<14>{} {}", hostname.unwrap_or("-"), text),
+        source_ip: "192.0.2.1".to_string(),
+        parse_error: None,
+        original_bytes: None,
+    }
+}
+
+/// `count` messages a minute apart from `start`, numbered in their text
+pub fn messages(start: DateTime<Utc>, count: usize) -> Vec<SyslogMessage> {
+    (0..count)
+        .map(|i| message(start + Duration::minutes(i as i64), Some("host"), &format!("message {}", i)))
+        .collect()
+}
+
+/// The storage backends a test can run against, leaving PostgreSQL out as it needs a server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Memory,
+    Sqlite,
+    Partitioned,
+}
+
+/// Runs `test` against a fresh, empty store of each backend in turn
+pub async fn for_each_backend(test: impl AsyncFn(&dyn LogStore, Backend)) {
+    for backend in [Backend::Memory, Backend::Sqlite, Backend::Partitioned] {
+        let dir = TempDir::new();
+        let store: Box<dyn LogStore> = match backend {
+            Backend::Memory => Box::new(MemoryStore::new(1000)),
+            Backend::Sqlite => Box::new(SqliteStore::open(&dir.config()).await.unwrap()),
+            Backend::Partitioned => {
+                let config = DatabaseConfig {
+                    partitioning: Partitioning::Daily,
+                    ..dir.config()
+                };
+                Box::new(PartitionedStore::open(&config).await.unwrap())
+            }
+        };
+        // Shown with the assertion that fails
+        eprintln!("Testing the {:?} backend", backend);
+        test(store.as_ref(), backend).await;
+    }
+}
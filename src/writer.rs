@@ -0,0 +1,318 @@
+use crate::{queue::IngestQueue, store::LogStore, AppState};
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use syslog_parser::SyslogMessage;
+use tracing::{error, info, warn};
+
+/// Tries at writing a batch before its messages are given up
+const WRITE_ATTEMPTS: u32 = 4;
+
+/// Wait before the first retry, doubled before each one after
+const RETRY_BACKOFF: Duration = Duration::from_millis(250);
+
+/// How the writer task groups queued messages into transactions
+#[derive(Debug, Clone, Copy)]
+pub struct WriterConfig {
+    /// A batch is written as soon as it holds this many messages
+    pub batch_size: usize,
+    /// ...or once its oldest message has waited this long
+    pub flush_interval: Duration,
+}
+
+/// Counters describing the writer's throughput, updated after every batch
+#[derive(Debug, Default)]
+pub struct WriterMetrics {
+    batches: AtomicU64,
+    failed_batches: AtomicU64,
+    messages_written: AtomicU64,
+    dropped_messages: AtomicU64,
+    last_batch_size: AtomicU64,
+    max_batch_size: AtomicU64,
+    last_batch_latency_us: AtomicU64,
+    total_batch_latency_us: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WriterStats {
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    pub batches: u64,
+    pub failed_batches: u64,
+    pub messages_written: u64,
+    /// Messages lost with batches that still failed after every retry
+    pub dropped_messages: u64,
+    pub last_batch_size: u64,
+    pub max_batch_size: u64,
+    pub average_batch_size: f64,
+    pub last_batch_latency_ms: f64,
+    pub average_batch_latency_ms: f64,
+}
+
+impl WriterMetrics {
+    fn record_batch(&self, size: usize, latency: Duration, ok: bool) {
+        let size = size as u64;
+        let latency_us = latency.as_micros() as u64;
+
+        self.batches.fetch_add(1, Ordering::Relaxed);
+        if ok {
+            self.messages_written.fetch_add(size, Ordering::Relaxed);
+        } else {
+            self.failed_batches.fetch_add(1, Ordering::Relaxed);
+            self.dropped_messages.fetch_add(size, Ordering::Relaxed);
+        }
+        self.last_batch_size.store(size, Ordering::Relaxed);
+        self.max_batch_size.fetch_max(size, Ordering::Relaxed);
+        self.last_batch_latency_us.store(latency_us, Ordering::Relaxed);
+        self.total_batch_latency_us.fetch_add(latency_us, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self, queue: &IngestQueue) -> WriterStats {
+        let batches = self.batches.load(Ordering::Relaxed);
+        let failed_batches = self.failed_batches.load(Ordering::Relaxed);
+        let messages_written = self.messages_written.load(Ordering::Relaxed);
+        let successful_batches = batches - failed_batches;
+
+        WriterStats {
+            queue_depth: queue.len(),
+            queue_capacity: queue.capacity(),
+            batches,
+            failed_batches,
+            messages_written,
+            dropped_messages: self.dropped_messages.load(Ordering::Relaxed),
+            last_batch_size: self.last_batch_size.load(Ordering::Relaxed),
+            max_batch_size: self.max_batch_size.load(Ordering::Relaxed),
+            average_batch_size: if successful_batches > 0 {
+                messages_written as f64 / successful_batches as f64
+            } else {
+                0.0
+            },
+            last_batch_latency_ms: self.last_batch_latency_us.load(Ordering::Relaxed) as f64 / 1000.0,
+            average_batch_latency_ms: if batches > 0 {
+                self.total_batch_latency_us.load(Ordering::Relaxed) as f64 / batches as f64 / 1000.0
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+/// Drains the ingest queue forever, writing messages to the database in batched transactions
+pub async fn run_writer(queue: Arc<IngestQueue>, state: AppState, config: WriterConfig) {
+    info!(
+        "Storage writer started (batch size {}, flush interval {:?})",
+        config.batch_size, config.flush_interval
+    );
+
+    let mut batch = Vec::with_capacity(config.batch_size);
+
+    loop {
+        queue.recv_batch(&mut batch, config.batch_size, config.flush_interval).await;
+        write_batch(&mut batch, &state).await;
+    }
+}
+
+async fn write_batch(batch: &mut Vec<SyslogMessage>, state: &AppState) {
+    let started = Instant::now();
+    let result = insert_with_retry(state.store.as_ref(), batch, RETRY_BACKOFF).await;
+    state.writer_metrics.record_batch(batch.len(), started.elapsed(), result.is_ok());
+
+    match result {
+        Ok(()) => {
+            // Statuses are recorded once the rows exist, including for earlier
+            // messages a signature block in this batch vouches for
+            if let Some(verifier) = &state.signatures {
+                let updates = verifier.observe(batch);
+                if let Err(e) = state.store.set_signatures(&updates).await {
+                    warn!("Failed to record signature statuses: {}", e);
+                }
+            }
+            for message in batch.drain(..) {
+                record_stored(message, state);
+            }
+        }
+        Err(e) => {
+            error!("Dropping batch of {} messages after {} attempts: {}", batch.len(), WRITE_ATTEMPTS, e);
+            batch.clear();
+        }
+    }
+}
+
+/// Inserts a batch, retrying with backoff so that a passing database error
+/// does not lose it. Listeners keep queueing meanwhile, under the overflow policy.
+async fn insert_with_retry(store: &dyn LogStore, batch: &[SyslogMessage], backoff: Duration) -> Result<()> {
+    let mut pending = Cow::Borrowed(batch);
+    let mut backoff = backoff;
+    let mut attempt = 1;
+    loop {
+        let e = match store.insert_batch(&pending).await {
+            Err(e) if attempt < WRITE_ATTEMPTS => e,
+            result => return result,
+        };
+        warn!(
+            "Failed to write batch of {} messages (attempt {} of {}), retrying in {:?}: {}",
+            pending.len(),
+            attempt,
+            WRITE_ATTEMPTS,
+            backoff,
+            e
+        );
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+        attempt += 1;
+
+        // Partitioned storage commits each day separately, so part of the batch
+        // may be stored already, and an id cannot be inserted twice
+        let mut unstored = Vec::with_capacity(pending.len());
+        for message in pending.iter() {
+            if !matches!(store.get(&message.id).await, Ok(Some(_))) {
+                unstored.push(message.clone());
+            }
+        }
+        pending = Cow::Owned(unstored);
+    }
+}
+
+/// Notifies websocket clients once a message is stored. The database counts
+/// it for `/api/stats` as part of the insert.
+fn record_stored(message: SyslogMessage, state: &AppState) {
+    let _ = state.tx.send(message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        signing::SignatureUpdate,
+        store::{
+            testing::{day, messages},
+            ChainReport, HoldCriteria, LegalHold, LogEntry, LogFilter, MemoryStore, MessageTotals, Page,
+            StoreStats, TimeSeriesPoint, TimeSeriesQuery,
+        },
+    };
+    use anyhow::bail;
+    use async_trait::async_trait;
+    use std::sync::atomic::AtomicU32;
+
+    /// A memory store whose next `failures` inserts fail, after storing the
+    /// first `stored_on_failure` messages of the batch
+    #[derive(Debug)]
+    struct FailingStore {
+        inner: MemoryStore,
+        failures: AtomicU32,
+        stored_on_failure: usize,
+        inserts: AtomicU32,
+    }
+
+    impl FailingStore {
+        fn new(failures: u32, stored_on_failure: usize) -> FailingStore {
+            FailingStore {
+                inner: MemoryStore::new(100),
+                failures: AtomicU32::new(failures),
+                stored_on_failure,
+                inserts: AtomicU32::new(0),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl LogStore for FailingStore {
+        async fn insert_batch(&self, messages: &[SyslogMessage]) -> Result<()> {
+            self.inserts.fetch_add(1, Ordering::Relaxed);
+            if self.failures.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1)).is_ok() {
+                self.inner.insert_batch(&messages[..self.stored_on_failure.min(messages.len())]).await?;
+                bail!("database is locked");
+            }
+            self.inner.insert_batch(messages).await
+        }
+        async fn query(&self, filter: &LogFilter, page: Page) -> Result<Vec<LogEntry>> {
+            self.inner.query(filter, page).await
+        }
+        async fn get(&self, id: &str) -> Result<Option<SyslogMessage>> {
+            self.inner.get(id).await
+        }
+        async fn oldest(&self, filter: &LogFilter, limit: usize) -> Result<Vec<LogEntry>> {
+            self.inner.oldest(filter, limit).await
+        }
+        async fn delete(&self, filter: &LogFilter, limit: Option<u64>) -> Result<u64> {
+            self.inner.delete(filter, limit).await
+        }
+        async fn count(&self, filter: &LogFilter) -> Result<u64> {
+            self.inner.count(filter).await
+        }
+        async fn trash(&self, filter: &LogFilter, limit: Option<u64>) -> Result<u64> {
+            self.inner.trash(filter, limit).await
+        }
+        async fn restore(&self, filter: &LogFilter) -> Result<u64> {
+            self.inner.restore(filter).await
+        }
+        async fn legal_holds(&self) -> Result<Vec<LegalHold>> {
+            self.inner.legal_holds().await
+        }
+        async fn add_legal_hold(&self, reason: &str, owner: &str, criteria: &HoldCriteria) -> Result<LegalHold> {
+            self.inner.add_legal_hold(reason, owner, criteria).await
+        }
+        async fn release_legal_hold(&self, id: i64) -> Result<bool> {
+            self.inner.release_legal_hold(id).await
+        }
+        async fn set_signatures(&self, updates: &[SignatureUpdate]) -> Result<()> {
+            self.inner.set_signatures(updates).await
+        }
+        async fn stats(&self) -> Result<StoreStats> {
+            self.inner.stats().await
+        }
+        async fn counts(&self) -> Result<MessageTotals> {
+            self.inner.counts().await
+        }
+        async fn timeseries(&self, query: &TimeSeriesQuery) -> Result<Vec<TimeSeriesPoint>> {
+            self.inner.timeseries(query).await
+        }
+        async fn verify_chain(&self) -> Result<ChainReport> {
+            self.inner.verify_chain().await
+        }
+    }
+
+    const BACKOFF: Duration = Duration::from_millis(1);
+
+    #[tokio::test]
+    async fn a_failed_insert_is_retried() {
+        let store = FailingStore::new(1, 0);
+        let batch = messages(day(0), 3);
+        insert_with_retry(&store, &batch, BACKOFF).await.unwrap();
+        assert_eq!(store.inserts.load(Ordering::Relaxed), 2);
+        assert_eq!(store.count(&LogFilter::All).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn a_retry_skips_what_was_stored() {
+        let store = FailingStore::new(1, 2);
+        let batch = messages(day(0), 3);
+        insert_with_retry(&store, &batch, BACKOFF).await.unwrap();
+        assert_eq!(store.count(&LogFilter::All).await.unwrap(), 3);
+        for message in &batch {
+            assert!(store.get(&message.id).await.unwrap().is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn a_batch_is_dropped_after_every_attempt_fails() {
+        let store = FailingStore::new(u32::MAX, 0);
+        let batch = messages(day(0), 3);
+        assert!(insert_with_retry(&store, &batch, BACKOFF).await.is_err());
+        assert_eq!(store.inserts.load(Ordering::Relaxed), WRITE_ATTEMPTS);
+
+        let metrics = WriterMetrics::default();
+        metrics.record_batch(batch.len(), Duration::ZERO, false);
+        metrics.record_batch(2, Duration::ZERO, true);
+        let stats = metrics.snapshot(&IngestQueue::new(1, crate::queue::OverflowPolicy::Block));
+        assert_eq!((stats.batches, stats.failed_batches), (2, 1));
+        assert_eq!((stats.messages_written, stats.dropped_messages), (2, 3));
+    }
+}
@@ -2,6 +2,7 @@ class SyslogDashboard {
     constructor() {
         this.ws = null;
         this.logs = [];
+        this.searchResults = null;
         this.currentPage = 1;
         this.pageSize = 50;
         this.isPaused = false;
@@ -144,7 +145,7 @@ class SyslogDashboard {
     async loadInitialData() {
         try {
             const response = await fetch('/api/logs?limit=100');
-            const logs = await response.json();
+            const { logs } = await response.json();
             this.logs = logs;
             this.renderLogs();
             this.updatePagination();
@@ -235,29 +236,33 @@ class SyslogDashboard {
         return !this.filters.search && !this.filters.facility && !this.filters.severity;
     }
 
-    applyFilters() {
+    async applyFilters() {
         this.currentPage = 1;
+        
+        // Text search runs against the server's full-text index rather than the loaded page
+        if (this.filters.search.trim()) {
+            await this.searchLogs();
+        } else {
+            this.searchResults = null;
+        }
+        
         this.renderLogs();
         this.updatePagination();
     }
 
+    async searchLogs() {
+        const params = new URLSearchParams({ search: this.filters.search, limit: 1000 });
+        try {
+            const response = await fetch(`/api/logs?${params}`);
+            this.searchResults = (await response.json()).logs;
+        } catch (error) {
+            console.error('Failed to search logs:', error);
+            this.searchResults = [];
+        }
+    }
+
     getFilteredLogs() {
-        return this.logs.filter(log => {
-            // Search filter
-            if (this.filters.search) {
-                const searchTerm = this.filters.search.toLowerCase();
-                const searchableText = [
-                    log.message,
-                    log.hostname || '',
-                    log.app_name || '',
-                    log.source_ip
-                ].join(' ').toLowerCase();
-                
-                if (!searchableText.includes(searchTerm)) {
-                    return false;
-                }
-            }
-            
+        return (this.searchResults || this.logs).filter(log => {
             // Facility filter
             if (this.filters.facility && log.facility.toString() !== this.filters.facility) {
                 return false;
@@ -306,7 +311,7 @@ class SyslogDashboard {
             <td><span class="facility-badge">${log.facility}</span></td>
             <td>${log.hostname || '-'}</td>
             <td>${log.app_name || '-'}</td>
-            <td class="message-cell" title="${this.escapeHtml(log.message)}">${this.escapeHtml(log.message)}</td>
+            <td class="message-cell" title="${this.escapeHtml(log.message)}">${log.snippet || this.escapeHtml(log.message)}</td>
             <td>${log.source_ip}</td>
             <td><button class="view-btn" onclick="dashboard.showLogDetail('${log.id}')">查看</button></td>
         `;
@@ -321,7 +326,8 @@ class SyslogDashboard {
     }
 
     showLogDetail(logId) {
-        const log = this.logs.find(l => l.id === logId);
+        const log = this.logs.find(l => l.id === logId)
+            || (this.searchResults || []).find(l => l.id === logId);
         if (!log) return;
         
         const modalBody = document.getElementById('logModalBody');
@@ -363,6 +369,11 @@ class SyslogDashboard {
                     <label>源IP地址</label>
                     <div class="value">${log.source_ip}</div>
                 </div>
+                ${log.structured_data ? `
+                <div class="log-field">
+                    <label>结构化数据</label>
+                    <div class="value">${this.escapeHtml(log.structured_data)}</div>
+                </div>` : ''}
                 <div class="log-field">
                     <label>消息内容</label>
                     <div class="value">${this.escapeHtml(log.message)}</div>
@@ -371,6 +382,11 @@ class SyslogDashboard {
                     <label>原始消息</label>
                     <div class="value">${this.escapeHtml(log.raw_message)}</div>
                 </div>
+                ${log.parse_error ? `
+                <div class="log-field">
+                    <label>解析警告</label>
+                    <div class="value">${this.escapeHtml(log.parse_error)}</div>
+                </div>` : ''}
             </div>
         `;
         
@@ -557,7 +573,7 @@ class SyslogDashboard {
     async clearLogs() {
         const confirmed = await this.showConfirmDialog(
             '确认清空日志',
-            '确定要清空所有日志吗？此操作不可撤销。'
+            '确定要清空所有日志吗？日志将移入回收站，保留期内可通过 /api/trash/restore 恢复。'
         );
         
         if (confirmed) {
@@ -0,0 +1,97 @@
+//! Wire formats shared by the syslog parser and the `syslog_sender` test tool.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::fmt;
+
+/// Largest valid PRI value: facility 23 (local7) with severity 7 (debug)
+pub const MAX_PRIORITY: u8 = 191;
+
+/// Placeholder for an absent RFC 5424 header field or structured data
+pub const NILVALUE: &str = "-";
+
+/// Largest facility code: 23 (local7)
+pub const MAX_FACILITY: u8 = 23;
+
+/// Largest severity code: 7 (debug)
+pub const MAX_SEVERITY: u8 = 7;
+
+/// Combines a facility (0-23) and severity (0-7) into a PRI value. Anything
+/// out of range is a bug in the caller, and is clamped in release builds so
+/// the PRI written is still a valid one.
+pub fn priority(facility: u8, severity: u8) -> u8 {
+    debug_assert!(
+        facility <= MAX_FACILITY && severity <= MAX_SEVERITY,
+        "facility {} or severity {} out of range",
+        facility,
+        severity
+    );
+    facility.min(MAX_FACILITY) * 8 + severity.min(MAX_SEVERITY)
+}
+
+/// A BSD syslog message as described by RFC 3164:
+/// `<PRI>Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG`
+#[derive(Debug, Clone)]
+pub struct Rfc3164Message<'a> {
+    pub facility: u8,
+    pub severity: u8,
+    /// Local time of the sender; the format carries no year or zone
+    pub timestamp: NaiveDateTime,
+    pub hostname: &'a str,
+    pub tag: &'a str,
+    pub proc_id: Option<&'a str>,
+    pub message: &'a str,
+}
+
+impl fmt::Display for Rfc3164Message<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "<{}>{} {} {}",
+            priority(self.facility, self.severity),
+            self.timestamp.format("%b %e %H:%M:%S"),
+            self.hostname,
+            self.tag
+        )?;
+        if let Some(proc_id) = self.proc_id {
+            write!(f, "[{}]", proc_id)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// A syslog message as described by RFC 5424:
+/// `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG`.
+/// Absent header fields are written as `NILVALUE`.
+#[derive(Debug, Clone)]
+pub struct Rfc5424Message<'a> {
+    pub facility: u8,
+    pub severity: u8,
+    pub timestamp: DateTime<Utc>,
+    pub hostname: Option<&'a str>,
+    pub app_name: Option<&'a str>,
+    pub proc_id: Option<&'a str>,
+    pub msg_id: Option<&'a str>,
+    /// One or more serialized `[SD-ID param="value"]` elements
+    pub structured_data: Option<&'a str>,
+    pub message: &'a str,
+}
+
+impl fmt::Display for Rfc5424Message<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "<{}>1 {} {} {} {} {} {}",
+            priority(self.facility, self.severity),
+            self.timestamp.to_rfc3339(),
+            self.hostname.unwrap_or(NILVALUE),
+            self.app_name.unwrap_or(NILVALUE),
+            self.proc_id.unwrap_or(NILVALUE),
+            self.msg_id.unwrap_or(NILVALUE),
+            self.structured_data.unwrap_or(NILVALUE)
+        )?;
+        if !self.message.is_empty() {
+            write!(f, " {}", self.message)?;
+        }
+        Ok(())
+    }
+}
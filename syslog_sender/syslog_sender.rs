@@ -1,7 +1,90 @@
+use std::fs;
 use std::io::Write;
 use std::net::{TcpStream, UdpSocket};
-use chrono::Utc;
+use std::path::Path;
+use chrono::{Local, Utc};
 use clap::{Arg, Command};
+use syslog_format::{Rfc3164Message, Rfc5424Message};
+
+// 测试不同的 facility 和 severity 组合
+// (facility, severity, hostname, app_name, proc_id, msg_id, message)
+const TEST_CASES: [(u8, u8, &str, &str, &str, &str, &str); 5] = [
+    (16, 6, "testhost1", "myapp", "1234", "MSG001", "This is a test info message"),
+    (0, 3, "testhost2", "kernel", "0", "ERR001", "This is a test error message"),
+    (23, 4, "testhost3", "webapp", "5678", "WARN001", "This is a test warning message"),
+    (1, 2, "testhost4", "daemon", "9999", "CRIT001", "This is a test critical message"),
+    (8, 7, "testhost5", "logger", "1111", "DEBUG001", "This is a test debug message"),
+];
+
+// 用于测试容错性的无效格式消息，同时作为 fuzz 语料的种子
+const INVALID_MESSAGES: [&str; 5] = [
+    "This is a plain text message without syslog format",
+    "<>Invalid priority",
+    "<999>Priority too high",
+    "<16>Incomplete message",
+    "GET /?ide_webview_request_time=1752666052349 HTTP/1.1", // 模拟之前出错的消息
+];
+
+// 按 RFC 3164 和 RFC 5424 格式化第 round 轮的测试消息
+fn format_test_case(round: usize) -> (String, String) {
+    let (facility, severity, hostname, app_name, proc_id, msg_id, base_message) = TEST_CASES[round % TEST_CASES.len()];
+    let message = format!("{} #{}", base_message, round + 1);
+
+    let rfc3164 = Rfc3164Message {
+        facility,
+        severity,
+        timestamp: Local::now().naive_local(),
+        hostname,
+        tag: app_name,
+        proc_id: None,
+        message: &message,
+    }
+    .to_string();
+
+    let rfc5424 = Rfc5424Message {
+        facility,
+        severity,
+        timestamp: Utc::now(),
+        hostname: Some(hostname),
+        app_name: Some(app_name),
+        proc_id: Some(proc_id),
+        msg_id: Some(msg_id),
+        structured_data: None,
+        message: &message,
+    }
+    .to_string();
+
+    (rfc3164, rfc5424)
+}
+
+// 将测试消息写成 fuzz 种子语料: <dir>/parse 下每条消息一个文件,
+// <dir>/tcp_framing 下为首字节 (最大帧长) 加换行分隔的消息流
+fn write_corpus(dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut messages: Vec<String> = INVALID_MESSAGES.iter().map(|msg| msg.to_string()).collect();
+    for round in 0..TEST_CASES.len() {
+        let (rfc3164, rfc5424) = format_test_case(round);
+        messages.push(rfc3164);
+        messages.push(rfc5424);
+    }
+
+    let parse_dir = dir.join("parse");
+    fs::create_dir_all(&parse_dir)?;
+    for (i, msg) in messages.iter().enumerate() {
+        fs::write(parse_dir.join(format!("seed-{:02}", i)), msg)?;
+    }
+
+    let framing_dir = dir.join("tcp_framing");
+    fs::create_dir_all(&framing_dir)?;
+    let stream = messages.join("\n");
+    for (i, max_size) in [16u8, 64, 255].iter().enumerate() {
+        let mut seed = vec![*max_size];
+        seed.extend_from_slice(stream.as_bytes());
+        fs::write(framing_dir.join(format!("seed-{:02}", i)), seed)?;
+    }
+
+    println!("Wrote {} parse seeds and 3 framing seeds to {}", messages.len(), dir.display());
+    Ok(())
+}
 
 #[derive(Debug, Clone)]
 struct SyslogSender {
@@ -15,59 +98,20 @@ impl SyslogSender {
         Self { host, udp_port, tcp_port }
     }
 
-    // 发送 RFC 3164 格式的 syslog 消息 (UDP)
-    fn send_rfc3164_udp(&self, facility: u8, severity: u8, hostname: &str, tag: &str, message: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let priority = facility * 8 + severity;
-        let timestamp = chrono::Local::now().format("%b %d %H:%M:%S");
-        let syslog_msg = format!("<{}>{} {} {}: {}", priority, timestamp, hostname, tag, message);
-        
-        let socket = UdpSocket::bind("0.0.0.0:0")?;
-        socket.send_to(syslog_msg.as_bytes(), format!("{}:{}", self.host, self.udp_port))?;
-        println!("[UDP RFC3164] Sent: {}", syslog_msg);
-        Ok(())
-    }
-
-    // 发送 RFC 5424 格式的 syslog 消息 (UDP)
-    fn send_rfc5424_udp(&self, facility: u8, severity: u8, hostname: &str, app_name: &str, proc_id: &str, msg_id: &str, message: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let priority = facility * 8 + severity;
-        let timestamp = Utc::now().to_rfc3339();
-        let syslog_msg = format!(
-            "<{}>1 {} {} {} {} {} - {}",
-            priority, timestamp, hostname, app_name, proc_id, msg_id, message
-        );
-        
+    // 通过 UDP 发送一条已格式化的 syslog 消息
+    fn send_udp(&self, label: &str, syslog_msg: &str) -> Result<(), Box<dyn std::error::Error>> {
         let socket = UdpSocket::bind("0.0.0.0:0")?;
         socket.send_to(syslog_msg.as_bytes(), format!("{}:{}", self.host, self.udp_port))?;
-        println!("[UDP RFC5424] Sent: {}", syslog_msg);
-        Ok(())
-    }
-
-    // 发送 RFC 3164 格式的 syslog 消息 (TCP)
-    fn send_rfc3164_tcp(&self, facility: u8, severity: u8, hostname: &str, tag: &str, message: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let priority = facility * 8 + severity;
-        let timestamp = chrono::Local::now().format("%b %d %H:%M:%S");
-        let syslog_msg = format!("<{}>{} {} {}: {}\n", priority, timestamp, hostname, tag, message);
-        
-        let mut stream = TcpStream::connect(format!("{}:{}", self.host, self.tcp_port))?;
-        stream.write_all(syslog_msg.as_bytes())?;
-        stream.flush()?;
-        println!("[TCP RFC3164] Sent: {}", syslog_msg.trim());
+        println!("[UDP {}] Sent: {}", label, syslog_msg);
         Ok(())
     }
 
-    // 发送 RFC 5424 格式的 syslog 消息 (TCP)
-    fn send_rfc5424_tcp(&self, facility: u8, severity: u8, hostname: &str, app_name: &str, proc_id: &str, msg_id: &str, message: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let priority = facility * 8 + severity;
-        let timestamp = Utc::now().to_rfc3339();
-        let syslog_msg = format!(
-            "<{}>1 {} {} {} {} {} - {}\n",
-            priority, timestamp, hostname, app_name, proc_id, msg_id, message
-        );
-        
+    // 通过 TCP 发送一条已格式化的 syslog 消息 (以换行符分帧)
+    fn send_tcp(&self, label: &str, syslog_msg: &str) -> Result<(), Box<dyn std::error::Error>> {
         let mut stream = TcpStream::connect(format!("{}:{}", self.host, self.tcp_port))?;
-        stream.write_all(syslog_msg.as_bytes())?;
+        stream.write_all(format!("{}\n", syslog_msg).as_bytes())?;
         stream.flush()?;
-        println!("[TCP RFC5424] Sent: {}", syslog_msg.trim());
+        println!("[TCP {}] Sent: {}", label, syslog_msg);
         Ok(())
     }
 
@@ -108,8 +152,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             .value_name("COUNT")
             .help("Number of messages to send")
             .default_value("5"))
+        .arg(Arg::new("write-corpus")
+            .long("write-corpus")
+            .value_name("DIR")
+            .help("Write the test messages as a fuzzing seed corpus to DIR instead of sending them"))
         .get_matches();
 
+    if let Some(dir) = matches.get_one::<String>("write-corpus") {
+        return write_corpus(Path::new(dir));
+    }
+
     let host = matches.get_one::<String>("host").unwrap().clone();
     let udp_port: u16 = matches.get_one::<String>("udp-port").unwrap().parse()?;
     let tcp_port: u16 = matches.get_one::<String>("tcp-port").unwrap().parse()?;
@@ -121,37 +173,27 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("Target: {}:{} (UDP), {}:{} (TCP)", sender.host, sender.udp_port, sender.host, sender.tcp_port);
     println!("Sending {} messages of each type...\n", count);
 
-    // 测试不同的 facility 和 severity 组合
-    let test_cases = vec![
-        (16, 6, "testhost1", "myapp", "1234", "MSG001", "This is a test info message"),
-        (0, 3, "testhost2", "kernel", "0", "ERR001", "This is a test error message"),
-        (23, 4, "testhost3", "webapp", "5678", "WARN001", "This is a test warning message"),
-        (1, 2, "testhost4", "daemon", "9999", "CRIT001", "This is a test critical message"),
-        (8, 7, "testhost5", "logger", "1111", "DEBUG001", "This is a test debug message"),
-    ];
-
     for i in 0..count {
-        let (facility, severity, hostname, app_name, proc_id, msg_id, base_message) = &test_cases[i % test_cases.len()];
-        let message = format!("{} #{}", base_message, i + 1);
-        
         println!("--- Round {} ---", i + 1);
         
+        let (rfc3164, rfc5424) = format_test_case(i);
+        
         // 发送 RFC 3164 格式消息 (TCP)
-        if let Err(e) = sender.send_rfc3164_tcp(*facility, *severity, hostname, app_name, &message) {
+        if let Err(e) = sender.send_tcp("RFC3164", &rfc3164) {
             eprintln!("Error sending RFC3164 TCP: {}", e);
         }
         
         // 发送 RFC 5424 格式消息 (TCP)
-        if let Err(e) = sender.send_rfc5424_tcp(*facility, *severity, hostname, app_name, proc_id, msg_id, &message) {
+        if let Err(e) = sender.send_tcp("RFC5424", &rfc5424) {
             eprintln!("Error sending RFC5424 TCP: {}", e);
         }
         
         // 尝试发送 UDP 消息 (可能会失败，因为端口被占用)
-        if let Err(e) = sender.send_rfc3164_udp(*facility, *severity, hostname, app_name, &message) {
+        if let Err(e) = sender.send_udp("RFC3164", &rfc3164) {
             eprintln!("Error sending RFC3164 UDP: {} (This is expected if port 514 is in use)", e);
         }
         
-        if let Err(e) = sender.send_rfc5424_udp(*facility, *severity, hostname, app_name, proc_id, msg_id, &message) {
+        if let Err(e) = sender.send_udp("RFC5424", &rfc5424) {
             eprintln!("Error sending RFC5424 UDP: {} (This is expected if port 514 is in use)", e);
         }
         
@@ -160,15 +202,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // 测试无效格式消息
     println!("\n--- Testing Invalid Messages ---");
-    let invalid_messages = vec![
-        "This is a plain text message without syslog format",
-        "<>Invalid priority",
-        "<999>Priority too high",
-        "<16>Incomplete message",
-        "GET /?ide_webview_request_time=1752666052349 HTTP/1.1", // 模拟之前出错的消息
-    ];
-
-    for (i, msg) in invalid_messages.iter().enumerate() {
+    for (i, msg) in INVALID_MESSAGES.iter().enumerate() {
         println!("Invalid message {}: {}", i + 1, msg);
         if let Err(e) = sender.send_invalid_message(msg) {
             eprintln!("Error sending invalid message: {}", e);
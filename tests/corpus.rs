@@ -0,0 +1,80 @@
+//! Golden corpus regression suite. Every `tests/corpus/*.log` file holds one line
+//! as sent by a real device or forwarder, and the `*.json` file next to it holds
+//! the `SyslogMessage` it must parse into. `id` and `timestamp` are generated at
+//! parse time and left out of the expectations.
+//!
+//! After an intended parser change, rewrite the expectations with
+//! `UPDATE_CORPUS=1 cargo test --test corpus` and review the diff.
+
+use serde_json::Value;
+use similar::TextDiff;
+use std::{env, fs, path::Path};
+use syslog_parser::{ParseMode, SyslogParser};
+
+const SOURCE_IP: &str = "192.0.2.10";
+
+fn parse_to_json(parser: &SyslogParser, line: &str) -> Value {
+    let message = parser.parse(line, SOURCE_IP, ParseMode::Lenient).unwrap();
+    let mut value = serde_json::to_value(message).unwrap();
+    let fields = value.as_object_mut().unwrap();
+    fields.remove("id");
+    fields.remove("timestamp");
+    value
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap() + "\n"
+}
+
+#[test]
+fn corpus_matches_expected() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let update = env::var_os("UPDATE_CORPUS").is_some();
+    let parser = SyslogParser::new().unwrap();
+
+    let mut cases: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+        .collect();
+    cases.sort();
+    assert!(!cases.is_empty(), "no corpus files found in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for log_path in &cases {
+        let name = log_path.file_stem().unwrap().to_string_lossy();
+        let contents = fs::read_to_string(log_path).unwrap();
+        let line = contents.strip_suffix('\n').unwrap_or(&contents);
+        let actual = parse_to_json(&parser, line);
+
+        let json_path = log_path.with_extension("json");
+        if update {
+            fs::write(&json_path, pretty(&actual)).unwrap();
+            continue;
+        }
+
+        let Ok(expected) = fs::read_to_string(&json_path) else {
+            failures.push(format!("{}: missing {}", name, json_path.display()));
+            continue;
+        };
+        let expected: Value = serde_json::from_str(&expected)
+            .unwrap_or_else(|e| panic!("{}: invalid JSON in {}: {}", name, json_path.display(), e));
+
+        if expected != actual {
+            let (expected, actual) = (pretty(&expected), pretty(&actual));
+            let diff = TextDiff::from_lines(&expected, &actual)
+                .unified_diff()
+                .header("expected", "actual")
+                .to_string();
+            failures.push(format!("{}:\n{}", name, diff));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} corpus cases do not match:\n\n{}",
+        failures.len(),
+        cases.len(),
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,144 @@
+//! Round-trip tests: messages built by the `syslog_format` writers used by
+//! `syslog_sender` must come back field for field from `SyslogParser::parse`.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use proptest::prelude::*;
+use syslog_format::{Rfc3164Message, Rfc5424Message, NILVALUE};
+use syslog_parser::{ParseMode, SyslogParser};
+
+/// RFC 5424 header fields are 1..=max printable US-ASCII characters, and a
+/// lone `-` is the NILVALUE rather than a value.
+fn header_field(max_len: usize) -> impl Strategy<Value = Option<String>> {
+    proptest::option::of(
+        proptest::string::string_regex(&format!("[!-~]{{1,{}}}", max_len))
+            .unwrap()
+            .prop_filter("NILVALUE is not a value", |field| field != NILVALUE),
+    )
+}
+
+fn hostname() -> impl Strategy<Value = String> {
+    "[A-Za-z0-9][A-Za-z0-9.-]{0,62}"
+}
+
+/// RFC 3164 TAGs are alphanumeric; a few punctuation characters show up in practice
+fn tag() -> impl Strategy<Value = String> {
+    "[A-Za-z0-9_./-]{1,32}"
+}
+
+/// Free text including unicode, tabs, newlines and leading/trailing spaces
+fn message() -> impl Strategy<Value = String> {
+    prop_oneof![
+        any::<String>(),
+        "[ \t]{0,3}[\\PC\t\n ]{0,80}[ \t\n]{0,3}",
+        Just(String::new()),
+        Just(" ".to_string()),
+    ]
+    .prop_filter("a leading BOM is stripped by design", |message| !message.starts_with('\u{FEFF}'))
+}
+
+fn sd_param_value() -> impl Strategy<Value = String> {
+    "[\\PC ]{0,20}".prop_map(|value| {
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace(']', "\\]")
+    })
+}
+
+/// One or more `[SD-ID name="value"]` elements with escaped values
+fn structured_data() -> impl Strategy<Value = Option<String>> {
+    proptest::option::of(
+        proptest::collection::vec(
+            ("[a-zA-Z]{1,10}(@[0-9]{1,5})?", "[a-zA-Z]{1,10}", sd_param_value()),
+            1..4,
+        )
+        .prop_map(|elements| {
+            elements
+                .into_iter()
+                .map(|(id, name, value)| format!("[{} {}=\"{}\"]", id, name, value))
+                .collect::<String>()
+        }),
+    )
+}
+
+fn timestamp() -> impl Strategy<Value = DateTime<Utc>> {
+    (0i64..4_102_444_800).prop_map(|secs| DateTime::from_timestamp(secs, 0).unwrap())
+}
+
+fn local_timestamp() -> impl Strategy<Value = NaiveDateTime> {
+    timestamp().prop_map(|timestamp| timestamp.naive_utc())
+}
+
+proptest! {
+    #[test]
+    fn rfc5424_round_trip(
+        facility in 0u8..=23,
+        severity in 0u8..=7,
+        timestamp in timestamp(),
+        hostname in header_field(255),
+        app_name in header_field(48),
+        proc_id in header_field(128),
+        msg_id in header_field(32),
+        structured_data in structured_data(),
+        message in message(),
+    ) {
+        let raw = Rfc5424Message {
+            facility,
+            severity,
+            timestamp,
+            hostname: hostname.as_deref(),
+            app_name: app_name.as_deref(),
+            proc_id: proc_id.as_deref(),
+            msg_id: msg_id.as_deref(),
+            structured_data: structured_data.as_deref(),
+            message: &message,
+        }
+        .to_string();
+
+        let parsed = SyslogParser::new().unwrap().parse(&raw, "127.0.0.1", ParseMode::Strict).unwrap();
+
+        prop_assert_eq!(parsed.facility, facility);
+        prop_assert_eq!(parsed.severity, severity);
+        prop_assert_eq!(parsed.hostname, hostname);
+        prop_assert_eq!(parsed.app_name, app_name);
+        prop_assert_eq!(parsed.proc_id, proc_id);
+        prop_assert_eq!(parsed.msg_id, msg_id);
+        prop_assert_eq!(parsed.structured_data, structured_data);
+        prop_assert_eq!(parsed.message, message);
+        prop_assert_eq!(parsed.raw_message, raw);
+        prop_assert_eq!(parsed.parse_error, None);
+    }
+
+    #[test]
+    fn rfc3164_round_trip(
+        facility in 0u8..=23,
+        severity in 0u8..=7,
+        timestamp in local_timestamp(),
+        hostname in hostname(),
+        tag in tag(),
+        proc_id in proptest::option::of("[0-9]{1,7}"),
+        message in message(),
+    ) {
+        let raw = Rfc3164Message {
+            facility,
+            severity,
+            timestamp,
+            hostname: &hostname,
+            tag: &tag,
+            proc_id: proc_id.as_deref(),
+            message: &message,
+        }
+        .to_string();
+
+        let parsed = SyslogParser::new().unwrap().parse(&raw, "127.0.0.1", ParseMode::Strict).unwrap();
+
+        prop_assert_eq!(parsed.facility, facility);
+        prop_assert_eq!(parsed.severity, severity);
+        prop_assert_eq!(parsed.hostname, Some(hostname));
+        prop_assert_eq!(parsed.app_name, Some(tag));
+        prop_assert_eq!(parsed.proc_id, proc_id);
+        prop_assert_eq!(parsed.msg_id, None);
+        prop_assert_eq!(parsed.message, message);
+        prop_assert_eq!(parsed.parse_error, None);
+    }
+}